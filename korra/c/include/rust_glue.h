@@ -12,14 +12,43 @@
 extern "C" {
 #endif
 
+// ABI version this header describes (must match KORRA_ABI_VERSION in lib.rs)
+#define KORRA_ABI_VERSION 7
+
 // Opaque agent handle
 typedef void* agent_handle_t;
 
+// Status codes returned by the Rust engine (must match KorraStatus in lib.rs)
+typedef enum {
+    KORRA_STATUS_OK = 0,
+    KORRA_STATUS_NULL_POINTER = -1,
+    KORRA_STATUS_INVALID_UTF8 = -2,
+    KORRA_STATUS_INIT_FAILED = -3,
+    KORRA_STATUS_EXECUTION_FAILED = -4,
+    KORRA_STATUS_TIMEOUT = -5,
+    KORRA_STATUS_OUT_OF_MEMORY = -6,
+    KORRA_STATUS_INVALID_HANDLE = -7,
+    KORRA_STATUS_NOT_FOUND = -8,
+    KORRA_STATUS_BUFFER_TOO_SMALL = -9,
+    KORRA_STATUS_TRAP_UNREACHABLE = -10,
+    KORRA_STATUS_TRAP_MEMORY_OUT_OF_BOUNDS = -11,
+    KORRA_STATUS_TRAP_DIVISION_BY_ZERO = -12,
+    KORRA_STATUS_TRAP_INTEGER_OVERFLOW = -13,
+    KORRA_STATUS_TRAP_STACK_OVERFLOW = -14,
+    KORRA_STATUS_TRAP_OTHER = -15,
+    KORRA_STATUS_BUSY = -16,
+    KORRA_STATUS_INPUT_TOO_LARGE = -17
+} korra_status_t;
+
 // Function types for Rust callbacks
 typedef agent_handle_t (*rust_agent_create_fn)(const char*, const char*);
-typedef int (*rust_agent_execute_fn)(agent_handle_t, const void*, size_t, void**, size_t*);
+typedef korra_status_t (*rust_agent_execute_fn)(agent_handle_t, const void*, size_t, void**, size_t*);
 typedef void (*rust_agent_destroy_fn)(agent_handle_t);
 
+// Completion callback for rust_agent_execute_async
+typedef void (*rust_agent_completion_fn)(korra_status_t status, void* output,
+                                         size_t output_size, void* user_data);
+
 // Struct containing Rust callback functions
 typedef struct {
     rust_agent_create_fn agent_create;
@@ -47,15 +76,19 @@ agent_handle_t create_agent(const char* agent_type, const char* config);
 /**
  * Execute an agent with provided input
  * 
+ * May be called from several threads with the same handle; a call made while
+ * another is using the agent returns KORRA_STATUS_BUSY instead of running
+ * concurrently with it.
+ * 
  * @param handle Agent handle
  * @param input Input data
  * @param input_size Size of input data
- * @param output Pointer to store output data (caller must free)
+ * @param output Pointer to store output data (free with rust_agent_free_output)
  * @param output_size Pointer to store output data size
- * @return 0 on success, -1 on failure
+ * @return KORRA_STATUS_OK on success, a negative korra_status_t on failure
  */
-int execute_agent(agent_handle_t handle, const void* input, size_t input_size,
-                  void** output, size_t* output_size);
+korra_status_t execute_agent(agent_handle_t handle, const void* input, size_t input_size,
+                             void** output, size_t* output_size);
 
 /**
  * Destroy an agent instance
@@ -64,6 +97,194 @@ int execute_agent(agent_handle_t handle, const void* input, size_t input_size,
  */
 void destroy_agent(agent_handle_t handle);
 
+/**
+ * Execute an agent on a Rust worker thread
+ * 
+ * Exported by the Rust engine. The input is copied before this returns.
+ * The callback runs on a worker thread, exactly once per call that returns
+ * KORRA_STATUS_OK, and never before this function has returned. Queued
+ * executions wait for other calls on the same handle instead of failing with
+ * KORRA_STATUS_BUSY, and the handle may be destroyed while an execution is
+ * in flight. A non-NULL output passed to the callback must
+ * be freed with rust_agent_free_output.
+ * 
+ * @param handle Agent handle
+ * @param input Input data
+ * @param input_size Size of input data
+ * @param callback Function invoked with the result
+ * @param user_data Opaque pointer passed back to the callback
+ * @return KORRA_STATUS_OK if queued, a negative korra_status_t otherwise
+ */
+korra_status_t rust_agent_execute_async(agent_handle_t handle, const void* input,
+                                        size_t input_size, rust_agent_completion_fn callback,
+                                        void* user_data);
+
+/**
+ * Free an output buffer produced by execute_agent
+ * 
+ * Exported by the Rust engine. Every non-NULL output must be freed exactly
+ * once through this function rather than with free() or c_free_callback.
+ * 
+ * @param output Output buffer returned by execute_agent or passed to a
+ *               completion callback, or NULL
+ * @param output_size Size reported alongside the buffer
+ */
+void rust_agent_free_output(void* output, size_t output_size);
+
+/**
+ * Execute an agent into a caller-provided buffer
+ * 
+ * Exported by the Rust engine. Allocates nothing, so one buffer can be reused
+ * across calls. If the output does not fit, *out_len is set to the size
+ * needed and KORRA_STATUS_BUFFER_TOO_SMALL is returned; the execution has
+ * still run, including its state changes.
+ * 
+ * @param handle Agent handle
+ * @param input Input data
+ * @param input_size Size of input data
+ * @param out_buf Buffer to copy the output into (may be NULL if out_cap is 0)
+ * @param out_cap Capacity of out_buf in bytes
+ * @param out_len Pointer to store the output size
+ * @return KORRA_STATUS_OK on success, a negative korra_status_t on failure
+ */
+korra_status_t rust_agent_execute_into(agent_handle_t handle, const void* input, size_t input_size,
+                                       void* out_buf, size_t out_cap, size_t* out_len);
+
+/**
+ * Create an agent from a JSON config file
+ * 
+ * Exported by the Rust engine. Reads the file at path and creates the agent
+ * as create_agent would from its contents.
+ * 
+ * @param agent_type Type of agent to create
+ * @param path NUL-terminated UTF-8 path to the config file
+ * @param out_handle Pointer to store the new handle (NULL on failure)
+ * @return KORRA_STATUS_OK on success, KORRA_STATUS_NOT_FOUND if the file does
+ *         not exist, another negative korra_status_t otherwise
+ */
+korra_status_t rust_agent_create_from_file(const char* agent_type, const char* path,
+                                           agent_handle_t* out_handle);
+
+/**
+ * Describe a live agent
+ * 
+ * Exported by the Rust engine. The JSON object holds id, agent_type,
+ * config_keys, has_proof, state_size and module_hash (null without a
+ * WASM module).
+ * 
+ * @param handle Agent handle
+ * @param out Pointer to store a NUL-terminated JSON string, owned by the caller
+ * @return KORRA_STATUS_OK on success, a negative korra_status_t on failure
+ */
+korra_status_t rust_agent_info(agent_handle_t handle, char** out);
+
+/**
+ * Shut an agent down before destroying it
+ * 
+ * Exported by the Rust engine. Flushes persistent state, records a final
+ * "shutdown" proof (see rust_agent_last_proof) and releases the sandbox.
+ * Later executions fail; calling this again is a no-op.
+ * 
+ * @param handle Agent handle
+ * @return KORRA_STATUS_OK on success, a negative korra_status_t on failure
+ */
+korra_status_t rust_agent_shutdown(agent_handle_t handle);
+
+/**
+ * Get an agent's last execution proof as JSON
+ * 
+ * Exported by the Rust engine. The string is allocated with
+ * c_alloc_callback and must be released with c_free_callback.
+ * 
+ * @param handle Agent handle
+ * @param out Pointer to store the NUL-terminated proof JSON
+ * @return 0 on success, -1 if the handle is invalid or no proof exists yet
+ */
+int rust_agent_last_proof(agent_handle_t handle, char** out);
+
+/**
+ * Store a value in an agent's state
+ * 
+ * Exported by the Rust engine. The value is copied before this returns.
+ * 
+ * @param handle Agent handle
+ * @param key NUL-terminated UTF-8 state key
+ * @param value Value bytes (may be NULL if value_len is 0)
+ * @param value_len Size of the value in bytes
+ * @return KORRA_STATUS_OK on success, KORRA_STATUS_OUT_OF_MEMORY if the state
+ *         quota would be exceeded, another negative korra_status_t otherwise
+ */
+korra_status_t rust_agent_state_set(agent_handle_t handle, const char* key,
+                                    const void* value, size_t value_len);
+
+/**
+ * Copy a value out of an agent's state
+ * 
+ * Exported by the Rust engine. A non-NULL value must be freed with
+ * rust_agent_free_output; empty values are returned as NULL with length 0.
+ * 
+ * @param handle Agent handle
+ * @param key NUL-terminated UTF-8 state key
+ * @param out Pointer to store the value buffer
+ * @param out_len Pointer to store the value size
+ * @return KORRA_STATUS_OK on success, KORRA_STATUS_NOT_FOUND if the key does
+ *         not exist, another negative korra_status_t otherwise
+ */
+korra_status_t rust_agent_state_get(agent_handle_t handle, const char* key,
+                                    void** out, size_t* out_len);
+
+/**
+ * Get the Rust engine version string
+ * 
+ * Exported by the Rust engine. The string is static and must not be freed.
+ * 
+ * @return NUL-terminated version, e.g. "0.1.0 (1a2b3c4)"
+ */
+const char* rust_agent_version(void);
+
+/**
+ * Get the ABI version of the loaded Rust engine
+ * 
+ * Exported by the Rust engine. Compare against KORRA_ABI_VERSION to detect
+ * an incompatible shared library before calling anything else.
+ * 
+ * @return ABI version of the engine
+ */
+unsigned int rust_agent_abi_version(void);
+
+/**
+ * Copy the last Rust engine error on the calling thread into a buffer
+ * 
+ * Exported by the Rust engine. Works like strerror for the most recent
+ * failed call: the message is truncated to fit and always NUL-terminated.
+ * 
+ * @param buf Destination buffer, or NULL to query the length
+ * @param buf_len Size of the destination buffer in bytes
+ * @return Full message length excluding the NUL, or 0 if the last call succeeded
+ */
+size_t rust_agent_last_error(char* buf, size_t buf_len);
+
+/**
+ * Set the minimum level of Rust engine log messages
+ * 
+ * Exported by the Rust engine. Messages below the level never reach
+ * c_log_callback. Defaults to LOG_LEVEL_INFO.
+ * 
+ * @param level Minimum log level, one of the LOG_LEVEL_* values in debug.h
+ */
+void rust_agent_set_log_level(int level);
+
+/**
+ * Switch Rust engine log messages between plain text and JSON
+ * 
+ * Exported by the Rust engine. In JSON mode each message passed to
+ * c_log_callback is an object with "level", "msg", "agent_id" (null outside
+ * an agent execution) and "ts" (milliseconds since the Unix epoch) fields.
+ * 
+ * @param enabled Non-zero for JSON, 0 for plain text (the default)
+ */
+void rust_agent_set_log_json(int enabled);
+
 /**
  * C callback for Rust to call for logging
  * 
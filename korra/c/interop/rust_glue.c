@@ -47,16 +47,16 @@ agent_handle_t create_agent(const char* agent_type, const char* config) {
 }
 
 // Execute an agent with provided input
-int execute_agent(agent_handle_t handle, const void* input, size_t input_size,
-                  void** output, size_t* output_size) {
+korra_status_t execute_agent(agent_handle_t handle, const void* input, size_t input_size,
+                             void** output, size_t* output_size) {
     if (!rust_agent_execute) {
         ERROR_LOG("Rust FFI not initialized");
-        return -1;
+        return KORRA_STATUS_INIT_FAILED;
     }
     
     if (!handle) {
         ERROR_LOG("Invalid agent handle");
-        return -1;
+        return KORRA_STATUS_NULL_POINTER;
     }
     
     DEBUG_LOG("Executing agent with %zu bytes of input", input_size);
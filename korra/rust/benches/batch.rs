@@ -0,0 +1,32 @@
+//! Batch execution against one execute call per input, over 1000 small inputs
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const INPUTS: usize = 1000;
+
+fn batch_throughput(c: &mut Criterion) {
+    let inputs: Vec<Vec<u8>> = (0..INPUTS).map(|i| format!("record-{}", i).into_bytes()).collect();
+    let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+
+    let mut group = c.benchmark_group("1000_inputs");
+    group.throughput(Throughput::Elements(INPUTS as u64));
+
+    let mut agent = common::agent(common::ECHO, "");
+    group.bench_function("looped", |b| {
+        b.iter(|| {
+            for input in &inputs {
+                agent.execute(input).unwrap();
+            }
+        })
+    });
+
+    let mut agent = common::agent(common::ECHO, "");
+    group.bench_function("batch", |b| b.iter(|| agent.execute_batch(&inputs).unwrap()));
+
+    group.finish();
+}
+
+criterion_group!(benches, batch_throughput);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Host callbacks and helpers shared by the benchmarks
+//!
+//! The engine calls back into its C host for logging and output buffers, so
+//! benchmark binaries provide those symbols themselves.
+
+use std::alloc::{self, Layout};
+use std::os::raw::{c_char, c_int, c_void};
+
+use base64::{Engine as _, engine::general_purpose};
+use korra_rust::engine::agent::Agent;
+
+/// Bytes kept before each output buffer to remember its size
+const HEADER_SIZE: usize = 16;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size + HEADER_SIZE, HEADER_SIZE).expect("output buffer too large")
+}
+
+#[no_mangle]
+extern "C" fn c_log_callback(_level: c_int, _message: *const c_char) {}
+
+#[no_mangle]
+extern "C" fn c_alloc_callback(size: usize) -> *mut u8 {
+    unsafe {
+        let base = alloc::alloc(layout(size));
+        if base.is_null() {
+            return base;
+        }
+        (base as *mut usize).write(size);
+        base.add(HEADER_SIZE)
+    }
+}
+
+#[no_mangle]
+extern "C" fn c_free_callback(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let base = (ptr as *mut u8).sub(HEADER_SIZE);
+        alloc::dealloc(base, layout((base as *const usize).read()));
+    }
+}
+
+/// Echoes its input back
+#[allow(dead_code)]
+pub const ECHO: &str = r#"(module
+    (memory (export "memory") 1)
+    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+    (func (export "agent_run") (param i32 i32) (result i64)
+        (i64.or
+            (i64.shl (i64.extend_i32_u (local.get 0)) (i64.const 32))
+            (i64.extend_i32_u (local.get 1)))))"#;
+
+/// Create a custom agent running `wat`, with extra config fields spliced in
+#[allow(dead_code)]
+pub fn agent(wat: &str, extra_config: &str) -> Agent {
+    let config = format!(
+        r#"{{"wasm_base64": "{}"{}}}"#,
+        general_purpose::STANDARD.encode(wat),
+        extra_config
+    );
+    Agent::new("custom", &config).expect("failed to create benchmark agent")
+}
@@ -0,0 +1,75 @@
+//! Read-heavy state access from several threads, under the store's RwLock and under a Mutex
+
+mod common;
+
+use std::sync::Mutex;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use korra_rust::state::core::{ConcurrentStateStore, StateStore};
+
+const THREADS: usize = 4;
+const OPS_PER_THREAD: usize = 10_000;
+const KEYS: usize = 1000;
+
+/// One write for every `WRITE_EVERY` operations
+const WRITE_EVERY: usize = 20;
+
+fn read_heavy_mix(c: &mut Criterion) {
+    let keys: Vec<String> = (0..KEYS).map(|i| format!("key-{}", i)).collect();
+
+    let rwlock = ConcurrentStateStore::new();
+    let mutex = Mutex::new(StateStore::new());
+    for key in &keys {
+        rwlock.set(key, b"value").unwrap();
+        mutex.lock().unwrap().set(key, b"value").unwrap();
+    }
+
+    let mut group = c.benchmark_group("read_heavy");
+    group.throughput(Throughput::Elements((THREADS * OPS_PER_THREAD) as u64));
+
+    group.bench_function("rwlock", |b| {
+        b.iter(|| {
+            thread::scope(|scope| {
+                for t in 0..THREADS {
+                    let (rwlock, keys) = (&rwlock, &keys);
+                    scope.spawn(move || {
+                        for op in 0..OPS_PER_THREAD {
+                            let key = &keys[(t * OPS_PER_THREAD + op) % KEYS];
+                            if op % WRITE_EVERY == 0 {
+                                rwlock.set(key, b"value").unwrap();
+                            } else {
+                                rwlock.get(key).unwrap();
+                            }
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            thread::scope(|scope| {
+                for t in 0..THREADS {
+                    let (mutex, keys) = (&mutex, &keys);
+                    scope.spawn(move || {
+                        for op in 0..OPS_PER_THREAD {
+                            let key = &keys[(t * OPS_PER_THREAD + op) % KEYS];
+                            if op % WRITE_EVERY == 0 {
+                                mutex.lock().unwrap().set(key, b"value").unwrap();
+                            } else {
+                                mutex.lock().unwrap().get(key);
+                            }
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, read_heavy_mix);
+criterion_main!(benches);
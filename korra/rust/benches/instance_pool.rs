@@ -0,0 +1,20 @@
+//! Sustained execute throughput with on-demand and pooled instances
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+fn execute_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, extra_config) in [("on_demand", ""), ("pooled", r#", "instance_pool_size": 16"#)] {
+        let mut agent = common::agent(common::ECHO, extra_config);
+        group.bench_function(name, |b| b.iter(|| agent.execute(b"ping").unwrap()));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, execute_throughput);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+//! Spinning up 100 agents from one module, with and without the module cache
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use korra_rust::sandbox::wasm_host::WasmHost;
+
+const AGENTS: usize = 100;
+
+fn agent_construction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("100_agents");
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            for _ in 0..AGENTS {
+                WasmHost::clear_module_cache();
+                common::agent(common::ECHO, "");
+            }
+        })
+    });
+
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            WasmHost::clear_module_cache();
+            for _ in 0..AGENTS {
+                common::agent(common::ECHO, "");
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, agent_construction);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! Snapshot creation on a 100k-key store against copying every entry
+
+mod common;
+
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use korra_rust::state::core::StateStore;
+
+const KEYS: usize = 100_000;
+
+fn snapshot_creation(c: &mut Criterion) {
+    let mut store = StateStore::with_snapshot_limit(1);
+    for i in 0..KEYS {
+        store.set(&format!("key-{}", i), format!("value-{}", i).as_bytes()).unwrap();
+    }
+
+    let mut group = c.benchmark_group("100k_keys");
+    group.sample_size(20);
+
+    group.bench_function("create_snapshot", |b| b.iter(|| store.create_snapshot()));
+
+    // What snapshots cost when they cloned every entry
+    group.bench_function("full_copy", |b| {
+        b.iter(|| store.scan_prefix("").into_iter().collect::<HashMap<String, Vec<u8>>>())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, snapshot_creation);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+//! Build script embedding the engine version reported over FFI
+
+use std::process::Command;
+
+fn main() {
+    let version = env!("CARGO_PKG_VERSION");
+
+    // Append the short commit hash when building from a git checkout
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty());
+
+    let build_version = match git_hash {
+        Some(hash) => format!("{} ({})", version, hash),
+        None => version.to_string(),
+    };
+
+    println!("cargo:rustc-env=KORRA_BUILD_VERSION={}", build_version);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+    println!("cargo:rerun-if-changed=../../.git/refs/heads");
+}
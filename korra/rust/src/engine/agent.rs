@@ -1,13 +1,25 @@
 //! Agent definition, lifecycle, and logic routing
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
 
-use crate::sandbox::wasm_host::WasmHost;
-use crate::verifier::proof::ExecutionProof;
-use crate::state::core::StateStore;
+use base64::{Engine as _, engine::general_purpose};
+
+use crate::engine::config::AgentConfig;
+use crate::engine::custom::{self, CustomAgentHandler};
+use crate::engine::hooks::{self, AgentTypeHooks};
+use crate::engine::metrics::ExecutionMetrics;
+use crate::engine::middleware::AgentMiddleware;
+use crate::engine::retry::RetryPolicy;
+use crate::sandbox::wasm_host::{Capabilities, GuestEvent, ResourceUsage, TimeSource, TrapCode, WasmHost, WasmHostError};
+use crate::verifier::hasher::{hasher_for, HmacSha256Hasher, ProofHasher, Sha256Hasher};
+use crate::verifier::proof::{ExecutionProof, ProofMetadata};
+use crate::state::core::{StateSnapshot, StateStore};
+use crate::LogScope;
 
 /// Error type for agent operations
 #[derive(Debug)]
@@ -17,6 +29,21 @@ pub enum AgentError {
     StateError(String),
     SandboxError(String),
     InvalidInput(String),
+    /// The input is larger than the agent's `max_input_bytes`
+    InputTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    Timeout(String),
+    OutOfMemory(String),
+    /// Guest code trapped; the code says why
+    Trap(TrapCode, String),
+    /// A batch input failed; `completed` holds the outputs and proofs of the inputs before it
+    BatchFailed {
+        index: usize,
+        completed: Vec<(Vec<u8>, ExecutionProof)>,
+        error: Box<AgentError>,
+    },
 }
 
 impl fmt::Display for AgentError {
@@ -27,6 +54,15 @@ impl fmt::Display for AgentError {
             AgentError::StateError(msg) => write!(f, "Agent state error: {}", msg),
             AgentError::SandboxError(msg) => write!(f, "Agent sandbox error: {}", msg),
             AgentError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            AgentError::InputTooLarge { size, limit } => {
+                write!(f, "Input of {} bytes exceeds limit of {} bytes", size, limit)
+            }
+            AgentError::Timeout(msg) => write!(f, "Agent timed out: {}", msg),
+            AgentError::OutOfMemory(msg) => write!(f, "Agent out of memory: {}", msg),
+            AgentError::Trap(code, msg) => write!(f, "Agent trapped ({}): {}", code.as_str(), msg),
+            AgentError::BatchFailed { index, error, .. } => {
+                write!(f, "Batch input {} failed: {}", index, error)
+            }
         }
     }
 }
@@ -44,6 +80,7 @@ pub enum AgentType {
 }
 
 impl AgentType {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "analyzer" => Some(AgentType::Analyzer),
@@ -54,16 +91,50 @@ impl AgentType {
             _ => None,
         }
     }
+    
+    /// Canonical lowercase name, as accepted by `from_str`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgentType::Analyzer => "analyzer",
+            AgentType::Transformer => "transformer",
+            AgentType::Validator => "validator",
+            AgentType::Coordinator => "coordinator",
+            AgentType::Custom => "custom",
+        }
+    }
+}
+
+impl fmt::Display for AgentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
+/// Input recorded in the proof emitted by `Agent::shutdown`
+pub const SHUTDOWN_INPUT: &[u8] = b"shutdown";
+
 /// Agent definition for KORRA
 pub struct Agent {
     id: String,
     agent_type: AgentType,
     config: HashMap<String, String>,
+    settings: AgentConfig,
     state: Arc<Mutex<StateStore>>,
-    sandbox: WasmHost,
+    sandbox: Option<WasmHost>,
+    custom_handler: Option<Arc<dyn CustomAgentHandler>>,
+    hooks: Box<dyn AgentTypeHooks>,
+    middleware: Vec<Box<dyn AgentMiddleware>>,
+    max_input_bytes: Option<usize>,
+    deterministic_timestamp: Option<u64>,
+    proof_hasher: Option<HmacSha256Hasher>,
+    proof_labels: BTreeMap<String, String>,
+    auto_rollback: bool,
+    retry_policy: Option<RetryPolicy>,
+    shut_down: bool,
     last_execution: Option<ExecutionProof>,
+    last_proof_events: Vec<ExecutionProof>,
+    last_metrics: Option<ExecutionMetrics>,
+    cumulative_metrics: ExecutionMetrics,
 }
 
 impl Agent {
@@ -74,30 +145,65 @@ impl Agent {
             AgentError::InitError(format!("Unsupported agent type: {}", agent_type_str))
         })?;
         
-        // Parse config JSON
-        let config: HashMap<String, String> = match serde_json::from_str(config_json) {
+        // Parse and validate config JSON
+        let settings = match AgentConfig::from_json(config_json) {
             Ok(c) => c,
             Err(e) => {
                 return Err(AgentError::InitError(format!("Invalid config JSON: {}", e)));
             }
         };
+        let config = settings.to_map();
         
         // Get agent ID from config
-        let id = config.get("id").cloned().unwrap_or_else(|| {
+        let id = settings.id.clone().unwrap_or_else(|| {
             let uuid = uuid::Uuid::new_v4();
             uuid.to_string()
         });
         
-        // Create state store
-        let state = Arc::new(Mutex::new(StateStore::new()));
+        // Create state store, backed by a log file when configured
+        let state = match &settings.state_path {
+            Some(path) => StateStore::open(path).map_err(|e| {
+                AgentError::InitError(format!("Failed to open state at {}: {}", path, e))
+            })?,
+            None => StateStore::new(),
+        };
+        let state = Arc::new(Mutex::new(state));
         
-        // Initialize WASM sandbox
-        let wasm_path = config.get("wasm_path").ok_or_else(|| {
-            AgentError::InitError("Missing wasm_path in config".to_string())
-        })?;
+        // Resolve the custom handler, which only custom agents may name
+        let custom_handler = match settings.extra.get("custom_handler") {
+            Some(name) if agent_type != AgentType::Custom => {
+                return Err(AgentError::InitError(format!(
+                    "custom_handler '{}' requires a custom agent", name
+                )));
+            }
+            Some(name) => Some(custom::lookup(name).ok_or_else(|| {
+                AgentError::InitError(format!("Unknown custom handler: {}", name))
+            })?),
+            None => None,
+        };
         
-        let sandbox = match WasmHost::new(wasm_path) {
-            Ok(s) => s,
+        // Initialize WASM sandbox from a module path or inline base64 module;
+        // agents with a custom handler may run without one
+        let sandbox = if let Some(wasm_path) = &settings.wasm_path {
+            Some(WasmHost::new(wasm_path))
+        } else if let Some(wasm_base64) = &settings.wasm_base64 {
+            let module_bytes = general_purpose::STANDARD.decode(wasm_base64).map_err(|e| {
+                AgentError::InitError(format!("Invalid wasm_base64 in config: {}", e))
+            })?;
+            Some(WasmHost::from_bytes(&module_bytes))
+        } else if custom_handler.is_some() {
+            None
+        } else {
+            return Err(AgentError::InitError("Missing wasm_path or wasm_base64 in config".to_string()));
+        };
+
+        // Reject bad capability names before any sandbox applies them
+        if let Some(names) = &settings.capabilities {
+            Capabilities::from_str(names).map_err(AgentError::InitError)?;
+        }
+        
+        let sandbox = match sandbox.transpose() {
+            Ok(s) => s.map(|s| Self::apply_limits(s, &settings)),
             Err(e) => {
                 return Err(AgentError::SandboxError(format!("Failed to create WASM host: {}", e)));
             }
@@ -107,37 +213,667 @@ impl Agent {
             id,
             agent_type,
             config,
+            max_input_bytes: settings.max_input_bytes,
+            deterministic_timestamp: settings.deterministic_timestamp,
+            proof_hasher: None,
+            proof_labels: BTreeMap::new(),
+            settings,
             state,
             sandbox,
+            custom_handler,
+            hooks: hooks::hooks_for(agent_type),
+            middleware: Vec::new(),
+            auto_rollback: false,
+            retry_policy: None,
+            shut_down: false,
             last_execution: None,
+            last_proof_events: Vec::new(),
+            last_metrics: None,
+            cumulative_metrics: ExecutionMetrics::default(),
         })
     }
     
+    /// Create an agent whose proofs are HMAC-SHA256 digests keyed with `proof_key`
+    ///
+    /// Only holders of the key can produce or check these proofs; verify them
+    /// with `ExecutionProof::verify_keyed`. The key is never serialized.
+    pub fn new_with_proof_key(agent_type_str: &str, config_json: &str, proof_key: &[u8]) -> Result<Self, AgentError> {
+        let mut agent = Self::new(agent_type_str, config_json)?;
+        agent.proof_hasher = Some(HmacSha256Hasher::new(proof_key));
+        Ok(agent)
+    }
+    
+    /// Apply resource limits and capabilities from config; anything unset keeps the sandbox default
+    fn apply_limits(mut sandbox: WasmHost, settings: &AgentConfig) -> WasmHost {
+        if let Some(timeout_ms) = settings.execution_timeout_ms {
+            sandbox.set_execution_timeout_ms(timeout_ms);
+        }
+        if let Some(memory_limit) = settings.memory_limit {
+            sandbox.set_memory_limit(memory_limit);
+            if sandbox.memory_limit() != memory_limit {
+                crate::log_warn(&format!(
+                    "memory_limit {} is below the minimum, using {}", memory_limit, sandbox.memory_limit()
+                ));
+            }
+        }
+        if let Some(fuel_limit) = settings.fuel_limit {
+            sandbox.set_fuel_limit(fuel_limit);
+        }
+        if let Some(stack_size) = settings.stack_size {
+            if let Err(e) = sandbox.set_stack_size(stack_size) {
+                crate::log_warn(&format!("Failed to apply stack_size {}: {}", stack_size, e));
+            } else if sandbox.stack_size() != stack_size {
+                crate::log_warn(&format!(
+                    "stack_size {} is out of range, using {}", stack_size, sandbox.stack_size()
+                ));
+            }
+        }
+        if let Some(pool_size) = settings.instance_pool_size {
+            if let Err(e) = sandbox.set_instance_pool_size(Some(pool_size)) {
+                crate::log_warn(&format!("Failed to apply instance_pool_size {}: {}", pool_size, e));
+            }
+        }
+        if let Some(Ok(capabilities)) = settings.capabilities.as_deref().map(Capabilities::from_str) {
+            sandbox.set_capabilities(capabilities);
+        }
+        sandbox
+    }
+    
+    /// Replace the agent's WASM module with the one at `module_path`
+    ///
+    /// The id, state, and limits are kept, and the next execution runs the new
+    /// module. An agent without a sandbox gets one. `settings()` still reports
+    /// the config the agent was created with.
+    pub fn reload_module(&mut self, module_path: &str) -> Result<(), AgentError> {
+        let result = match &mut self.sandbox {
+            Some(sandbox) => sandbox.reload(module_path),
+            None => WasmHost::new(module_path)
+                .map(|sandbox| self.sandbox = Some(Self::apply_limits(sandbox, &self.settings))),
+        };
+        result.map_err(|e| AgentError::SandboxError(format!("Failed to reload WASM module: {}", e)))
+    }
+    
+    /// Replace the agent's WASM module with an in-memory one
+    pub fn reload_module_bytes(&mut self, module_bytes: &[u8]) -> Result<(), AgentError> {
+        let result = match &mut self.sandbox {
+            Some(sandbox) => sandbox.reload_bytes(module_bytes),
+            None => WasmHost::from_bytes(module_bytes)
+                .map(|sandbox| self.sandbox = Some(Self::apply_limits(sandbox, &self.settings))),
+        };
+        result.map_err(|e| AgentError::SandboxError(format!("Failed to reload WASM module: {}", e)))
+    }
+    
+    /// Create a copy of this agent with a fresh id and a forked state
+    ///
+    /// The fork shares the compiled module and starts from the current state,
+    /// but executions on either agent never affect the other. Limits, handler,
+    /// auto-rollback, and the retry policy carry over; middleware, sub-agents, proofs, and
+    /// metrics do not.
+    pub fn fork(&self) -> Result<Agent, AgentError> {
+        self.ensure_running()?;
+        let state = self.lock_state()?.fork();
+        let id = uuid::Uuid::new_v4().to_string();
+        
+        let mut settings = self.settings.clone();
+        settings.id = Some(id.clone());
+        let mut config = self.config.clone();
+        config.insert("id".to_string(), id.clone());
+        
+        Ok(Agent {
+            id,
+            agent_type: self.agent_type,
+            config,
+            settings,
+            state: Arc::new(Mutex::new(state)),
+            sandbox: self.sandbox.clone(),
+            custom_handler: self.custom_handler.clone(),
+            hooks: hooks::hooks_for(self.agent_type),
+            middleware: Vec::new(),
+            max_input_bytes: self.max_input_bytes,
+            deterministic_timestamp: self.deterministic_timestamp,
+            proof_hasher: self.proof_hasher.clone(),
+            proof_labels: self.proof_labels.clone(),
+            auto_rollback: self.auto_rollback,
+            retry_policy: self.retry_policy,
+            shut_down: false,
+            last_execution: None,
+            last_proof_events: Vec::new(),
+            last_metrics: None,
+            cumulative_metrics: ExecutionMetrics::default(),
+        })
+    }
+    
+    /// Register a handler that custom agents can name with `custom_handler`
+    pub fn register_custom_handler(name: &str, handler: impl CustomAgentHandler + 'static) {
+        custom::register(name, Arc::new(handler));
+    }
+    
+    /// Install middleware to run around every execution, after any already installed
+    pub fn add_middleware(&mut self, middleware: impl AgentMiddleware + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+    
+    /// Get the largest input accepted, if limited
+    pub fn max_input_bytes(&self) -> Option<usize> {
+        self.max_input_bytes
+    }
+    
+    /// Reject inputs larger than `limit` bytes, or accept any size with `None`
+    pub fn set_max_input_bytes(&mut self, limit: Option<usize>) {
+        self.max_input_bytes = limit;
+    }
+    
+    /// Check an input against the size limit
+    fn check_input_size(&self, input: &[u8]) -> Result<(), AgentError> {
+        match self.max_input_bytes {
+            Some(limit) if input.len() > limit => Err(AgentError::InputTooLarge { size: input.len(), limit }),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Shut the agent down, returning its final proof
+    ///
+    /// Flushes persistent state, records a `shutdown` proof chained to the
+    /// last execution, and releases the sandbox.
+    pub fn shutdown(mut self) -> Result<ExecutionProof, AgentError> {
+        self.finalize()?;
+        self.last_execution
+            .take()
+            .ok_or_else(|| AgentError::ExecutionError("Agent has no shutdown proof".to_string()))
+    }
+    
+    /// Shut the agent down in place; later calls do nothing
+    ///
+    /// Afterwards every execution fails. The shutdown proof is recorded and
+    /// the sandbox released even if flushing the state fails.
+    pub fn finalize(&mut self) -> Result<(), AgentError> {
+        if self.shut_down {
+            return Ok(());
+        }
+        let _log_scope = LogScope::enter(&self.id);
+        let flushed = self.lock_state()?.flush();
+        
+        let proof = self.new_proof(SHUTDOWN_INPUT, &[]);
+        crate::log_info(&format!("Shutdown proof: {}", proof.to_json()));
+        self.last_execution = Some(proof);
+        self.sandbox = None;
+        self.shut_down = true;
+        
+        flushed.map_err(|e| AgentError::StateError(format!("Failed to flush state: {}", e)))
+    }
+    
+    /// Whether the agent has been shut down
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
+    
+    fn ensure_running(&self) -> Result<(), AgentError> {
+        if self.shut_down {
+            return Err(AgentError::ExecutionError("Agent has been shut down".to_string()));
+        }
+        Ok(())
+    }
+    
+    /// Get the labels recorded in every proof
+    pub fn proof_labels(&self) -> &BTreeMap<String, String> {
+        &self.proof_labels
+    }
+    
+    /// Record `key = value` in the metadata of every later proof
+    pub fn set_proof_label(&mut self, key: &str, value: &str) {
+        self.proof_labels.insert(key.to_string(), value.to_string());
+    }
+    
+    /// Get the timestamp used for reproducible proofs, if deterministic mode is on
+    pub fn deterministic_timestamp(&self) -> Option<u64> {
+        self.deterministic_timestamp
+    }
+    
+    /// Stamp proofs with `timestamp` instead of the clock, or `None` to use the clock
+    ///
+    /// With a fixed timestamp, agents sharing an id produce identical proof
+    /// hashes for the same sequence of inputs and outputs, so proofs from
+    /// different nodes can be compared. Proof TTLs count from this timestamp,
+    /// and guests calling `now_millis` see it too.
+    pub fn set_deterministic_timestamp(&mut self, timestamp: Option<u64>) {
+        self.deterministic_timestamp = timestamp;
+    }
+    
+    /// Serve the guest's `now_millis` from `source`; agents without a sandbox ignore it
+    pub fn set_time_source(&mut self, source: Arc<dyn TimeSource>) {
+        if let Some(sandbox) = &mut self.sandbox {
+            sandbox.set_time_source(source);
+        }
+    }
+    
+    /// Generate the proof for an execution, chained to the previous one when present
+    fn new_proof(&self, input: &[u8], output: &[u8]) -> ExecutionProof {
+        let prev = self.last_execution.as_ref();
+        let hasher = self.hasher();
+        let proof = match (self.deterministic_timestamp, prev) {
+            (Some(timestamp), prev) => {
+                ExecutionProof::new_deterministic_with_hasher(hasher, &self.id, input, output, timestamp, prev)
+            }
+            (None, Some(prev)) => ExecutionProof::new_chained_with_hasher(hasher, &self.id, input, output, prev),
+            (None, None) => ExecutionProof::new_with_hasher(hasher, &self.id, input, output),
+        };
+        proof.with_metadata(hasher, ProofMetadata {
+            agent_type: Some(self.agent_type.as_str().to_string()),
+            module_hash: self.module_hash().map(|h| h.to_string()),
+            labels: self.proof_labels.clone(),
+        })
+    }
+    
+    /// Hasher for new proofs: the proof key, else the previous proof's algorithm
+    fn hasher(&self) -> &dyn ProofHasher {
+        match &self.proof_hasher {
+            Some(hasher) => hasher,
+            None => self.last_execution.as_ref().and_then(|p| hasher_for(p.algorithm())).unwrap_or(&Sha256Hasher),
+        }
+    }
+    
+    /// Turn guest proof events into proofs, once the execution's proof exists
+    ///
+    /// Event proofs are deterministic, stamped with the execution proof's
+    /// timestamp, and chained to each other in emission order, so
+    /// `verify_chain` over the labels and data checks the whole sequence.
+    fn record_events(&mut self, events: Vec<GuestEvent>) {
+        let timestamp = self.last_execution.as_ref().map_or(0, |p| p.timestamp());
+        let hasher = self.hasher();
+        let mut proofs: Vec<ExecutionProof> = Vec::with_capacity(events.len());
+        for event in &events {
+            let proof = ExecutionProof::new_deterministic_with_hasher(
+                hasher, &self.id, event.label.as_bytes(), &event.data, timestamp, proofs.last(),
+            );
+            proofs.push(proof);
+        }
+        self.last_proof_events = proofs;
+    }
+    
+    /// Get the proof events emitted by guest code during the last successful execution
+    ///
+    /// Each proof's input is the event label and its output the event data.
+    pub fn last_proof_events(&self) -> &[ExecutionProof] {
+        &self.last_proof_events
+    }
+    
+    /// Snapshot the agent's state, returning the snapshot id
+    pub fn snapshot(&self) -> Result<u64, AgentError> {
+        Ok(self.lock_state()?.create_snapshot())
+    }
+    
+    /// Roll the agent's state back to a snapshot, returning whether it existed
+    pub fn rollback(&self, id: u64) -> Result<bool, AgentError> {
+        Ok(self.lock_state()?.rollback(id))
+    }
+    
+    /// Whether state changes are undone when `execute` fails
+    pub fn auto_rollback(&self) -> bool {
+        self.auto_rollback
+    }
+    
+    /// Capture the state before each `execute` and restore it if execution fails
+    ///
+    /// The capture is separate from the snapshot list, so it works even when
+    /// snapshots are disabled and never evicts user snapshots.
+    pub fn set_auto_rollback(&mut self, enabled: bool) {
+        self.auto_rollback = enabled;
+    }
+    
+    /// Get the policy for retrying transient sandbox failures, if any
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+    
+    /// Retry executions whose sandbox fails in a way `policy` covers, or never with `None`
+    ///
+    /// Each retry starts from the state as it was before the first attempt.
+    /// Traps, cancellation, and invalid input always fail straight away.
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.retry_policy = policy;
+    }
+    
     /// Execute the agent with the provided input
     pub fn execute(&mut self, input: &[u8]) -> Result<Vec<u8>, AgentError> {
+        self.run_guarded(input, None, None)
+    }
+    
+    /// Execute the agent, aborting with `ExecutionError("cancelled")` once `cancel` is set
+    ///
+    /// State changes made by a cancelled execution are rolled back.
+    pub fn execute_cancellable(&mut self, input: &[u8], cancel: &AtomicBool) -> Result<Vec<u8>, AgentError> {
+        self.run_guarded(input, Some(cancel), None)
+    }
+    
+    /// Execute the agent through the guest export `method` instead of its usual entry point
+    ///
+    /// The export takes and returns data like `agent_run`, and the execution
+    /// is otherwise handled like `execute`, proof included. Fails with an
+    /// execution error naming `method` if the module does not export it.
+    pub fn call(&mut self, method: &str, input: &[u8]) -> Result<Vec<u8>, AgentError> {
+        self.run_guarded(input, None, Some(method))
+    }
+    
+    /// Run an execution, restoring the state if it fails and rollback applies
+    fn run_guarded(&mut self, input: &[u8], cancel: Option<&AtomicBool>, method: Option<&str>) -> Result<Vec<u8>, AgentError> {
+        self.ensure_running()?;
+        let _log_scope = LogScope::enter(&self.id);
+        let checkpoint = if self.auto_rollback || cancel.is_some() {
+            Some(self.lock_state()?.checkpoint())
+        } else {
+            None
+        };
+        
+        let result = self.run(input, cancel, method);
+        if let (Some(checkpoint), Err(e)) = (&checkpoint, &result) {
+            let cancelled = matches!(e, AgentError::ExecutionError(msg) if msg == "cancelled");
+            if self.auto_rollback || cancelled {
+                self.lock_state()?.restore_checkpoint(checkpoint);
+            }
+        }
+        
+        result
+    }
+    
+    fn lock_state(&self) -> Result<std::sync::MutexGuard<'_, StateStore>, AgentError> {
+        self.state.lock().map_err(|e| AgentError::StateError(format!("Failed to lock state: {}", e)))
+    }
+    
+    fn run(&mut self, input: &[u8], cancel: Option<&AtomicBool>, method: Option<&str>) -> Result<Vec<u8>, AgentError> {
+        self.check_input_size(input)?;
+        let started = Instant::now();
+        for middleware in &self.middleware {
+            middleware.before(input)?;
+        }
+        
         // Create execution context
-        let mut context = ExecutionContext {
+        let mut context = self.context(input);
+        context.entry_point = method.or(context.entry_point);
+        self.hooks.before(&mut context)?;
+        
+        // Execute in sandbox; agents without a module pass the input straight through
+        let result = match &self.sandbox {
+            Some(sandbox) => self.execute_sandbox(sandbox, &mut context, cancel)?,
+            None => input.to_vec(),
+        };
+        let usage = context.usage;
+        let events = context.events;
+        
+        let (result, _) = self.finish(input, result)?;
+        self.record_events(events);
+        self.record_metrics(ExecutionMetrics {
+            executions: 1,
+            duration: started.elapsed(),
+            input_size: input.len(),
+            output_size: result.len(),
+            fuel_consumed: usage.fuel_consumed,
+            host_fuel: usage.host_fuel,
+            peak_memory: usage.peak_memory,
+        });
+        
+        Ok(result)
+    }
+    
+    /// Execute the agent on each input in turn, returning each output with its proof
+    ///
+    /// The sandbox instance is reused across the batch, so guest memory carries
+    /// over between inputs. Inputs fail and are retried like single executions:
+    /// with auto-rollback on, a failed input's state changes are undone, and
+    /// the retry policy reruns the batch from the failed input. On failure the
+    /// inputs that succeeded are returned in `AgentError::BatchFailed`.
+    ///
+    /// The batch is measured as a whole: `last_metrics` covers every input that
+    /// succeeded.
+    pub fn execute_batch(&mut self, inputs: &[&[u8]]) -> Result<Vec<(Vec<u8>, ExecutionProof)>, AgentError> {
+        self.ensure_running()?;
+        let _log_scope = LogScope::enter(&self.id);
+        let started = Instant::now();
+        
+        // Run the pre-execution middleware and hooks over every input up front
+        for (index, input) in inputs.iter().enumerate() {
+            let mut context = self.context(input);
+            let checked = self.check_input_size(input)
+                .and_then(|()| self.middleware.iter().try_for_each(|middleware| middleware.before(input)))
+                .and_then(|()| self.hooks.before(&mut context));
+            if let Err(e) = checked {
+                return Err(AgentError::BatchFailed {
+                    index,
+                    completed: Vec::new(),
+                    error: Box::new(e),
+                });
+            }
+        }
+        
+        let policy = self.retry_policy.filter(|policy| policy.max_attempts > 1);
+        let checkpointed = self.auto_rollback || policy.is_some();
+        let mut completed = Vec::with_capacity(inputs.len());
+        let mut usage = ResourceUsage::default();
+        // Proof events of completed inputs only; failed and retried inputs leave none
+        let mut events = Vec::new();
+        let mut attempt = 1;
+        let failure = loop {
+            let offset = completed.len();
+            let (results, checkpoints, run_usage) = self.run_batch(&inputs[offset..], checkpointed);
+            usage.fuel_consumed += run_usage.fuel_consumed;
+            usage.host_fuel.accumulate(&run_usage.host_fuel);
+            usage.peak_memory = usage.peak_memory.max(run_usage.peak_memory);
+            
+            // The failed input's index within this run, the policy to retry it under, and the error
+            let mut failure = None;
+            for (index, (result, input_events)) in results.into_iter().enumerate() {
+                let finished = match result {
+                    Ok(output) => self.finish(inputs[offset + index], output),
+                    Err(e) => {
+                        let retry = policy.filter(|policy| attempt < policy.max_attempts && policy.retries(&e));
+                        failure = Some((index, retry, self.sandbox_error(e)));
+                        break;
+                    }
+                };
+                match finished {
+                    Ok(finished) => {
+                        completed.push(finished);
+                        events.extend(input_events);
+                    }
+                    Err(e) => {
+                        failure = Some((index, None, e));
+                        break;
+                    }
+                }
+            }
+            
+            let Some((index, retry, error)) = failure else {
+                break None;
+            };
+            if self.auto_rollback || retry.is_some() {
+                if let Some(checkpoint) = checkpoints.get(index) {
+                    self.lock_state()?.restore_checkpoint(checkpoint);
+                }
+            }
+            let Some(policy) = retry else {
+                break Some((offset + index, error));
+            };
+            
+            let backoff = policy.backoff(attempt);
+            crate::log_warn(&format!(
+                "Batch input {} failed on attempt {} of {}, retrying in {} ms: {}",
+                offset + index,
+                attempt,
+                policy.max_attempts,
+                backoff.as_millis(),
+                error
+            ));
+            std::thread::sleep(backoff);
+            attempt += 1;
+        };
+        
+        if !completed.is_empty() {
+            self.record_events(events);
+            self.record_metrics(ExecutionMetrics {
+                executions: completed.len() as u64,
+                duration: started.elapsed(),
+                input_size: inputs[..completed.len()].iter().map(|i| i.len()).sum(),
+                output_size: completed.iter().map(|(output, _)| output.len()).sum(),
+                fuel_consumed: usage.fuel_consumed,
+                host_fuel: usage.host_fuel,
+                peak_memory: usage.peak_memory,
+            });
+        }
+        
+        match failure {
+            Some((index, error)) => Err(AgentError::BatchFailed {
+                index,
+                completed,
+                error: Box::new(error),
+            }),
+            None => Ok(completed),
+        }
+    }
+    
+    /// Run a batch of inputs in the sandbox, stopping at the first failure
+    ///
+    /// With `checkpointed` set, the state is captured before each input so a
+    /// failed input's changes can be undone. Returns each result with the
+    /// proof events of its input, the checkpoints, and the resources of the run.
+    #[allow(clippy::type_complexity)]
+    fn run_batch(
+        &self,
+        inputs: &[&[u8]],
+        checkpointed: bool,
+    ) -> (Vec<(Result<Vec<u8>, WasmHostError>, Vec<GuestEvent>)>, Vec<StateSnapshot>, ResourceUsage) {
+        let Some(sandbox) = &self.sandbox else {
+            let results = inputs.iter().map(|input| (Ok(input.to_vec()), Vec::new())).collect();
+            return (results, Vec::new(), ResourceUsage::default());
+        };
+        
+        let mut context = self.context(&[]);
+        let mut checkpoints = Vec::new();
+        let results = sandbox.execute_batch_with(&mut context, inputs, || {
+            if checkpointed {
+                checkpoints.push(self.state.lock().unwrap_or_else(PoisonError::into_inner).checkpoint());
+            }
+        });
+        
+        (results, checkpoints, context.usage)
+    }
+    
+    /// Context for running the agent on `input` through its configured entry point
+    fn context<'a>(&'a self, input: &'a [u8]) -> ExecutionContext<'a> {
+        ExecutionContext {
             agent_id: &self.id,
             agent_type: self.agent_type,
             input,
             state: self.state.clone(),
+            usage: ResourceUsage::default(),
+            proof_hasher: self.proof_hasher.as_ref(),
+            events: Vec::new(),
+            deterministic_timestamp: self.deterministic_timestamp,
+            entry_point: self.settings.method.as_deref(),
+        }
+    }
+    
+    /// Post-process sandbox output and record its proof
+    ///
+    /// The output goes through the custom handler, if the agent has one, then
+    /// the type's hooks, then the installed middleware.
+    fn finish(&mut self, input: &[u8], output: Vec<u8>) -> Result<(Vec<u8>, ExecutionProof), AgentError> {
+        let output = match &self.custom_handler {
+            Some(handler) => handler.handle(&mut self.context(&output))?,
+            None => output,
         };
         
-        // Execute in sandbox
-        let result = match self.sandbox.execute(&mut context) {
-            Ok(r) => r,
-            Err(e) => {
-                return Err(AgentError::ExecutionError(format!("Sandbox execution failed: {}", e)));
-            }
-        };
+        let mut context = self.context(input);
+        let mut output = self.hooks.after(&mut context, output)?;
+        for middleware in &self.middleware {
+            output = middleware.after(output)?;
+        }
         
-        // Generate execution proof
-        let proof = ExecutionProof::new(&self.id, input, &result);
-        self.last_execution = Some(proof);
+        // Generate execution proof, chained to the previous one when present
+        let proof = self.new_proof(input, &output);
+        self.hooks.on_proof(&context, &output, &proof)?;
+        self.last_execution = Some(proof.clone());
         
-        Ok(result)
+        Ok((output, proof))
+    }
+    
+    /// Attach a sub-agent that a coordinator fans its output out to
+    pub fn add_sub_agent(&mut self, agent: Agent) -> Result<(), AgentError> {
+        self.hooks.add_sub_agent(agent)
     }
     
+    /// Run the sandbox, retrying failures the retry policy covers
+    ///
+    /// Before every retry the state is restored to its contents ahead of the
+    /// first attempt, so a failed attempt's writes never reach the next one.
+    fn execute_sandbox(
+        &self,
+        sandbox: &WasmHost,
+        context: &mut ExecutionContext,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<Vec<u8>, AgentError> {
+        let policy = self.retry_policy.filter(|policy| policy.max_attempts > 1);
+        let checkpoint = match policy {
+            Some(_) => Some(self.lock_state()?.checkpoint()),
+            None => None,
+        };
+        
+        let mut attempt = 1;
+        loop {
+            let result = match cancel {
+                Some(cancel) => sandbox.execute_cancellable(context, cancel),
+                None => sandbox.execute(context),
+            };
+            let (error, policy) = match (result, policy) {
+                (Err(e), Some(policy)) if attempt < policy.max_attempts && policy.retries(&e) => (e, policy),
+                (result, _) => return result.map_err(|e| self.sandbox_error(e)),
+            };
+            if let Some(checkpoint) = &checkpoint {
+                self.lock_state()?.restore_checkpoint(checkpoint);
+            }
+            
+            let backoff = policy.backoff(attempt);
+            crate::log_warn(&format!(
+                "Execution attempt {} of {} failed, retrying in {} ms: {}",
+                attempt,
+                policy.max_attempts,
+                backoff.as_millis(),
+                error
+            ));
+            std::thread::sleep(backoff);
+            attempt += 1;
+        }
+    }
+    
+    /// Map a sandbox error, keeping timeouts and memory exhaustion distinguishable
+    fn sandbox_error(&self, error: WasmHostError) -> AgentError {
+        match error {
+            WasmHostError::ExecutionError(msg) if msg == "timeout" => {
+                let timeout_ms = self.sandbox.as_ref().map_or(0, |s| s.execution_timeout_ms());
+                AgentError::Timeout(format!("Exceeded {} ms", timeout_ms))
+            }
+            WasmHostError::ExecutionError(msg) if msg == "cancelled" => AgentError::ExecutionError(msg),
+            WasmHostError::MemoryError(msg) => AgentError::OutOfMemory(msg),
+            WasmHostError::Trap(code, msg) => AgentError::Trap(code, msg),
+            e => AgentError::ExecutionError(format!("Sandbox execution failed: {}", e)),
+        }
+    }
+    
+    /// Store the metrics of a successful execution and add them to the totals
+    fn record_metrics(&mut self, metrics: ExecutionMetrics) {
+        self.cumulative_metrics.accumulate(&metrics);
+        self.last_metrics = Some(metrics);
+    }
+    
+    /// Get the metrics of the last successful execution
+    pub fn last_metrics(&self) -> Option<&ExecutionMetrics> {
+        self.last_metrics.as_ref()
+    }
+    
+    /// Get the metrics of all successful executions combined
+    pub fn cumulative_metrics(&self) -> &ExecutionMetrics {
+        &self.cumulative_metrics
+    }
+    
+    
     /// Get the last execution proof
     pub fn get_last_proof(&self) -> Option<&ExecutionProof> {
         self.last_execution.as_ref()
@@ -153,10 +889,52 @@ impl Agent {
         self.agent_type
     }
     
-    /// Get agent configuration
+    /// Get agent configuration as flat string key/value pairs
     pub fn config(&self) -> &HashMap<String, String> {
         &self.config
     }
+    
+    /// Get the agent's state store
+    pub fn state(&self) -> Arc<Mutex<StateStore>> {
+        self.state.clone()
+    }
+    
+    /// Get the sandbox running this agent, if it has a WASM module
+    pub fn sandbox(&self) -> Option<&WasmHost> {
+        self.sandbox.as_ref()
+    }
+    
+    /// Get the base64 SHA-256 of the agent's WASM module, if it has one
+    ///
+    /// Agents running the same module bytes report the same hash, so nodes
+    /// can confirm they ran the same code.
+    pub fn module_hash(&self) -> Option<&str> {
+        self.sandbox.as_ref().map(|s| s.module_hash())
+    }
+    
+    /// Get the typed agent configuration
+    pub fn settings(&self) -> &AgentConfig {
+        &self.settings
+    }
+    
+    /// Summarize the live agent as JSON for status displays
+    ///
+    /// Holds `id`, `agent_type`, sorted `config_keys`, `has_proof`, and
+    /// `state_size`, the number of unexpired state entries.
+    pub fn info_json(&self) -> Result<String, AgentError> {
+        let mut config_keys: Vec<&String> = self.config.keys().collect();
+        config_keys.sort();
+        let state_size = self.lock_state()?.size();
+        
+        Ok(serde_json::json!({
+            "id": self.id,
+            "agent_type": self.agent_type.as_str(),
+            "config_keys": config_keys,
+            "has_proof": self.last_execution.is_some(),
+            "state_size": state_size,
+            "module_hash": self.module_hash(),
+        }).to_string())
+    }
 }
 
 /// Execution context for agent
@@ -165,4 +943,575 @@ pub struct ExecutionContext<'a> {
     pub agent_type: AgentType,
     pub input: &'a [u8],
     pub state: Arc<Mutex<StateStore>>,
-}
\ No newline at end of file
+    /// Resources consumed by guest code, filled in by the sandbox
+    pub usage: ResourceUsage,
+    /// Keyed hasher the agent's proofs are built with, if it has a proof key
+    pub proof_hasher: Option<&'a HmacSha256Hasher>,
+    /// Proof events emitted by guest code, filled in by the sandbox
+    pub events: Vec<GuestEvent>,
+    /// Fixed Unix time in seconds that guests see instead of the clock
+    pub deterministic_timestamp: Option<u64>,
+    /// Guest export the sandbox calls instead of `agent_run`, if any
+    pub entry_point: Option<&'a str>,
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    use base64::{Engine as _, engine::general_purpose};
+
+    use super::*;
+    use crate::engine::retry::RetryOn;
+
+    const EMPTY_OUTPUT: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "alloc") (param i32) (result i32) (i32.const 0))
+        (func (export "agent_run") (param i32 i32) (result i64) (i64.const 0)))"#;
+
+    const NO_ALLOC: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "agent_run") (param i32 i32) (result i64) (i64.const 0)))"#;
+
+    /// Records each input as a state key, then traps on inputs starting with 'x'
+    const RECORD_INPUT: &str = r#"(module
+        (import "korra" "state_set" (func $state_set (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 100) "\01\00\00\00v")
+        (func (export "alloc") (param i32) (result i32) (i32.const 8))
+        (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+            (i32.store (i32.const 4) (local.get $len))
+            (drop (call $state_set (i32.const 4) (i32.const 100)))
+            (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120)) (then unreachable))
+            (i64.or
+                (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                (i64.extend_i32_u (local.get $len)))))"#;
+
+    fn agent(wat: &str) -> Agent {
+        agent_with(wat, "")
+    }
+
+    fn agent_with(wat: &str, extra_config: &str) -> Agent {
+        let config = format!(
+            r#"{{"wasm_base64": "{}"{}}}"#,
+            general_purpose::STANDARD.encode(wat),
+            extra_config
+        );
+        Agent::new("custom", &config).unwrap()
+    }
+
+    fn recording_agent() -> Agent {
+        agent_with(RECORD_INPUT, r#", "capabilities": "STATE_WRITE""#)
+    }
+
+    fn policy(max_attempts: u32, initial_backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff,
+            max_backoff: initial_backoff,
+            retry_on: RetryOn::INSTANTIATION,
+        }
+    }
+
+    #[test]
+    fn transient_instantiation_failures_are_retried() {
+        let mut agent = agent(EMPTY_OUTPUT);
+        agent.set_retry_policy(Some(policy(3, Duration::from_millis(1))));
+
+        agent.sandbox.as_ref().unwrap().inject_instantiation_failures(2);
+        assert_eq!(agent.execute(b"input").unwrap(), b"");
+
+        agent.sandbox.as_ref().unwrap().inject_instantiation_failures(3);
+        let error = agent.execute(b"input").unwrap_err();
+        assert!(error.to_string().contains("Injected failure"), "{}", error);
+    }
+
+    #[test]
+    fn link_errors_are_not_retried() {
+        let mut agent = agent(NO_ALLOC);
+        agent.set_retry_policy(Some(policy(5, Duration::from_secs(10))));
+
+        let started = Instant::now();
+        let error = agent.execute(b"input").unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(error.to_string().contains("Link error"), "{}", error);
+    }
+
+    #[test]
+    fn batch_returns_a_proof_per_input() {
+        let mut agent = recording_agent();
+        let inputs: [&[u8]; 3] = [b"a", b"b", b"c"];
+
+        let results = agent.execute_batch(&inputs).unwrap();
+        assert_eq!(results.len(), 3);
+        for (input, (output, proof)) in inputs.iter().zip(&results) {
+            assert_eq!(output, input);
+            assert!(proof.verify(agent.id(), input, output));
+        }
+        assert_eq!(results[2].1.prev_hash(), Some(results[1].1.proof_hash()));
+    }
+
+    #[test]
+    fn batch_failure_keeps_completed_proofs_and_rolls_back_the_failed_input() {
+        let mut agent = recording_agent();
+        agent.set_auto_rollback(true);
+
+        let error = agent.execute_batch(&[b"a", b"xfail", b"c"]).unwrap_err();
+        let AgentError::BatchFailed { index, completed, error } = error else {
+            panic!("expected a batch failure");
+        };
+        assert_eq!(index, 1);
+        assert_eq!(completed.len(), 1);
+        assert!(completed[0].1.verify(agent.id(), b"a", b"a"));
+        assert!(matches!(*error, AgentError::Trap(TrapCode::Unreachable, _)));
+
+        let state = agent.state();
+        let state = state.lock().unwrap();
+        assert!(state.get("a").is_some());
+        assert!(state.get("xfail").is_none());
+        assert!(state.get("c").is_none());
+    }
+
+    #[test]
+    fn batch_retries_transient_failures() {
+        let mut agent = recording_agent();
+        agent.set_retry_policy(Some(policy(3, Duration::from_millis(1))));
+        agent.sandbox.as_ref().unwrap().inject_instantiation_failures(2);
+
+        let results = agent.execute_batch(&[b"a", b"b"]).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn inputs_over_the_limit_are_rejected_before_running() {
+        let mut agent = agent_with(EMPTY_OUTPUT, r#", "max_input_bytes": 4"#);
+
+        assert!(agent.execute(b"four").is_ok());
+        assert!(matches!(
+            agent.execute(b"fives"),
+            Err(AgentError::InputTooLarge { size: 5, limit: 4 })
+        ));
+        assert_eq!(agent.cumulative_metrics().executions, 1);
+    }
+
+    #[test]
+    fn invalid_numeric_options_fail_at_construction() {
+        let module = general_purpose::STANDARD.encode(EMPTY_OUTPUT);
+        let config = format!(r#"{{"wasm_base64": "{}", "execution_timeout_ms": "soon"}}"#, module);
+
+        match Agent::new("custom", &config) {
+            Err(AgentError::InitError(msg)) => assert!(msg.contains("execution_timeout_ms"), "{}", msg),
+            other => panic!("expected InitError, got {:?}", other.err()),
+        }
+
+        let agent = agent_with(EMPTY_OUTPUT, r#", "execution_timeout_ms": "250", "team": "blue""#);
+        assert_eq!(agent.settings().execution_timeout_ms, Some(250));
+        assert_eq!(agent.config()["execution_timeout_ms"], "250");
+        assert_eq!(agent.config()["team"], "blue");
+    }
+
+    #[test]
+    fn config_limits_reach_the_sandbox() {
+        let agent = agent_with(
+            EMPTY_OUTPUT,
+            r#", "execution_timeout_ms": 100, "memory_limit": 131072, "fuel_limit": 5000000"#,
+        );
+        let sandbox = agent.sandbox().unwrap();
+
+        assert_eq!(sandbox.execution_timeout_ms(), 100);
+        assert_eq!(sandbox.memory_limit(), 131072);
+        assert_eq!(sandbox.fuel_limit(), Some(5_000_000));
+    }
+
+    #[test]
+    fn cancelling_aborts_a_running_guest_and_undoes_its_writes() {
+        const WRITE_THEN_SPIN: &str = r#"(module
+            (import "korra" "state_set" (func $state_set (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\01\00\00\00k\01\00\00\00v")
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $state_set (i32.const 0) (i32.const 5)))
+                (loop $spin (br $spin))
+                (i64.const 0)))"#;
+        let mut agent = agent_with(WRITE_THEN_SPIN, r#", "execution_timeout_ms": 60000"#);
+        let cancel = AtomicBool::new(false);
+
+        let started = Instant::now();
+        let result = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(50));
+                cancel.store(true, Ordering::SeqCst);
+            });
+            agent.execute_cancellable(b"input", &cancel)
+        });
+
+        assert!(matches!(&result, Err(AgentError::ExecutionError(msg)) if msg == "cancelled"), "{:?}", result);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(agent.state().try_lock().unwrap().get("k"), None);
+    }
+
+    #[test]
+    fn metrics_advance_across_executions() {
+        let mut agent = recording_agent();
+        assert!(agent.last_metrics().is_none());
+
+        agent.execute(b"first").unwrap();
+        let first = *agent.last_metrics().unwrap();
+        assert_eq!(first.executions, 1);
+        assert_eq!((first.input_size, first.output_size), (5, 5));
+        assert!(first.fuel_consumed > 0);
+        assert!(first.peak_memory >= 65536);
+
+        agent.execute(b"second!").unwrap();
+        let total = agent.cumulative_metrics();
+        assert_eq!(total.executions, 2);
+        assert_eq!((total.input_size, total.output_size), (12, 12));
+        assert!(total.fuel_consumed > first.fuel_consumed);
+        assert!(total.duration >= first.duration);
+    }
+
+    #[test]
+    fn custom_handlers_receive_the_input() {
+        struct Reverse;
+
+        impl CustomAgentHandler for Reverse {
+            fn handle(&self, context: &mut ExecutionContext) -> Result<Vec<u8>, AgentError> {
+                Ok(context.input.iter().rev().copied().collect())
+            }
+        }
+
+        Agent::register_custom_handler("test-reverse", Reverse);
+        let mut standalone = Agent::new("custom", r#"{"custom_handler": "test-reverse"}"#).unwrap();
+        assert_eq!(standalone.execute(b"abc").unwrap(), b"cba");
+
+        let mut chained = agent_with(RECORD_INPUT, r#", "capabilities": "STATE_WRITE", "custom_handler": "test-reverse""#);
+        assert_eq!(chained.execute(b"wasm").unwrap(), b"msaw");
+
+        let unknown = Agent::new("custom", r#"{"custom_handler": "test-missing"}"#);
+        assert!(matches!(unknown, Err(AgentError::InitError(msg)) if msg.contains("test-missing")));
+        let wrong_type = Agent::new("analyzer", r#"{"custom_handler": "test-reverse"}"#);
+        assert!(matches!(wrong_type, Err(AgentError::InitError(_))));
+    }
+
+    #[test]
+    fn each_agent_type_adds_its_own_processing() {
+        const ECHO: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len)))))"#;
+        let typed = |agent_type: &str| {
+            let config = format!(r#"{{"wasm_base64": "{}"}}"#, general_purpose::STANDARD.encode(ECHO));
+            Agent::new(agent_type, &config).unwrap()
+        };
+
+        let mut analyzer = typed("analyzer");
+        assert_eq!(analyzer.execute(b"abc").unwrap(), b"abc");
+        let stats = analyzer.state().lock().unwrap().get("korra.stats.input_bytes");
+        assert_eq!(stats, Some(3u64.to_le_bytes().to_vec()));
+
+        let mut validator = typed("validator");
+        assert_eq!(validator.execute(b"abc").unwrap(), b"abc");
+        assert!(validator.get_last_proof().unwrap().verify(validator.id(), b"abc", b"abc"));
+
+        let mut coordinator = typed("coordinator");
+        coordinator.add_sub_agent(typed("transformer")).unwrap();
+        coordinator.add_sub_agent(typed("transformer")).unwrap();
+        assert_eq!(coordinator.execute(b"ab").unwrap(), b"\x02\x00\x00\x00ab\x02\x00\x00\x00ab");
+
+        let mut transformer = typed("transformer");
+        assert_eq!(transformer.execute(b"abc").unwrap(), b"abc");
+        assert!(transformer.add_sub_agent(typed("transformer")).is_err());
+        assert!(transformer.state().lock().unwrap().keys().is_empty());
+    }
+
+    #[test]
+    fn middleware_can_reject_inputs_and_transform_outputs() {
+        struct MaxSize(usize);
+
+        impl AgentMiddleware for MaxSize {
+            fn before(&self, input: &[u8]) -> Result<(), AgentError> {
+                if input.len() > self.0 {
+                    return Err(AgentError::InvalidInput(format!("{} bytes is too many", input.len())));
+                }
+                Ok(())
+            }
+        }
+
+        struct Append(u8);
+
+        impl AgentMiddleware for Append {
+            fn after(&self, mut output: Vec<u8>) -> Result<Vec<u8>, AgentError> {
+                output.push(self.0);
+                Ok(output)
+            }
+        }
+
+        let mut agent = recording_agent();
+        agent.add_middleware(MaxSize(4));
+        agent.add_middleware(Append(b'1'));
+        agent.add_middleware(Append(b'2'));
+
+        assert!(matches!(agent.execute(b"too long"), Err(AgentError::InvalidInput(_))));
+        assert!(agent.state().lock().unwrap().keys().is_empty());
+
+        assert_eq!(agent.execute(b"ok").unwrap(), b"ok12");
+        assert!(agent.get_last_proof().unwrap().verify(agent.id(), b"ok", b"ok12"));
+    }
+
+    #[test]
+    fn agent_type_names_round_trip() {
+        let all = [
+            AgentType::Analyzer,
+            AgentType::Transformer,
+            AgentType::Validator,
+            AgentType::Coordinator,
+            AgentType::Custom,
+        ];
+        for agent_type in all {
+            assert_eq!(AgentType::from_str(agent_type.as_str()), Some(agent_type));
+            assert_eq!(agent_type.to_string(), agent_type.as_str());
+        }
+    }
+
+    #[test]
+    fn failed_executions_leave_state_unchanged_under_auto_rollback() {
+        let mut agent = recording_agent();
+        agent.execute(b"kept").unwrap();
+        let snapshot = agent.snapshot().unwrap();
+        agent.execute(b"undone").unwrap();
+        assert!(agent.rollback(snapshot).unwrap());
+        assert_eq!(agent.state().lock().unwrap().keys(), vec!["kept".to_string()]);
+
+        agent.set_auto_rollback(true);
+        assert!(agent.execute(b"xtrap").is_err());
+        assert_eq!(agent.state().lock().unwrap().keys(), vec!["kept".to_string()]);
+
+        agent.set_auto_rollback(false);
+        assert!(agent.execute(b"xtrap").is_err());
+        assert_eq!(agent.state().lock().unwrap().get("xtrap"), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn reloaded_modules_keep_the_agent_state() {
+        let mut agent = recording_agent();
+        let id = agent.id().to_string();
+        assert_eq!(agent.execute(b"before").unwrap(), b"before");
+
+        agent.reload_module_bytes(EMPTY_OUTPUT.as_bytes()).unwrap();
+
+        assert_eq!(agent.execute(b"after").unwrap(), b"");
+        assert_eq!(agent.id(), id);
+        assert_eq!(agent.state().lock().unwrap().keys(), vec!["before".to_string()]);
+        assert!(agent.reload_module_bytes(b"not wasm").is_err());
+        assert_eq!(agent.execute(b"still").unwrap(), b"");
+    }
+
+    #[test]
+    fn forks_start_from_the_parent_state_but_diverge() {
+        let mut parent = recording_agent();
+        parent.execute(b"shared").unwrap();
+
+        let mut fork = parent.fork().unwrap();
+        assert_ne!(fork.id(), parent.id());
+        assert_eq!(fork.module_hash(), parent.module_hash());
+        fork.execute(b"fork-only").unwrap();
+        parent.execute(b"parent-only").unwrap();
+
+        let mut parent_keys = parent.state().lock().unwrap().keys();
+        let mut fork_keys = fork.state().lock().unwrap().keys();
+        parent_keys.sort();
+        fork_keys.sort();
+        assert_eq!(parent_keys, vec!["parent-only".to_string(), "shared".to_string()]);
+        assert_eq!(fork_keys, vec!["fork-only".to_string(), "shared".to_string()]);
+    }
+
+    #[test]
+    fn shutdown_persists_state_and_chains_a_final_proof() {
+        let path = std::env::temp_dir().join(format!("korra-shutdown-{}.log", uuid::Uuid::new_v4()));
+        let mut agent = agent_with(
+            RECORD_INPUT,
+            &format!(r#", "capabilities": "STATE_WRITE", "state_path": "{}""#, path.display()),
+        );
+        agent.execute(b"pending").unwrap();
+        let last = agent.get_last_proof().unwrap().proof_hash().to_string();
+        let id = agent.id().to_string();
+
+        let proof = agent.shutdown().unwrap();
+        assert!(proof.verify(&id, SHUTDOWN_INPUT, b""));
+        assert_eq!(proof.prev_hash(), Some(last.as_str()));
+
+        let reopened = StateStore::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(reopened.get("pending"), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn finalize_twice_is_harmless() {
+        let mut agent = recording_agent();
+        agent.finalize().unwrap();
+        let proof = agent.get_last_proof().unwrap().proof_hash().to_string();
+
+        agent.finalize().unwrap();
+        assert!(agent.is_shut_down());
+        assert_eq!(agent.get_last_proof().unwrap().proof_hash(), proof);
+        assert!(agent.execute(b"input").is_err());
+    }
+
+    #[test]
+    fn deterministic_agents_produce_identical_proofs() {
+        let config = r#", "id": "replay", "deterministic_timestamp": 1700000000"#;
+        let mut first = agent_with(EMPTY_OUTPUT, config);
+        let mut second = agent_with(EMPTY_OUTPUT, config);
+
+        first.execute(b"input").unwrap();
+        second.execute(b"input").unwrap();
+        let first_proof = first.get_last_proof().unwrap();
+        let second_proof = second.get_last_proof().unwrap();
+        assert_eq!(first_proof.proof_hash(), second_proof.proof_hash());
+        assert_eq!(first.deterministic_timestamp(), Some(1_700_000_000));
+
+        first.set_deterministic_timestamp(None);
+        second.set_deterministic_timestamp(None);
+        first.execute(b"input").unwrap();
+        second.execute(b"input").unwrap();
+        assert_ne!(first.get_last_proof().unwrap().proof_hash(), second.get_last_proof().unwrap().proof_hash());
+    }
+
+    #[test]
+    fn keyed_proofs_need_the_right_key() {
+        let config = format!(r#"{{"wasm_base64": "{}"}}"#, general_purpose::STANDARD.encode(EMPTY_OUTPUT));
+        let mut agent = Agent::new_with_proof_key("custom", &config, b"secret").unwrap();
+        agent.execute(b"input").unwrap();
+        let proof = agent.get_last_proof().unwrap();
+
+        assert!(proof.verify_keyed(b"secret", agent.id(), b"input", b""));
+        assert!(!proof.verify_keyed(b"other", agent.id(), b"input", b""));
+        assert!(!proof.verify(agent.id(), b"input", b""));
+        assert!(!proof.to_json().contains("secret"));
+    }
+
+    #[test]
+    fn guest_proof_events_are_captured_in_order() {
+        const TWO_EVENTS: &str = r#"(module
+            (import "korra" "emit_proof_event" (func $emit (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 100) "fetchedrows=3parsedok")
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $emit (i32.const 100) (i32.const 7) (i32.const 107) (i32.const 6)))
+                (drop (call $emit (i32.const 113) (i32.const 6) (i32.const 119) (i32.const 2)))
+                (i64.const 0)))"#;
+        let mut agent = agent(TWO_EVENTS);
+        agent.execute(b"input").unwrap();
+
+        let events = agent.last_proof_events();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].verify(agent.id(), b"fetched", b"rows=3"));
+        assert!(events[1].verify(agent.id(), b"parsed", b"ok"));
+        assert_eq!(events[1].prev_hash(), Some(events[0].proof_hash()));
+    }
+
+    #[test]
+    fn configured_stack_size_turns_deep_recursion_into_a_trap() {
+        const RECURSE: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func $recurse (param i32) (result i32)
+                (i32.add (call $recurse (i32.add (local.get 0) (i32.const 1))) (i32.const 1)))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (i64.extend_i32_u (call $recurse (i32.const 0)))))"#;
+        let mut agent = agent_with(RECURSE, r#", "stack_size": 65536"#);
+        assert_eq!(agent.sandbox().unwrap().stack_size(), 65536);
+
+        let error = agent.execute(b"input").unwrap_err();
+        assert!(matches!(error, AgentError::Trap(TrapCode::StackOverflow, _)), "{}", error);
+    }
+
+    #[test]
+    fn metrics_attribute_fuel_to_each_host_function() {
+        use crate::sandbox::wasm_host::HostCall;
+
+        const STATE_CALLS: &str = r#"(module
+            (import "korra" "state_set" (func $state_set (param i32 i32) (result i32)))
+            (import "korra" "state_get" (func $state_get (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\03\00\00\00key")
+            (data (i32.const 16) "\05\00\00\00value")
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $state_set (i32.const 0) (i32.const 16)))
+                (drop (call $state_set (i32.const 0) (i32.const 16)))
+                (drop (call $state_get (i32.const 0)))
+                (i64.const 0)))"#;
+        let mut agent = agent_with(STATE_CALLS, r#", "fuel_limit": 1000000"#);
+        agent.execute(b"input").unwrap();
+        let metrics = agent.last_metrics().unwrap();
+        let host_fuel = metrics.host_fuel;
+
+        // Every call copies the same eight key and value bytes, so each costs the same
+        let per_call = host_fuel.fuel(HostCall::StateGet);
+        assert!(per_call > 8);
+        assert_eq!(host_fuel.calls(HostCall::StateGet), 1);
+        assert_eq!(host_fuel.calls(HostCall::StateSet), 2);
+        assert_eq!(host_fuel.fuel(HostCall::StateSet), 2 * per_call);
+        assert_eq!(host_fuel.calls(HostCall::EmitProofEvent), 0);
+        assert_eq!(host_fuel.total(), 3 * per_call);
+        assert!(metrics.guest_fuel() > 0);
+        assert_eq!(metrics.guest_fuel() + host_fuel.total(), metrics.fuel_consumed);
+    }
+
+    #[test]
+    fn batch_proof_events_cover_only_completed_inputs() {
+        const EMIT_INPUT: &str = r#"(module
+            (import "korra" "emit_proof_event" (func $emit (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 8))
+            (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+                (drop (call $emit (local.get $ptr) (local.get $len) (local.get $ptr) (i32.const 0)))
+                (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120)) (then unreachable))
+                (i64.const 0)))"#;
+        let mut agent = agent(EMIT_INPUT);
+        agent.execute_batch(&[b"a", b"b", b"c"]).unwrap();
+        let events = agent.last_proof_events();
+        assert_eq!(events.len(), 3);
+        for (event, label) in events.iter().zip(["a", "b", "c"]) {
+            assert!(event.verify(agent.id(), label.as_bytes(), b""));
+        }
+
+        agent.set_auto_rollback(true);
+        let err = agent.execute_batch(&[b"a", b"xfail", b"c"]).unwrap_err();
+        assert!(matches!(err, AgentError::BatchFailed { index: 1, .. }));
+        let events = agent.last_proof_events();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].verify(agent.id(), b"a", b""));
+    }
+
+    #[test]
+    fn call_runs_the_named_export() {
+        const TWO_EXPORTS: &str = r#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 100) "ready")
+            (data (i32.const 110) "ran")
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "init") (param i32 i32) (result i64)
+                (i64.const 429496729605))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (i64.const 472446402563)))"#;
+        let mut agent = agent(TWO_EXPORTS);
+
+        assert_eq!(agent.call("init", b"input").unwrap(), b"ready");
+        assert_eq!(agent.call("agent_run", b"input").unwrap(), b"ran");
+        assert_eq!(agent.execute(b"input").unwrap(), b"ran");
+
+        let result = agent.call("missing", b"input");
+        assert!(
+            matches!(&result, Err(AgentError::ExecutionError(msg)) if msg.contains("'missing'")),
+            "{:?}",
+            result
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Typed agent configuration
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+/// Agent configuration parsed from the JSON passed to `Agent::new`
+///
+/// Numeric options accept either JSON numbers or numeric strings, since the
+/// config used to be a flat map of strings. Unknown keys land in `extra`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AgentConfig {
+    pub id: Option<String>,
+    pub wasm_path: Option<String>,
+    pub wasm_base64: Option<String>,
+    pub state_path: Option<String>,
+    pub capabilities: Option<String>,
+    pub method: Option<String>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub execution_timeout_ms: Option<u64>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub memory_limit: Option<usize>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub fuel_limit: Option<u64>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub max_input_bytes: Option<usize>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub deterministic_timestamp: Option<u64>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub stack_size: Option<usize>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub instance_pool_size: Option<u32>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, String>,
+}
+
+impl AgentConfig {
+    /// Parse and validate a config JSON object
+    ///
+    /// Errors name the offending field, e.g. `config field 'memory_limit'
+    /// must be an integer`.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        validate(&value)?;
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+    
+    /// Flatten the config back into string key/value pairs
+    pub fn to_map(&self) -> HashMap<String, String> {
+        let mut map = self.extra.clone();
+        let known = [
+            ("id", self.id.clone()),
+            ("wasm_path", self.wasm_path.clone()),
+            ("wasm_base64", self.wasm_base64.clone()),
+            ("state_path", self.state_path.clone()),
+            ("capabilities", self.capabilities.clone()),
+            ("method", self.method.clone()),
+            ("execution_timeout_ms", self.execution_timeout_ms.map(|v| v.to_string())),
+            ("memory_limit", self.memory_limit.map(|v| v.to_string())),
+            ("fuel_limit", self.fuel_limit.map(|v| v.to_string())),
+            ("max_input_bytes", self.max_input_bytes.map(|v| v.to_string())),
+            ("deterministic_timestamp", self.deterministic_timestamp.map(|v| v.to_string())),
+            ("stack_size", self.stack_size.map(|v| v.to_string())),
+            ("instance_pool_size", self.instance_pool_size.map(|v| v.to_string())),
+        ];
+        for (key, value) in known {
+            if let Some(value) = value {
+                map.insert(key.to_string(), value);
+            }
+        }
+        map
+    }
+}
+
+/// Fields holding non-negative integers
+const INTEGER_FIELDS: [&str; 7] = [
+    "execution_timeout_ms",
+    "memory_limit",
+    "fuel_limit",
+    "max_input_bytes",
+    "deterministic_timestamp",
+    "stack_size",
+    "instance_pool_size",
+];
+
+/// Check field types up front so errors can name the field at fault
+fn validate(value: &serde_json::Value) -> Result<(), String> {
+    use serde_json::Value;
+
+    let fields = value.as_object().ok_or("config must be a JSON object")?;
+    for (name, field) in fields {
+        let integer = INTEGER_FIELDS.contains(&name.as_str());
+        let valid = match field {
+            Value::Null => integer || is_string_field(name),
+            Value::Number(n) => integer && n.is_u64(),
+            Value::String(s) => !integer || s.trim().parse::<u64>().is_ok(),
+            _ => false,
+        };
+        if !valid {
+            let expected = if integer { "a non-negative integer" } else { "a string" };
+            return Err(format!("config field '{}' must be {}", name, expected));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a known field holds an optional string
+fn is_string_field(name: &str) -> bool {
+    matches!(name, "id" | "wasm_path" | "wasm_base64" | "state_path" | "capabilities" | "method")
+}
+
+/// Deserialize an optional number given either as a JSON number or a string
+fn number_or_string<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let raw = Option::<serde_json::Value>::deserialize(deserializer)?;
+    let text = match raw {
+        None => return Ok(None),
+        Some(serde_json::Value::Number(n)) => n.to_string(),
+        Some(serde_json::Value::String(s)) => s,
+        Some(other) => {
+            return Err(serde::de::Error::custom(format!("expected a number, got {}", other)));
+        }
+    };
+    text.trim().parse().map(Some).map_err(|e| {
+        serde::de::Error::custom(format!("invalid number {:?}: {}", text, e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_is_an_optional_string() {
+        assert_eq!(AgentConfig::from_json(r#"{"method": "summarize"}"#).unwrap().method.as_deref(), Some("summarize"));
+        assert_eq!(AgentConfig::from_json(r#"{"method": null}"#).unwrap().method, None);
+        assert_eq!(
+            AgentConfig::from_json(r#"{"method": 5}"#).unwrap_err(),
+            "config field 'method' must be a string"
+        );
+    }
+
+    #[test]
+    fn malformed_fields_are_named_in_errors() {
+        let cases = [
+            (r#"{"execution_timeout_ms": "soon"}"#, "config field 'execution_timeout_ms' must be a non-negative integer"),
+            (r#"{"memory_limit": -1}"#, "config field 'memory_limit' must be a non-negative integer"),
+            (r#"{"fuel_limit": 1.5}"#, "config field 'fuel_limit' must be a non-negative integer"),
+            (r#"{"wasm_path": {"file": "a.wasm"}}"#, "config field 'wasm_path' must be a string"),
+            (r#"{"team": ["blue"]}"#, "config field 'team' must be a string"),
+            (r#"["not", "an", "object"]"#, "config must be a JSON object"),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(AgentConfig::from_json(json).unwrap_err(), expected, "{}", json);
+        }
+        assert_eq!(AgentConfig::from_json(r#"{"stack_size": " 65536 "}"#).unwrap().stack_size, Some(65536));
+    }
+}
@@ -0,0 +1,44 @@
+//! Registry of Rust handlers for custom agents
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::engine::agent::{AgentError, ExecutionContext};
+
+/// Behavior attached to custom agents by name
+///
+/// A custom agent names its handler with the `custom_handler` config key. If
+/// the agent also has a WASM module, the handler receives the module's output
+/// as its input; otherwise it receives the agent's input directly.
+pub trait CustomAgentHandler: Send + Sync {
+    /// Process the input in `context`, returning the agent's output
+    fn handle(&self, context: &mut ExecutionContext) -> Result<Vec<u8>, AgentError>;
+}
+
+/// Handlers registered by name
+fn handlers() -> &'static RwLock<HashMap<String, Arc<dyn CustomAgentHandler>>> {
+    static HANDLERS: OnceLock<RwLock<HashMap<String, Arc<dyn CustomAgentHandler>>>> = OnceLock::new();
+    HANDLERS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Register a handler under `name`, replacing any previous one
+///
+/// Agents already created keep the handler they were created with.
+pub fn register(name: &str, handler: Arc<dyn CustomAgentHandler>) {
+    if let Ok(mut handlers) = handlers().write() {
+        handlers.insert(name.to_string(), handler);
+    }
+}
+
+/// Remove the handler registered under `name`, returning whether one existed
+pub fn unregister(name: &str) -> bool {
+    handlers()
+        .write()
+        .map(|mut handlers| handlers.remove(name).is_some())
+        .unwrap_or(false)
+}
+
+/// Look up the handler registered under `name`
+pub fn lookup(name: &str) -> Option<Arc<dyn CustomAgentHandler>> {
+    handlers().read().ok()?.get(name).cloned()
+}
@@ -0,0 +1,135 @@
+//! Per-type processing around agent execution
+
+use std::sync::Mutex;
+
+use crate::engine::agent::{Agent, AgentError, AgentType, ExecutionContext};
+use crate::state::core::StateStore;
+use crate::verifier::proof::ExecutionProof;
+
+/// State key prefix under which analyzer agents keep their statistics
+pub const ANALYZER_STATS_PREFIX: &str = "korra.stats.";
+
+/// Hooks run around every execution of an agent, chosen by its type
+///
+/// All hooks default to doing nothing, so types without special handling
+/// behave exactly like a plain sandbox execution.
+pub trait AgentTypeHooks: Send + Sync {
+    /// Inspect the context before the sandbox runs; an error aborts execution
+    fn before(&self, _context: &mut ExecutionContext) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Post-process the output; `context.input` is still the original input
+    fn after(&self, _context: &mut ExecutionContext, output: Vec<u8>) -> Result<Vec<u8>, AgentError> {
+        Ok(output)
+    }
+
+    /// Inspect the proof generated for an execution before it is recorded
+    fn on_proof(&self, _context: &ExecutionContext, _output: &[u8], _proof: &ExecutionProof) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Attach a sub-agent; only coordinators accept them
+    fn add_sub_agent(&self, _agent: Agent) -> Result<(), AgentError> {
+        Err(AgentError::InvalidInput("Only coordinator agents accept sub-agents".to_string()))
+    }
+}
+
+/// Hooks for the given agent type
+pub fn hooks_for(agent_type: AgentType) -> Box<dyn AgentTypeHooks> {
+    match agent_type {
+        AgentType::Analyzer => Box::new(AnalyzerHooks),
+        AgentType::Validator => Box::new(ValidatorHooks),
+        AgentType::Coordinator => Box::new(CoordinatorHooks::default()),
+        AgentType::Transformer | AgentType::Custom => Box::new(DefaultHooks),
+    }
+}
+
+/// No extra processing
+pub struct DefaultHooks;
+
+impl AgentTypeHooks for DefaultHooks {}
+
+/// Counts executions and bytes processed into the agent's state, leaving output untouched
+///
+/// Counters are little-endian `u64` values under `ANALYZER_STATS_PREFIX`:
+/// `executions`, `input_bytes`, and `output_bytes`.
+pub struct AnalyzerHooks;
+
+impl AnalyzerHooks {
+    fn bump(store: &mut StateStore, name: &str, by: u64) -> Result<(), AgentError> {
+        let key = format!("{}{}", ANALYZER_STATS_PREFIX, name);
+        let current = store.get(&key)
+            .and_then(|v| <[u8; 8]>::try_from(v.as_slice()).ok())
+            .map_or(0, u64::from_le_bytes);
+        store.set(&key, &current.saturating_add(by).to_le_bytes())
+            .map_err(|e| AgentError::StateError(format!("Failed to record stats: {}", e)))
+    }
+}
+
+impl AgentTypeHooks for AnalyzerHooks {
+    fn after(&self, context: &mut ExecutionContext, output: Vec<u8>) -> Result<Vec<u8>, AgentError> {
+        let mut store = context.state.lock()
+            .map_err(|e| AgentError::StateError(format!("Failed to lock state: {}", e)))?;
+        Self::bump(&mut store, "executions", 1)?;
+        Self::bump(&mut store, "input_bytes", context.input.len() as u64)?;
+        Self::bump(&mut store, "output_bytes", output.len() as u64)?;
+        Ok(output)
+    }
+}
+
+/// Verifies every execution proof as soon as it is generated
+pub struct ValidatorHooks;
+
+impl AgentTypeHooks for ValidatorHooks {
+    fn on_proof(&self, context: &ExecutionContext, output: &[u8], proof: &ExecutionProof) -> Result<(), AgentError> {
+        let valid = match context.proof_hasher {
+            Some(hasher) => proof.verify_with_hasher(hasher, context.agent_id, context.input, output),
+            None => proof.verify(context.agent_id, context.input, output),
+        };
+        if !valid {
+            return Err(AgentError::ExecutionError("Execution proof failed verification".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Fans the output out to sub-agents and collects their outputs
+///
+/// With sub-agents attached, the coordinator's output is each sub-agent's
+/// output in order, each prefixed with its little-endian `u32` length.
+/// Without any, the output passes through unchanged.
+#[derive(Default)]
+pub struct CoordinatorHooks {
+    sub_agents: Mutex<Vec<Agent>>,
+}
+
+impl AgentTypeHooks for CoordinatorHooks {
+    fn after(&self, _context: &mut ExecutionContext, output: Vec<u8>) -> Result<Vec<u8>, AgentError> {
+        let mut sub_agents = self.sub_agents.lock()
+            .map_err(|e| AgentError::StateError(format!("Failed to lock sub-agents: {}", e)))?;
+        if sub_agents.is_empty() {
+            return Ok(output);
+        }
+
+        let mut combined = Vec::new();
+        for agent in sub_agents.iter_mut() {
+            let result = agent.execute(&output).map_err(|e| {
+                AgentError::ExecutionError(format!("Sub-agent {} failed: {}", agent.id(), e))
+            })?;
+            let len = u32::try_from(result.len()).map_err(|_| {
+                AgentError::ExecutionError(format!("Sub-agent {} output too large", agent.id()))
+            })?;
+            combined.extend_from_slice(&len.to_le_bytes());
+            combined.extend_from_slice(&result);
+        }
+        Ok(combined)
+    }
+
+    fn add_sub_agent(&self, agent: Agent) -> Result<(), AgentError> {
+        self.sub_agents.lock()
+            .map_err(|e| AgentError::StateError(format!("Failed to lock sub-agents: {}", e)))?
+            .push(agent);
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+//! Execution metrics for agents
+
+use std::time::Duration;
+
+use crate::sandbox::wasm_host::HostFuel;
+
+/// Measurements of one or more agent executions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionMetrics {
+    /// Number of executions measured
+    pub executions: u64,
+    /// Wall-clock time spent executing, including proof generation
+    pub duration: Duration,
+    /// Total input bytes
+    pub input_size: usize,
+    /// Total output bytes
+    pub output_size: usize,
+    /// Fuel units burned, by guest code and host calls together
+    pub fuel_consumed: u64,
+    /// Part of `fuel_consumed` charged by host function calls, per function
+    pub host_fuel: HostFuel,
+    /// Largest guest memory size reached, in bytes
+    pub peak_memory: usize,
+}
+
+impl ExecutionMetrics {
+    /// Fold another measurement into this one
+    ///
+    /// Counts, durations, sizes, and fuel are summed; peak memory keeps the maximum.
+    pub fn accumulate(&mut self, other: &ExecutionMetrics) {
+        self.executions += other.executions;
+        self.duration += other.duration;
+        self.input_size += other.input_size;
+        self.output_size += other.output_size;
+        self.fuel_consumed = self.fuel_consumed.saturating_add(other.fuel_consumed);
+        self.host_fuel.accumulate(&other.host_fuel);
+        self.peak_memory = self.peak_memory.max(other.peak_memory);
+    }
+
+    /// Fuel burned by guest instructions alone
+    pub fn guest_fuel(&self) -> u64 {
+        self.fuel_consumed.saturating_sub(self.host_fuel.total())
+    }
+}
@@ -0,0 +1,20 @@
+//! Interceptors run around agent execution
+
+use crate::engine::agent::AgentError;
+
+/// Interceptor installed on an agent with `Agent::add_middleware`
+///
+/// Middleware runs in registration order: every `before` hook runs before the
+/// sandbox, and every `after` hook runs on the output before its proof is
+/// generated, so the proof covers what the caller receives.
+pub trait AgentMiddleware: Send + Sync {
+    /// Inspect the input; an error stops execution before the sandbox runs
+    fn before(&self, _input: &[u8]) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Inspect or transform the output
+    fn after(&self, output: Vec<u8>) -> Result<Vec<u8>, AgentError> {
+        Ok(output)
+    }
+}
@@ -0,0 +1,9 @@
+//! Agent engine
+
+pub mod agent;
+pub mod config;
+pub mod custom;
+pub mod hooks;
+pub mod metrics;
+pub mod middleware;
+pub mod retry;
@@ -0,0 +1,91 @@
+//! Retrying sandbox executions that fail for transient reasons
+
+use std::time::Duration;
+
+use crate::sandbox::wasm_host::WasmHostError;
+
+bitflags::bitflags! {
+    /// Sandbox failures a `RetryPolicy` retries
+    ///
+    /// Traps, link errors, cancellation, and other execution errors depend only
+    /// on the module and input, so no flag covers them and they are never
+    /// retried.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct RetryOn: u8 {
+        /// Creating the guest instance failed, e.g. under memory pressure
+        const INSTANTIATION = 1 << 0;
+        /// The guest hit the memory limit
+        const MEMORY = 1 << 1;
+        /// The guest ran past the execution timeout
+        const TIMEOUT = 1 << 2;
+    }
+}
+
+/// How an agent retries sandbox executions that fail transiently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Most attempts per execution, the first included
+    pub max_attempts: u32,
+    /// Wait before the first retry; each later retry waits twice as long
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts
+    pub max_backoff: Duration,
+    /// Failures worth retrying
+    pub retry_on: RetryOn,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            retry_on: RetryOn::INSTANTIATION,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a sandbox error is one this policy retries
+    pub(crate) fn retries(&self, error: &WasmHostError) -> bool {
+        match error {
+            WasmHostError::InstantiationError(_) => self.retry_on.contains(RetryOn::INSTANTIATION),
+            WasmHostError::MemoryError(_) => self.retry_on.contains(RetryOn::MEMORY),
+            WasmHostError::ExecutionError(msg) if msg == "timeout" => self.retry_on.contains(RetryOn::TIMEOUT),
+            _ => false,
+        }
+    }
+
+    /// Wait before retry number `retry`, counting from 1
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::wasm_host::TrapCode;
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        let policy = RetryPolicy { retry_on: RetryOn::all(), ..RetryPolicy::default() };
+
+        assert!(policy.retries(&WasmHostError::InstantiationError("pool exhausted".into())));
+        assert!(policy.retries(&WasmHostError::MemoryError("limit".into())));
+        assert!(policy.retries(&WasmHostError::ExecutionError("timeout".into())));
+        assert!(!policy.retries(&WasmHostError::LinkError("missing export".into())));
+        assert!(!policy.retries(&WasmHostError::ExecutionError("cancelled".into())));
+        assert!(!policy.retries(&WasmHostError::Trap(TrapCode::Unreachable, "abort".into())));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff(2), Duration::from_millis(20));
+        assert_eq!(policy.backoff(8), Duration::from_secs(1));
+    }
+}
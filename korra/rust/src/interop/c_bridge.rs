@@ -1,11 +1,17 @@
 //! FFI layer to C thread system
 
+use std::error::Error;
 use std::ffi::{c_void, CStr, CString};
-use std::os::raw::{c_char, c_int};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::raw::c_char;
+use std::path::Path;
 use std::slice;
 use std::ptr;
+use std::sync::{Arc, Mutex};
 
-use crate::engine::agent::Agent;
+use crate::engine::agent::{Agent, AgentError};
 
 // Function to register Rust callbacks with C
 pub fn register_callbacks() {
@@ -45,7 +51,12 @@ pub fn free_c_str(c_str: *mut c_char) {
     }
 }
 
-// Function to convert C byte array to Rust slice
+/// Function to convert C byte array to Rust slice
+///
+/// # Safety
+///
+/// `bytes` must be null or point to at least `len` readable bytes that
+/// outlive the returned slice.
 pub unsafe fn c_bytes_to_slice<'a>(bytes: *const u8, len: usize) -> &'a [u8] {
     if bytes.is_null() {
         &[]
@@ -54,28 +65,184 @@ pub unsafe fn c_bytes_to_slice<'a>(bytes: *const u8, len: usize) -> &'a [u8] {
     }
 }
 
-// Function to allocate memory for C
+/// Allocate a zeroed buffer to hand to C
+///
+/// The buffer must be released with `free_for_c` and the same `size`, never
+/// with `free` or the C allocator. Returns null when `size` is zero.
 pub fn alloc_for_c(size: usize) -> *mut u8 {
-    let mut vec = Vec::with_capacity(size);
-    vec.resize(size, 0);
+    if size == 0 {
+        return ptr::null_mut();
+    }
     
-    let ptr = vec.as_mut_ptr();
-    std::mem::forget(vec);
+    // A boxed slice has no spare capacity, so `size` alone describes the allocation
+    Box::into_raw(vec![0u8; size].into_boxed_slice()) as *mut u8
+}
+
+/// Free a buffer returned by `alloc_for_c`
+///
+/// # Safety
+///
+/// `ptr` must be null or a buffer from `alloc_for_c` that has not been freed,
+/// and `size` must be the size it was allocated with.
+pub unsafe fn free_for_c(ptr: *mut u8, size: usize) {
+    if ptr.is_null() || size == 0 {
+        return;
+    }
     
-    ptr
+    drop(Vec::from_raw_parts(ptr, size, size));
+}
+
+/// Why an agent could not be built from a config file
+#[derive(Debug)]
+pub enum ConfigFileError {
+    /// The config file could not be read
+    Io(io::Error),
+    /// The config was read but the agent could not be created from it
+    Agent(AgentError),
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFileError::Io(e) => write!(f, "Failed to read config file: {}", e),
+            ConfigFileError::Agent(e) => write!(f, "Failed to create agent: {}", e),
+        }
+    }
+}
+
+impl Error for ConfigFileError {}
+
+/// Create an agent of `agent_type` from the JSON config file at `path`
+pub fn agent_from_config_file(agent_type: &str, path: impl AsRef<Path>) -> Result<Agent, ConfigFileError> {
+    let config = fs::read_to_string(path).map_err(ConfigFileError::Io)?;
+    Agent::new(agent_type, &config).map_err(ConfigFileError::Agent)
+}
+
+/// Tag stored at the start of every live agent handle ("KORRAAGT")
+const AGENT_HANDLE_MAGIC: u64 = 0x4B4F_5252_4141_4754;
+
+/// Boxed agent behind an opaque C handle
+///
+/// The magic sits at offset zero so most stray pointers are rejected before
+/// the agent itself is touched. The agent is shared so asynchronous
+/// executions keep it alive, and locked so executions on one handle run one
+/// at a time.
+#[repr(C)]
+struct AgentHandle {
+    magic: u64,
+    agent: Arc<Mutex<Agent>>,
 }
 
 // Function to convert Agent to C handle
 pub fn agent_to_handle(agent: Agent) -> *mut c_void {
-    let boxed = Box::new(agent);
+    let boxed = Box::new(AgentHandle {
+        magic: AGENT_HANDLE_MAGIC,
+        agent: Arc::new(Mutex::new(agent)),
+    });
     Box::into_raw(boxed) as *mut c_void
 }
 
-// Function to convert C handle to Agent
-pub unsafe fn handle_to_agent(handle: *mut c_void) -> Option<&'static mut Agent> {
+/// Function to convert C handle to Agent
+///
+/// Returns `None` for null handles and for pointers that do not carry the
+/// agent handle magic. The check is a best-effort debugging aid: it catches
+/// many unrelated pointers, but cannot make a destroyed handle safe to pass.
+///
+/// # Safety
+///
+/// `handle` must be null or point to at least 8 readable bytes.
+pub unsafe fn handle_to_agent(handle: *mut c_void) -> Option<Arc<Mutex<Agent>>> {
     if handle.is_null() {
-        None
-    } else {
-        Some(&mut *(handle as *mut Agent))
+        return None;
+    }
+
+    let handle = handle as *mut AgentHandle;
+    if ptr::read_volatile(ptr::addr_of!((*handle).magic)) != AGENT_HANDLE_MAGIC {
+        return None;
+    }
+
+    Some((*handle).agent.clone())
+}
+
+/// Run `f` with exclusive access to the agent behind a C handle
+///
+/// The borrow lasts only for the call, under the agent's lock, so no two
+/// callers ever hold `&mut Agent` at once. Returns `None` for null or invalid
+/// handles and for agents poisoned by a panic in an earlier call.
+///
+/// # Safety
+///
+/// `handle` must be null or point to at least 8 readable bytes.
+pub unsafe fn with_agent<R>(handle: *mut c_void, f: impl FnOnce(&mut Agent) -> R) -> Option<R> {
+    let agent = handle_to_agent(handle)?;
+    let mut agent = agent.lock().ok()?;
+    Some(f(&mut agent))
+}
+
+/// Destroy an agent handle, returning whether it was valid
+///
+/// The magic is zeroed before the memory is released, which often lets a
+/// second destroy be caught while debugging. This is best effort only: once
+/// freed the memory may be reused, so passing the handle again is undefined
+/// behavior. Asynchronous executions still in flight keep the agent alive
+/// until they complete.
+///
+/// # Safety
+///
+/// `handle` must be null or point to at least 8 readable bytes, and must not
+/// be used again after this returns.
+pub unsafe fn destroy_handle(handle: *mut c_void) -> bool {
+    if handle_to_agent(handle).is_none() {
+        return false;
+    }
+
+    let handle = handle as *mut AgentHandle;
+    ptr::write_volatile(ptr::addr_of_mut!((*handle).magic), 0);
+    drop(Box::from_raw(handle));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine as _, engine::general_purpose};
+
+    use super::*;
+
+    #[test]
+    fn buffers_round_trip_through_alloc_and_free() {
+        let ptr = alloc_for_c(16);
+        assert!(!ptr.is_null());
+
+        let buffer = unsafe { slice::from_raw_parts_mut(ptr, 16) };
+        assert!(buffer.iter().all(|&byte| byte == 0));
+        buffer.copy_from_slice(b"sixteen bytes!!!");
+
+        unsafe { free_for_c(ptr, 16) };
+    }
+
+    #[test]
+    fn empty_buffers_are_null() {
+        let ptr = alloc_for_c(0);
+        assert!(ptr.is_null());
+        unsafe { free_for_c(ptr, 0) };
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn scoped_agent_access_is_sequential_and_rejects_null() {
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param i32 i32) (result i64) (i64.const 0)))"#;
+        let config = format!(r#"{{"id": "scoped", "wasm_base64": "{}"}}"#, general_purpose::STANDARD.encode(wat));
+        let handle = agent_to_handle(Agent::new("custom", &config).unwrap());
+
+        let id = unsafe { with_agent(handle, |agent| agent.id().to_string()) };
+        assert_eq!(id.as_deref(), Some("scoped"));
+        unsafe { with_agent(handle, |agent| agent.set_proof_label("region", "eu")) }.unwrap();
+        let region = unsafe { with_agent(handle, |agent| agent.proof_labels().get("region").cloned()) };
+        assert_eq!(region, Some(Some("eu".to_string())));
+
+        assert!(unsafe { with_agent(ptr::null_mut(), |_| ()) }.is_none());
+        assert!(unsafe { destroy_handle(handle) });
+    }
+}
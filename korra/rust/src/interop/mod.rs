@@ -0,0 +1,4 @@
+//! Interop layer with the C runtime
+
+pub mod c_bridge;
+pub mod worker_pool;
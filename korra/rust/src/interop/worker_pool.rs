@@ -0,0 +1,54 @@
+//! Worker threads for asynchronous agent execution
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+/// Unit of work run on a pool thread
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Upper bound on pool threads, since each execution already owns a sandbox
+const MAX_WORKERS: usize = 4;
+
+/// Queue feeding the pool, spawning the workers on first use
+fn queue() -> &'static Sender<Job> {
+    static QUEUE: OnceLock<Sender<Job>> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(MAX_WORKERS);
+        for index in 0..workers {
+            let receiver = receiver.clone();
+            // A worker that fails to spawn just leaves the pool smaller
+            let _ = thread::Builder::new()
+                .name(format!("korra-worker-{}", index))
+                .spawn(move || run_worker(receiver));
+        }
+
+        sender
+    })
+}
+
+/// Pull jobs off the shared queue until it is closed
+fn run_worker(receiver: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        // Release the lock before running the job so other workers can dequeue
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Queue a job on the pool, returning false if it could not be queued
+pub fn submit(job: Job) -> bool {
+    queue().send(job).is_ok()
+}
@@ -3,10 +3,19 @@
 //! This crate provides the secure agent execution environment,
 //! WASM sandbox, and state management for KORRA.
 
+// The FFI entry points take raw pointers from C and null-check them
+// themselves, so they are deliberately not marked `unsafe`.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::cell::RefCell;
 use std::ffi::{c_void, CStr, CString};
 use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
 use std::slice;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub mod engine;
 pub mod sandbox;
@@ -15,46 +24,186 @@ pub mod state;
 pub mod interop;
 pub mod validator;
 
+/// Status codes returned across the FFI boundary
+///
+/// The integer values are part of the C ABI and mirror `korra_status_t` in
+/// `c/include/rust_glue.h`; keep both in sync when adding codes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KorraStatus {
+    /// The call succeeded
+    Ok = 0,
+    /// A required pointer argument was null
+    NullPointer = -1,
+    /// A string argument was not valid UTF-8
+    InvalidUtf8 = -2,
+    /// The agent could not be created or was misconfigured
+    InitFailed = -3,
+    /// The agent failed while executing
+    ExecutionFailed = -4,
+    /// The sandbox execution timeout elapsed
+    Timeout = -5,
+    /// The sandbox memory limit, the state quota, or an output allocation was exhausted
+    OutOfMemory = -6,
+    /// The handle was not a live agent handle
+    InvalidHandle = -7,
+    /// The requested state key or config file does not exist
+    NotFound = -8,
+    /// The caller-provided output buffer is smaller than the output
+    BufferTooSmall = -9,
+    /// The guest executed `unreachable`
+    TrapUnreachable = -10,
+    /// The guest accessed memory out of bounds
+    TrapMemoryOutOfBounds = -11,
+    /// The guest divided an integer by zero
+    TrapDivisionByZero = -12,
+    /// The guest overflowed an integer operation or conversion
+    TrapIntegerOverflow = -13,
+    /// The guest exceeded its stack size limit
+    TrapStackOverflow = -14,
+    /// The guest trapped for another reason, including a failed host call
+    TrapOther = -15,
+    /// Another call was already using the agent behind the handle
+    Busy = -16,
+    /// The input exceeded the agent's `max_input_bytes`
+    InputTooLarge = -17,
+}
+
+impl From<sandbox::wasm_host::TrapCode> for KorraStatus {
+    fn from(code: sandbox::wasm_host::TrapCode) -> Self {
+        use sandbox::wasm_host::TrapCode;
+
+        match code {
+            TrapCode::Unreachable => KorraStatus::TrapUnreachable,
+            TrapCode::MemoryOutOfBounds => KorraStatus::TrapMemoryOutOfBounds,
+            TrapCode::IntegerDivisionByZero => KorraStatus::TrapDivisionByZero,
+            TrapCode::IntegerOverflow | TrapCode::BadConversionToInteger => KorraStatus::TrapIntegerOverflow,
+            TrapCode::StackOverflow => KorraStatus::TrapStackOverflow,
+            TrapCode::BadIndirectCall | TrapCode::HostError | TrapCode::Other => KorraStatus::TrapOther,
+        }
+    }
+}
+
+impl From<&engine::agent::AgentError> for KorraStatus {
+    fn from(error: &engine::agent::AgentError) -> Self {
+        use engine::agent::AgentError;
+
+        match error {
+            AgentError::InitError(_) | AgentError::SandboxError(_) => KorraStatus::InitFailed,
+            AgentError::InvalidInput(_) => KorraStatus::InvalidUtf8,
+            AgentError::InputTooLarge { .. } => KorraStatus::InputTooLarge,
+            AgentError::ExecutionError(_) | AgentError::StateError(_) => KorraStatus::ExecutionFailed,
+            AgentError::Timeout(_) => KorraStatus::Timeout,
+            AgentError::OutOfMemory(_) => KorraStatus::OutOfMemory,
+            AgentError::Trap(code, _) => KorraStatus::from(*code),
+            AgentError::BatchFailed { error, .. } => KorraStatus::from(error.as_ref()),
+        }
+    }
+}
+
+impl From<&interop::c_bridge::ConfigFileError> for KorraStatus {
+    fn from(error: &interop::c_bridge::ConfigFileError) -> Self {
+        use interop::c_bridge::ConfigFileError;
+
+        match error {
+            ConfigFileError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => KorraStatus::NotFound,
+            ConfigFileError::Io(_) => KorraStatus::InitFailed,
+            ConfigFileError::Agent(e) => KorraStatus::from(e),
+        }
+    }
+}
+
 // FFI exports for C interop
 #[no_mangle]
 pub extern "C" fn rust_agent_create(
     agent_type: *const c_char,
     config: *const c_char
 ) -> *mut c_void {
-    // Safety checks
-    if agent_type.is_null() || config.is_null() {
-        log_error("Null pointer passed to rust_agent_create");
-        return ptr::null_mut();
-    }
+    guard_ffi("rust_agent_create", ptr::null_mut(), || {
+        clear_last_error();
+
+        // Safety checks
+        if agent_type.is_null() || config.is_null() {
+            report_error("Null pointer passed to rust_agent_create");
+            return ptr::null_mut();
+        }
     
-    // Convert C strings to Rust strings
-    let agent_type_str = unsafe { CStr::from_ptr(agent_type) }.to_str();
-    let config_str = unsafe { CStr::from_ptr(config) }.to_str();
+        // Convert C strings to Rust strings
+        let agent_type_str = unsafe { CStr::from_ptr(agent_type) }.to_str();
+        let config_str = unsafe { CStr::from_ptr(config) }.to_str();
     
-    if agent_type_str.is_err() || config_str.is_err() {
-        log_error("Invalid UTF-8 in agent_type or config");
-        return ptr::null_mut();
-    }
+        if agent_type_str.is_err() || config_str.is_err() {
+            report_error("Invalid UTF-8 in agent_type or config");
+            return ptr::null_mut();
+        }
     
-    let agent_type_str = agent_type_str.unwrap();
-    let config_str = config_str.unwrap();
+        let agent_type_str = agent_type_str.unwrap();
+        let config_str = config_str.unwrap();
     
-    log_info(&format!("Creating agent of type '{}' with config", agent_type_str));
+        log_info(&format!("Creating agent of type '{}' with config", agent_type_str));
     
-    // Create agent instance
-    match engine::agent::Agent::new(agent_type_str, config_str) {
-        Ok(agent) => {
-            // Box the agent and return a raw pointer
-            let boxed = Box::new(agent);
-            Box::into_raw(boxed) as *mut c_void
-        }
-        Err(e) => {
-            log_error(&format!("Failed to create agent: {}", e));
-            ptr::null_mut()
+        // Create agent instance
+        match engine::agent::Agent::new(agent_type_str, config_str) {
+            Ok(agent) => interop::c_bridge::agent_to_handle(agent),
+            Err(e) => {
+                report_error(&format!("Failed to create agent: {}", e));
+                ptr::null_mut()
+            }
         }
-    }
+    })
 }
 
+/// Create an agent from a JSON config file on disk
+///
+/// On success `*out_handle` receives the new handle; on failure it is set to
+/// null. A missing file returns `NotFound`, and an unreadable file or invalid
+/// config returns `InitFailed`.
+#[no_mangle]
+pub extern "C" fn rust_agent_create_from_file(
+    agent_type: *const c_char,
+    path: *const c_char,
+    out_handle: *mut *mut c_void
+) -> KorraStatus {
+    guard_ffi("rust_agent_create_from_file", KorraStatus::InitFailed, || {
+        clear_last_error();
+
+        if agent_type.is_null() || path.is_null() || out_handle.is_null() {
+            report_error("Null pointer passed to rust_agent_create_from_file");
+            return KorraStatus::NullPointer;
+        }
+        unsafe { *out_handle = ptr::null_mut() };
+
+        let (agent_type, path) = match (
+            unsafe { CStr::from_ptr(agent_type) }.to_str(),
+            unsafe { CStr::from_ptr(path) }.to_str(),
+        ) {
+            (Ok(agent_type), Ok(path)) => (agent_type, path),
+            _ => {
+                report_error("Invalid UTF-8 in agent_type or path");
+                return KorraStatus::InvalidUtf8;
+            }
+        };
+
+        log_info(&format!("Creating agent of type '{}' from config file '{}'", agent_type, path));
+
+        match interop::c_bridge::agent_from_config_file(agent_type, path) {
+            Ok(agent) => {
+                unsafe { *out_handle = interop::c_bridge::agent_to_handle(agent) };
+                KorraStatus::Ok
+            }
+            Err(e) => {
+                report_error(&e.to_string());
+                KorraStatus::from(&e)
+            }
+        }
+    })
+}
+
+/// Execute an agent, returning its output in a buffer from `c_alloc_callback`
+///
+/// Returns `Busy` instead of waiting when another call is already using the
+/// handle's agent, so concurrent executions on one handle never share it.
+/// Release the output with `rust_agent_free_output`.
 #[no_mangle]
 pub extern "C" fn rust_agent_execute(
     handle: *mut c_void,
@@ -62,65 +211,665 @@ pub extern "C" fn rust_agent_execute(
     input_size: usize,
     output: *mut *mut u8,
     output_size: *mut usize
-) -> c_int {
-    // Safety checks
-    if handle.is_null() || (input.is_null() && input_size > 0) || output.is_null() || output_size.is_null() {
-        log_error("Null pointer passed to rust_agent_execute");
-        return -1;
-    }
-    
-    // Get agent from handle
-    let agent = unsafe { &mut *(handle as *mut engine::agent::Agent) };
+) -> KorraStatus {
+    guard_ffi("rust_agent_execute", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        // Safety checks
+        if handle.is_null() || (input.is_null() && input_size > 0) || output.is_null() || output_size.is_null() {
+            report_error("Null pointer passed to rust_agent_execute");
+            return KorraStatus::NullPointer;
+        }
     
-    // Convert input to Rust slice
-    let input_slice = if input.is_null() {
-        &[]
-    } else {
-        unsafe { slice::from_raw_parts(input, input_size) }
-    };
+        // Get agent from handle
+        let agent = match unsafe { interop::c_bridge::handle_to_agent(handle) } {
+            Some(agent) => agent,
+            None => {
+                report_error("Invalid agent handle passed to rust_agent_execute");
+                return KorraStatus::InvalidHandle;
+            }
+        };
     
-    log_debug(&format!("Executing agent with {} bytes of input", input_size));
+        // Convert input to Rust slice
+        let input_slice = if input.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(input, input_size) }
+        };
     
-    // Execute agent
-    match agent.execute(input_slice) {
-        Ok(result) => {
-            // Allocate memory for output
-            let result_len = result.len();
-            let result_ptr = unsafe { alloc(result_len) };
-            
-            if result_ptr.is_null() {
-                log_error("Failed to allocate memory for agent output");
-                return -1;
+        let result = try_lock_agent(&agent).and_then(|mut agent| execute_to_output(&mut agent, input_slice));
+        match result {
+            Ok((result_ptr, result_len)) => {
+                unsafe {
+                    *output = result_ptr;
+                    *output_size = result_len;
+                }
+                KorraStatus::Ok
             }
-            
-            // Copy result to output buffer
-            unsafe {
-                ptr::copy_nonoverlapping(result.as_ptr(), result_ptr, result_len);
-                *output = result_ptr;
-                *output_size = result_len;
+            Err(status) => status,
+        }
+    })
+}
+
+/// Execute an agent, writing the output into a caller-provided buffer
+///
+/// Nothing is allocated, so hosts can reuse one buffer across calls. On
+/// success `*out_len` is the output size. If the output is larger than
+/// `out_cap`, `*out_len` is set to the size needed and `BufferTooSmall` is
+/// returned; the execution has still run, including its state changes and
+/// proof. `out_buf` may be null when `out_cap` is zero.
+#[no_mangle]
+pub extern "C" fn rust_agent_execute_into(
+    handle: *mut c_void,
+    input: *const u8,
+    input_size: usize,
+    out_buf: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize
+) -> KorraStatus {
+    guard_ffi("rust_agent_execute_into", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        // Safety checks
+        if handle.is_null()
+            || (input.is_null() && input_size > 0)
+            || (out_buf.is_null() && out_cap > 0)
+            || out_len.is_null()
+        {
+            report_error("Null pointer passed to rust_agent_execute_into");
+            return KorraStatus::NullPointer;
+        }
+
+        let agent = match unsafe { interop::c_bridge::handle_to_agent(handle) } {
+            Some(agent) => agent,
+            None => {
+                report_error("Invalid agent handle passed to rust_agent_execute_into");
+                return KorraStatus::InvalidHandle;
             }
-            
-            0 // Success
+        };
+
+        let input_slice = unsafe { interop::c_bridge::c_bytes_to_slice(input, input_size) };
+        let result = match try_lock_agent(&agent).and_then(|mut agent| execute_locked(&mut agent, input_slice)) {
+            Ok(result) => result,
+            Err(status) => return status,
+        };
+
+        unsafe { *out_len = result.len() };
+        if result.len() > out_cap {
+            report_error(&format!(
+                "Output of {} bytes does not fit in a {} byte buffer", result.len(), out_cap
+            ));
+            return KorraStatus::BufferTooSmall;
         }
-        Err(e) => {
-            log_error(&format!("Agent execution failed: {}", e));
-            -1 // Error
+        if !result.is_empty() {
+            unsafe { ptr::copy_nonoverlapping(result.as_ptr(), out_buf, result.len()) };
         }
+        KorraStatus::Ok
+    })
+}
+
+/// Completion callback for `rust_agent_execute_async`
+///
+/// On success `output` must be released with `rust_agent_free_output`; on
+/// failure it is null and `output_size` is zero.
+pub type AgentCompletionCallback = extern "C" fn(
+    status: KorraStatus,
+    output: *mut u8,
+    output_size: usize,
+    user_data: *mut c_void,
+);
+
+/// Caller context handed back untouched to the completion callback
+struct UserData(*mut c_void);
+
+// The pointer is opaque to Rust and only ever passed back to the caller
+unsafe impl Send for UserData {}
+
+impl UserData {
+    fn into_raw(self) -> *mut c_void {
+        self.0
     }
 }
 
+/// Execute an agent on a worker thread and report the result via a callback
+///
+/// The input is copied before this returns, so the caller may release it
+/// immediately. The callback runs on a worker thread, exactly once per
+/// successfully queued call, and never before this function has returned.
+/// Queued executions wait for any other call on the same handle instead of
+/// failing with `Busy`, and the handle may be destroyed while an execution is
+/// still in flight.
+/// Returns `KorraStatus::Ok` if the execution was queued; otherwise the
+/// callback is not invoked.
 #[no_mangle]
-pub extern "C" fn rust_agent_destroy(handle: *mut c_void) {
-    if handle.is_null() {
-        log_error("Null pointer passed to rust_agent_destroy");
-        return;
+pub extern "C" fn rust_agent_execute_async(
+    handle: *mut c_void,
+    input: *const u8,
+    input_size: usize,
+    callback: Option<AgentCompletionCallback>,
+    user_data: *mut c_void,
+) -> KorraStatus {
+    guard_ffi("rust_agent_execute_async", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        // Safety checks
+        let callback = match callback {
+            Some(cb) if !handle.is_null() && (!input.is_null() || input_size == 0) => cb,
+            _ => {
+                report_error("Null pointer passed to rust_agent_execute_async");
+                return KorraStatus::NullPointer;
+            }
+        };
+
+        let agent = match unsafe { interop::c_bridge::handle_to_agent(handle) } {
+            Some(agent) => agent,
+            None => {
+                report_error("Invalid agent handle passed to rust_agent_execute_async");
+                return KorraStatus::InvalidHandle;
+            }
+        };
+
+        // Copy the input since the caller's buffer may be released once we return
+        let input = unsafe { interop::c_bridge::c_bytes_to_slice(input, input_size) }.to_vec();
+        let user_data = UserData(user_data);
+
+        // Held by this call so the worker cannot report back before it returns
+        let (submitted, wait_for_submit) = mpsc::channel::<()>();
+
+        let job = move || {
+            let (status, output, output_size) = guard_ffi(
+                "rust_agent_execute_async worker",
+                (KorraStatus::ExecutionFailed, ptr::null_mut(), 0),
+                || match lock_agent(&agent).and_then(|mut agent| execute_to_output(&mut agent, &input)) {
+                    Ok((output, output_size)) => (KorraStatus::Ok, output, output_size),
+                    Err(status) => (status, ptr::null_mut(), 0),
+                },
+            );
+
+            let _ = wait_for_submit.recv();
+            callback(status, output, output_size, user_data.into_raw());
+        };
+
+        if !interop::worker_pool::submit(Box::new(job)) {
+            report_error("Failed to queue asynchronous agent execution");
+            return KorraStatus::ExecutionFailed;
+        }
+
+        drop(submitted);
+        KorraStatus::Ok
+    })
+}
+
+/// Run an agent and copy its result into a buffer from `alloc_output`
+fn execute_to_output(
+    agent: &mut engine::agent::Agent,
+    input: &[u8],
+) -> Result<(*mut u8, usize), KorraStatus> {
+    let _log_scope = LogScope::enter(agent.id());
+    let result = execute_locked(agent, input)?;
+
+    // Allocate memory for output
+    let result_len = result.len();
+    let result_ptr = unsafe { alloc_output(result_len) };
+    if result_ptr.is_null() {
+        report_error("Failed to allocate memory for agent output");
+        return Err(KorraStatus::OutOfMemory);
     }
+
+    // Copy result to output buffer
+    unsafe { ptr::copy_nonoverlapping(result.as_ptr(), result_ptr, result_len) };
+
+    Ok((result_ptr, result_len))
+}
+
+/// Lock a handle's agent, waiting for any other call on it to finish
+fn lock_agent(agent: &Mutex<engine::agent::Agent>) -> Result<MutexGuard<'_, engine::agent::Agent>, KorraStatus> {
+    agent.lock().map_err(|_| {
+        report_error("Agent is unusable after a panic during a previous execution");
+        KorraStatus::ExecutionFailed
+    })
+}
+
+/// Lock a handle's agent, failing with `Busy` if another call is using it
+fn try_lock_agent(agent: &Mutex<engine::agent::Agent>) -> Result<MutexGuard<'_, engine::agent::Agent>, KorraStatus> {
+    agent.try_lock().map_err(|e| match e {
+        TryLockError::WouldBlock => {
+            report_error("Agent is already in use by another call on the same handle");
+            KorraStatus::Busy
+        }
+        TryLockError::Poisoned(_) => {
+            report_error("Agent is unusable after a panic during a previous execution");
+            KorraStatus::ExecutionFailed
+        }
+    })
+}
+
+/// Execute a locked agent
+fn execute_locked(agent: &mut engine::agent::Agent, input: &[u8]) -> Result<Vec<u8>, KorraStatus> {
+    let _log_scope = LogScope::enter(agent.id());
+    log_debug(&format!("Executing agent with {} bytes of input", input.len()));
+
+    agent.execute(input).map_err(|e| {
+        report_error(&format!("Agent execution failed: {}", e));
+        KorraStatus::from(&e)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rust_agent_destroy(handle: *mut c_void) {
+    guard_ffi("rust_agent_destroy", (), || {
+        clear_last_error();
+
+        if handle.is_null() {
+            report_error("Null pointer passed to rust_agent_destroy");
+            return;
+        }
     
-    log_debug("Destroying agent");
+        log_debug("Destroying agent");
     
-    // Safely drop the Box
-    unsafe {
-        let _ = Box::from_raw(handle as *mut engine::agent::Agent);
+        // Only drop handles that still carry the agent magic
+        if !unsafe { interop::c_bridge::destroy_handle(handle) } {
+            report_error("Invalid agent handle passed to rust_agent_destroy");
+        }
+    })
+}
+
+/// Free an output buffer returned by `rust_agent_execute`
+///
+/// Every non-null output must be released exactly once through this function,
+/// passing the size that was reported alongside it. Null pointers are ignored.
+#[no_mangle]
+pub extern "C" fn rust_agent_free_output(output: *mut u8, output_size: usize) {
+    guard_ffi("rust_agent_free_output", (), || {
+        unsafe { free_output(output, output_size) };
+    })
+}
+
+/// Shut an agent down ahead of `rust_agent_destroy`
+///
+/// Flushes persistent state, records a final proof available through
+/// `rust_agent_last_proof`, and releases the sandbox. Later executions fail,
+/// and calling this again is a no-op.
+#[no_mangle]
+pub extern "C" fn rust_agent_shutdown(handle: *mut c_void) -> KorraStatus {
+    guard_ffi("rust_agent_shutdown", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        if handle.is_null() {
+            report_error("Null pointer passed to rust_agent_shutdown");
+            return KorraStatus::NullPointer;
+        }
+
+        let agent = match unsafe { interop::c_bridge::handle_to_agent(handle) } {
+            Some(agent) => agent,
+            None => {
+                report_error("Invalid agent handle passed to rust_agent_shutdown");
+                return KorraStatus::InvalidHandle;
+            }
+        };
+        let mut agent = match agent.lock() {
+            Ok(agent) => agent,
+            Err(_) => {
+                report_error("Agent is unusable after a panic during a previous execution");
+                return KorraStatus::ExecutionFailed;
+            }
+        };
+
+        match agent.finalize() {
+            Ok(()) => KorraStatus::Ok,
+            Err(e) => {
+                report_error(&format!("Agent shutdown failed: {}", e));
+                KorraStatus::from(&e)
+            }
+        }
+    })
+}
+
+/// Describe a live agent as a NUL-terminated JSON string
+///
+/// The JSON holds `id`, `agent_type`, `config_keys`, `has_proof`, and
+/// `state_size`. On success `*out` receives a string allocated with
+/// `c_alloc_callback`, which the caller owns.
+#[no_mangle]
+pub extern "C" fn rust_agent_info(handle: *mut c_void, out: *mut *mut c_char) -> KorraStatus {
+    guard_ffi("rust_agent_info", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        if handle.is_null() || out.is_null() {
+            report_error("Null pointer passed to rust_agent_info");
+            return KorraStatus::NullPointer;
+        }
+
+        let agent = match unsafe { interop::c_bridge::handle_to_agent(handle) } {
+            Some(agent) => agent,
+            None => {
+                report_error("Invalid agent handle passed to rust_agent_info");
+                return KorraStatus::InvalidHandle;
+            }
+        };
+        let agent = match agent.lock() {
+            Ok(agent) => agent,
+            Err(_) => {
+                report_error("Agent is unusable after a panic during a previous execution");
+                return KorraStatus::ExecutionFailed;
+            }
+        };
+        let info = match agent.info_json() {
+            Ok(info) => info,
+            Err(e) => {
+                report_error(&format!("Failed to describe agent: {}", e));
+                return KorraStatus::from(&e);
+            }
+        };
+
+        // Copy the JSON plus a NUL terminator into C-owned memory
+        let info_ptr = unsafe { alloc(info.len() + 1) };
+        if info_ptr.is_null() {
+            report_error("Failed to allocate memory for agent info");
+            return KorraStatus::OutOfMemory;
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(info.as_ptr(), info_ptr, info.len());
+            *info_ptr.add(info.len()) = 0;
+            *out = info_ptr as *mut c_char;
+        }
+
+        KorraStatus::Ok
+    })
+}
+
+/// Get the agent's last execution proof as a NUL-terminated JSON string
+///
+/// On success `*out` receives a string allocated with `c_alloc_callback`, which
+/// the caller owns. Returns -1 if the handle is invalid or no proof exists yet.
+#[no_mangle]
+pub extern "C" fn rust_agent_last_proof(handle: *mut c_void, out: *mut *mut c_char) -> c_int {
+    guard_ffi("rust_agent_last_proof", -1, || {
+        clear_last_error();
+
+        if handle.is_null() || out.is_null() {
+            report_error("Null pointer passed to rust_agent_last_proof");
+            return -1;
+        }
+
+        let agent = match unsafe { interop::c_bridge::handle_to_agent(handle) } {
+            Some(agent) => agent,
+            None => {
+                report_error("Invalid agent handle passed to rust_agent_last_proof");
+                return -1;
+            }
+        };
+        let agent = match agent.lock() {
+            Ok(agent) => agent,
+            Err(_) => {
+                report_error("Agent is unusable after a panic during a previous execution");
+                return -1;
+            }
+        };
+        let proof_json = match agent.get_last_proof() {
+            Some(proof) => proof.to_json(),
+            None => {
+                report_error("Agent has no execution proof yet");
+                return -1;
+            }
+        };
+
+        // Copy the JSON plus a NUL terminator into C-owned memory
+        let json_len = proof_json.len();
+        let json_ptr = unsafe { alloc(json_len + 1) };
+        if json_ptr.is_null() {
+            report_error("Failed to allocate memory for execution proof");
+            return -1;
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(proof_json.as_ptr(), json_ptr, json_len);
+            *json_ptr.add(json_len) = 0;
+            *out = json_ptr as *mut c_char;
+        }
+
+        0
+    })
+}
+
+/// Store a value in an agent's state
+///
+/// The value is copied, so the caller keeps ownership of its buffer. Returns
+/// `KorraStatus::OutOfMemory` if the write would exceed the state quota.
+#[no_mangle]
+pub extern "C" fn rust_agent_state_set(
+    handle: *mut c_void,
+    key: *const c_char,
+    value: *const u8,
+    value_len: usize,
+) -> KorraStatus {
+    guard_ffi("rust_agent_state_set", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        if handle.is_null() || key.is_null() || (value.is_null() && value_len > 0) {
+            report_error("Null pointer passed to rust_agent_state_set");
+            return KorraStatus::NullPointer;
+        }
+
+        let key = match unsafe { CStr::from_ptr(key) }.to_str() {
+            Ok(key) => key,
+            Err(_) => {
+                report_error("Invalid UTF-8 in state key");
+                return KorraStatus::InvalidUtf8;
+            }
+        };
+        let value = unsafe { interop::c_bridge::c_bytes_to_slice(value, value_len) };
+
+        let state = match agent_state(handle, "rust_agent_state_set") {
+            Ok(state) => state,
+            Err(status) => return status,
+        };
+        let mut store = match state.lock() {
+            Ok(store) => store,
+            Err(_) => {
+                report_error("Agent state is unusable after a panic");
+                return KorraStatus::ExecutionFailed;
+            }
+        };
+
+        match store.set(key, value) {
+            Ok(()) => KorraStatus::Ok,
+            Err(e) => {
+                report_error(&format!("Failed to set state key '{}': {}", key, e));
+                KorraStatus::OutOfMemory
+            }
+        }
+    })
+}
+
+/// Copy a value out of an agent's state
+///
+/// On success `*out` receives a buffer that must be released with
+/// `rust_agent_free_output`, or null if the value is empty. Returns
+/// `KorraStatus::NotFound` if the key does not exist.
+#[no_mangle]
+pub extern "C" fn rust_agent_state_get(
+    handle: *mut c_void,
+    key: *const c_char,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> KorraStatus {
+    guard_ffi("rust_agent_state_get", KorraStatus::ExecutionFailed, || {
+        clear_last_error();
+
+        if handle.is_null() || key.is_null() || out.is_null() || out_len.is_null() {
+            report_error("Null pointer passed to rust_agent_state_get");
+            return KorraStatus::NullPointer;
+        }
+
+        let key = match unsafe { CStr::from_ptr(key) }.to_str() {
+            Ok(key) => key,
+            Err(_) => {
+                report_error("Invalid UTF-8 in state key");
+                return KorraStatus::InvalidUtf8;
+            }
+        };
+
+        let state = match agent_state(handle, "rust_agent_state_get") {
+            Ok(state) => state,
+            Err(status) => return status,
+        };
+        let value = match state.lock() {
+            Ok(store) => store.get(key),
+            Err(_) => {
+                report_error("Agent state is unusable after a panic");
+                return KorraStatus::ExecutionFailed;
+            }
+        };
+        let value = match value {
+            Some(value) => value,
+            None => {
+                report_error(&format!("State key '{}' not found", key));
+                return KorraStatus::NotFound;
+            }
+        };
+
+        let value_ptr = if value.is_empty() {
+            ptr::null_mut()
+        } else {
+            let value_ptr = unsafe { alloc_output(value.len()) };
+            if value_ptr.is_null() {
+                report_error("Failed to allocate memory for state value");
+                return KorraStatus::OutOfMemory;
+            }
+            unsafe { ptr::copy_nonoverlapping(value.as_ptr(), value_ptr, value.len()) };
+            value_ptr
+        };
+
+        unsafe {
+            *out = value_ptr;
+            *out_len = value.len();
+        }
+        KorraStatus::Ok
+    })
+}
+
+/// Resolve a handle to its agent's state store
+///
+/// Waits for any in-flight execution on the handle, so callers never observe
+/// state halfway through a run.
+fn agent_state(handle: *mut c_void, caller: &str) -> Result<Arc<Mutex<state::core::StateStore>>, KorraStatus> {
+    let agent = unsafe { interop::c_bridge::handle_to_agent(handle) }.ok_or_else(|| {
+        report_error(&format!("Invalid agent handle passed to {}", caller));
+        KorraStatus::InvalidHandle
+    })?;
+    let agent = agent.lock().map_err(|_| {
+        report_error("Agent is unusable after a panic during a previous execution");
+        KorraStatus::ExecutionFailed
+    })?;
+    Ok(agent.state())
+}
+
+/// Set the minimum level of messages passed to `c_log_callback`
+///
+/// Levels match `LOG_LEVEL_*` in `debug.h`; the default is info. Messages
+/// below the threshold are dropped without crossing the FFI boundary.
+#[no_mangle]
+pub extern "C" fn rust_agent_set_log_level(level: c_int) {
+    MIN_LOG_LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Switch log messages between plain text (0, the default) and JSON (non-zero)
+///
+/// JSON messages are objects with `level`, `msg`, `agent_id`, and `ts`
+/// (milliseconds since the Unix epoch) fields; `agent_id` is null outside an
+/// agent execution.
+#[no_mangle]
+pub extern "C" fn rust_agent_set_log_json(enabled: c_int) {
+    LOG_JSON.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// ABI version of the exported FFI surface
+///
+/// Bumped whenever an exported signature or `KorraStatus` value changes; C
+/// hosts compare it against `KORRA_ABI_VERSION` from `rust_glue.h`.
+pub const KORRA_ABI_VERSION: u32 = 7;
+
+/// NUL-terminated engine version, e.g. `0.1.0 (1a2b3c4)`
+static BUILD_VERSION: &str = concat!(env!("KORRA_BUILD_VERSION"), "\0");
+
+/// Get the engine version string
+///
+/// The returned pointer is valid for the lifetime of the process and must not
+/// be freed.
+#[no_mangle]
+pub extern "C" fn rust_agent_version() -> *const c_char {
+    BUILD_VERSION.as_ptr() as *const c_char
+}
+
+/// Get the ABI version of this engine build
+#[no_mangle]
+pub extern "C" fn rust_agent_abi_version() -> u32 {
+    KORRA_ABI_VERSION
+}
+
+/// Copy the last error message on this thread into a caller-provided buffer
+///
+/// At most `buf_len - 1` bytes are copied and the result is always
+/// NUL-terminated. Returns the full message length excluding the terminator,
+/// or 0 if the last call on this thread succeeded. Pass a null buffer to query
+/// the length without copying.
+#[no_mangle]
+pub extern "C" fn rust_agent_last_error(buf: *mut c_char, buf_len: usize) -> usize {
+    guard_ffi("rust_agent_last_error", 0, || {
+        LAST_ERROR.with(|last| {
+            let last = last.borrow();
+            let message = match last.as_deref() {
+                Some(m) => m.as_bytes(),
+                None => return 0,
+            };
+
+            if !buf.is_null() && buf_len > 0 {
+                let copy_len = message.len().min(buf_len - 1);
+                unsafe {
+                    ptr::copy_nonoverlapping(message.as_ptr(), buf as *mut u8, copy_len);
+                    *buf.add(copy_len) = 0;
+                }
+            }
+
+            message.len()
+        })
+    })
+}
+
+thread_local! {
+    /// Message describing the most recent failed FFI call on this thread
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Log an FFI failure and remember it for `rust_agent_last_error`
+fn report_error(message: &str) {
+    log_error(message);
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message.to_string()));
+}
+
+/// Forget the previous failure so stale errors are not reported
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Run an FFI entry point body, turning a panic into the given error value
+///
+/// Unwinding across an `extern "C"` boundary is undefined behavior, so every
+/// exported function routes its body through here.
+fn guard_ffi<T>(name: &str, on_panic: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let detail = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic payload".to_string());
+            report_error(&format!("Panic in {}: {}", name, detail));
+            on_panic
+        }
     }
 }
 
@@ -131,8 +880,15 @@ const LOG_LEVEL_DEBUG: i32 = 0;
 const LOG_LEVEL_INFO: i32 = 1;
 const LOG_LEVEL_WARN: i32 = 2;
 const LOG_LEVEL_ERROR: i32 = 3;
+#[allow(dead_code)]
 const LOG_LEVEL_FATAL: i32 = 4;
 
+/// Messages below this level are dropped before reaching `c_log_callback`
+static MIN_LOG_LEVEL: AtomicI32 = AtomicI32::new(LOG_LEVEL_INFO);
+
+/// Whether log messages are emitted as JSON objects instead of plain text
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
 // External C functions
 extern "C" {
     fn c_log_callback(level: c_int, message: *const c_char);
@@ -142,23 +898,86 @@ extern "C" {
 
 // Helper functions for logging
 fn log_debug(message: &str) {
-    let c_str = CString::new(message).unwrap_or_else(|_| CString::new("Invalid UTF-8 in log message").unwrap());
-    unsafe { c_log_callback(LOG_LEVEL_DEBUG, c_str.as_ptr()) };
+    log_at(LOG_LEVEL_DEBUG, message);
 }
 
 fn log_info(message: &str) {
-    let c_str = CString::new(message).unwrap_or_else(|_| CString::new("Invalid UTF-8 in log message").unwrap());
-    unsafe { c_log_callback(LOG_LEVEL_INFO, c_str.as_ptr()) };
+    log_at(LOG_LEVEL_INFO, message);
 }
 
 fn log_warn(message: &str) {
-    let c_str = CString::new(message).unwrap_or_else(|_| CString::new("Invalid UTF-8 in log message").unwrap());
-    unsafe { c_log_callback(LOG_LEVEL_WARN, c_str.as_ptr()) };
+    log_at(LOG_LEVEL_WARN, message);
 }
 
 fn log_error(message: &str) {
-    let c_str = CString::new(message).unwrap_or_else(|_| CString::new("Invalid UTF-8 in log message").unwrap());
-    unsafe { c_log_callback(LOG_LEVEL_ERROR, c_str.as_ptr()) };
+    log_at(LOG_LEVEL_ERROR, message);
+}
+
+/// Forward a message to the host unless it is below the minimum log level
+fn log_at(level: i32, message: &str) {
+    if level < MIN_LOG_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let line = if LOG_JSON.load(Ordering::Relaxed) {
+        json_log_line(level, message)
+    } else {
+        LOG_AGENT_ID.with(|agent_id| match agent_id.borrow().as_deref() {
+            Some(agent_id) => format!("[agent {}] {}", agent_id, message),
+            None => message.to_string(),
+        })
+    };
+    let c_str = CString::new(line).unwrap_or_else(|_| CString::new("Invalid UTF-8 in log message").unwrap());
+    unsafe { c_log_callback(level, c_str.as_ptr()) };
+}
+
+/// Render a message as a JSON object with its level, agent, and timestamp
+fn json_log_line(level: i32, message: &str) -> String {
+    let level = match level {
+        LOG_LEVEL_DEBUG => "debug",
+        LOG_LEVEL_INFO => "info",
+        LOG_LEVEL_WARN => "warn",
+        LOG_LEVEL_ERROR => "error",
+        _ => "fatal",
+    };
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    LOG_AGENT_ID.with(|agent_id| {
+        serde_json::json!({
+            "level": level,
+            "msg": message,
+            "agent_id": *agent_id.borrow(),
+            "ts": ts,
+        })
+        .to_string()
+    })
+}
+
+thread_local! {
+    /// Agent whose execution is running on this thread, for log context
+    static LOG_AGENT_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Marks log messages on this thread as belonging to an agent until dropped
+///
+/// Plain-text messages are prefixed with `[agent <id>]` and JSON messages
+/// carry it in `agent_id`. Scopes nest, so a coordinator's context is restored once a sub-agent's
+/// execution finishes.
+pub(crate) struct LogScope {
+    previous: Option<String>,
+}
+
+impl LogScope {
+    pub(crate) fn enter(agent_id: &str) -> Self {
+        let previous = LOG_AGENT_ID.with(|current| current.replace(Some(agent_id.to_string())));
+        LogScope { previous }
+    }
+}
+
+impl Drop for LogScope {
+    fn drop(&mut self) {
+        LOG_AGENT_ID.with(|current| *current.borrow_mut() = self.previous.take());
+    }
 }
 
 // Helper functions for memory management
@@ -166,8 +985,483 @@ unsafe fn alloc(size: usize) -> *mut u8 {
     c_alloc_callback(size)
 }
 
+/// Allocate an execution output buffer handed to C
+///
+/// Paired with `free_output`, which `rust_agent_free_output` uses to release it.
+unsafe fn alloc_output(size: usize) -> *mut u8 {
+    log_debug(&format!("Allocating {} byte output buffer", size));
+    alloc(size)
+}
+
+/// Release an execution output buffer allocated by `alloc_output`
+///
+/// # Safety
+///
+/// `ptr` must be null or a buffer from `alloc_output` that has not been freed.
+unsafe fn free_output(ptr: *mut u8, size: usize) {
+    if !ptr.is_null() {
+        log_debug(&format!("Freeing {} byte output buffer", size));
+        free(ptr as *mut c_void);
+    }
+}
+
+/// Release memory through the C allocator
+///
+/// # Safety
+///
+/// `ptr` must be null or have been allocated by `c_alloc_callback`.
 pub unsafe fn free(ptr: *mut c_void) {
     if !ptr.is_null() {
         c_free_callback(ptr);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    use base64::{Engine as _, engine::general_purpose};
+
+    use super::*;
+
+    /// Spins for a few milliseconds, then returns an empty output
+    const BUSY_LOOP: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "alloc") (param i32) (result i32) (i32.const 0))
+        (func (export "agent_run") (param i32 i32) (result i64)
+            (local $i i32)
+            (loop $spin
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br_if $spin (i32.lt_u (local.get $i) (i32.const 1000000))))
+            (i64.const 0)))"#;
+
+    fn create_agent(wat: &str, extra_config: &str) -> *mut c_void {
+        let config = CString::new(format!(
+            r#"{{"wasm_base64": "{}"{}}}"#,
+            general_purpose::STANDARD.encode(wat),
+            extra_config
+        ))
+        .unwrap();
+        let handle = rust_agent_create(c"custom".as_ptr(), config.as_ptr());
+        assert!(!handle.is_null());
+        handle
+    }
+
+    #[test]
+    fn oversized_inputs_report_input_too_large() {
+        let handle = create_agent(BUSY_LOOP, r#", "max_input_bytes": 4"#);
+
+        let mut output = ptr::null_mut();
+        let mut output_size = 0;
+        let status = rust_agent_execute(handle, b"fives".as_ptr(), 5, &mut output, &mut output_size);
+
+        assert_eq!(status, KorraStatus::InputTooLarge);
+        rust_agent_destroy(handle);
+    }
+
+    #[test]
+    fn concurrent_executions_on_one_handle_report_busy() {
+        let handle = create_agent(BUSY_LOOP, "") as usize;
+        let barrier = Barrier::new(2);
+
+        let statuses: Vec<KorraStatus> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        (0..50)
+                            .map(|_| {
+                                let mut output = ptr::null_mut();
+                                let mut output_size = 0;
+                                let status = rust_agent_execute(
+                                    handle as *mut c_void,
+                                    ptr::null(),
+                                    0,
+                                    &mut output,
+                                    &mut output_size,
+                                );
+                                rust_agent_free_output(output, output_size);
+                                status
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+
+        assert!(statuses.iter().all(|&status| status == KorraStatus::Ok || status == KorraStatus::Busy));
+        assert!(statuses.contains(&KorraStatus::Busy));
+        assert!(statuses.contains(&KorraStatus::Ok));
+        rust_agent_destroy(handle as *mut c_void);
+    }
+
+    #[test]
+    fn panics_inside_execute_return_an_error_status() {
+        struct PanickingClock;
+
+        impl sandbox::wasm_host::TimeSource for PanickingClock {
+            fn now_millis(&self) -> u64 {
+                panic!("clock failed");
+            }
+        }
+
+        const READ_CLOCK: &str = r#"(module
+            (import "korra" "now_millis" (func $now_millis (result i64)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $now_millis))
+                (i64.const 0)))"#;
+        let handle = create_agent(READ_CLOCK, "");
+        unsafe {
+            interop::c_bridge::with_agent(handle, |agent| agent.set_time_source(Arc::new(PanickingClock))).unwrap();
+        }
+
+        let mut output = ptr::null_mut();
+        let mut output_size = 0;
+        let status = rust_agent_execute(handle, ptr::null(), 0, &mut output, &mut output_size);
+
+        assert_eq!(status, KorraStatus::ExecutionFailed);
+        assert!(output.is_null());
+        let mut message = [0 as c_char; 256];
+        rust_agent_last_error(message.as_mut_ptr(), message.len());
+        let message = unsafe { CStr::from_ptr(message.as_ptr()) }.to_string_lossy();
+        assert!(message.contains("Panic in rust_agent_execute: clock failed"), "{}", message);
+        rust_agent_destroy(handle);
+    }
+
+    #[test]
+    fn outputs_are_released_through_rust_agent_free_output() {
+        const ECHO: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len)))))"#;
+        let handle = create_agent(ECHO, "");
+        let live_before = test_support::live_allocations();
+
+        let mut output = ptr::null_mut();
+        let mut output_size = 0;
+        let status = rust_agent_execute(handle, b"echo".as_ptr(), 4, &mut output, &mut output_size);
+
+        assert_eq!(status, KorraStatus::Ok);
+        assert_eq!(unsafe { slice::from_raw_parts(output, output_size) }, b"echo");
+        assert_eq!(test_support::live_allocations(), live_before + 1);
+        rust_agent_free_output(output, output_size);
+        assert_eq!(test_support::live_allocations(), live_before);
+        rust_agent_destroy(handle);
+    }
+
+    #[test]
+    fn state_can_be_seeded_and_read_through_ffi() {
+        let handle = create_agent(BUSY_LOOP, "");
+
+        let status = rust_agent_state_set(handle, c"config".as_ptr(), b"fast".as_ptr(), 4);
+        assert_eq!(status, KorraStatus::Ok);
+
+        let mut value = ptr::null_mut();
+        let mut value_len = 0;
+        assert_eq!(rust_agent_state_get(handle, c"config".as_ptr(), &mut value, &mut value_len), KorraStatus::Ok);
+        assert_eq!(unsafe { slice::from_raw_parts(value, value_len) }, b"fast");
+        rust_agent_free_output(value, value_len);
+
+        assert_eq!(rust_agent_state_get(handle, c"missing".as_ptr(), &mut value, &mut value_len), KorraStatus::NotFound);
+        assert_eq!(
+            rust_agent_state_get(ptr::null_mut(), c"config".as_ptr(), &mut value, &mut value_len),
+            KorraStatus::NullPointer
+        );
+        rust_agent_destroy(handle);
+    }
+
+    #[test]
+    fn messages_below_the_log_level_are_dropped() {
+        let _settings = test_support::lock_log_settings();
+        rust_agent_set_log_level(LOG_LEVEL_WARN);
+        test_support::take_logs();
+
+        log_debug("debug");
+        log_info("info");
+        log_warn("warn");
+        log_error("error");
+        rust_agent_set_log_level(LOG_LEVEL_INFO);
+
+        assert_eq!(test_support::take_logs(), vec![
+            (LOG_LEVEL_WARN, "warn".to_string()),
+            (LOG_LEVEL_ERROR, "error".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn json_log_mode_emits_structured_lines() {
+        let _settings = test_support::lock_log_settings();
+        rust_agent_set_log_json(1);
+        test_support::take_logs();
+
+        {
+            let _scope = LogScope::enter("agent-7");
+            log_warn("disk \"low\"");
+        }
+        log_info("plain");
+        rust_agent_set_log_json(0);
+        log_info("text again");
+
+        let logs = test_support::take_logs();
+        let scoped: serde_json::Value = serde_json::from_str(&logs[0].1).unwrap();
+        assert_eq!(scoped["level"], "warn");
+        assert_eq!(scoped["msg"], "disk \"low\"");
+        assert_eq!(scoped["agent_id"], "agent-7");
+        assert!(scoped["ts"].as_u64().unwrap() > 0);
+        let unscoped: serde_json::Value = serde_json::from_str(&logs[1].1).unwrap();
+        assert!(unscoped["agent_id"].is_null());
+        assert_eq!(logs[2].1, "text again");
+    }
+
+    #[test]
+    fn execution_logs_carry_the_agent_id() {
+        let _settings = test_support::lock_log_settings();
+        rust_agent_set_log_level(LOG_LEVEL_DEBUG);
+        let first = create_agent(BUSY_LOOP, r#", "id": "first""#);
+        let second = create_agent(BUSY_LOOP, r#", "id": "second""#);
+        test_support::take_logs();
+
+        for (handle, id) in [(first, "first"), (second, "second")] {
+            let mut output = ptr::null_mut();
+            let mut output_size = 0;
+            assert_eq!(rust_agent_execute(handle, b"in".as_ptr(), 2, &mut output, &mut output_size), KorraStatus::Ok);
+            let logs = test_support::take_logs();
+            assert!(!logs.is_empty());
+            let prefix = format!("[agent {}] ", id);
+            assert!(logs.iter().all(|(_, line)| line.starts_with(&prefix)), "{:?}", logs);
+        }
+        rust_agent_set_log_level(LOG_LEVEL_INFO);
+        rust_agent_destroy(first);
+        rust_agent_destroy(second);
+    }
+
+    #[test]
+    fn agents_can_be_created_from_a_config_file() {
+        let path = std::env::temp_dir().join(format!("korra-config-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, format!(r#"{{"wasm_base64": "{}"}}"#, general_purpose::STANDARD.encode(BUSY_LOOP))).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        let mut handle = ptr::null_mut();
+        let status = rust_agent_create_from_file(c"custom".as_ptr(), c_path.as_ptr(), &mut handle);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(status, KorraStatus::Ok);
+        assert!(!handle.is_null());
+        rust_agent_destroy(handle);
+
+        let status = rust_agent_create_from_file(c"custom".as_ptr(), c_path.as_ptr(), &mut handle);
+        assert_eq!(status, KorraStatus::NotFound);
+        assert!(handle.is_null());
+    }
+
+    #[test]
+    fn execute_into_copies_outputs_that_fit_and_reports_the_size_otherwise() {
+        const ECHO: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 16))
+            (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len)))))"#;
+        let handle = create_agent(ECHO, "");
+        let mut buffer = [0u8; 8];
+        let mut out_len = 0;
+
+        let status = rust_agent_execute_into(handle, b"hello".as_ptr(), 5, buffer.as_mut_ptr(), buffer.len(), &mut out_len);
+        assert_eq!(status, KorraStatus::Ok);
+        assert_eq!(&buffer[..out_len], b"hello");
+
+        let input = b"longer than eight";
+        let status = rust_agent_execute_into(handle, input.as_ptr(), input.len(), buffer.as_mut_ptr(), buffer.len(), &mut out_len);
+        assert_eq!(status, KorraStatus::BufferTooSmall);
+        assert_eq!(out_len, input.len());
+        assert_eq!(&buffer[..5], b"hello");
+
+        rust_agent_destroy(handle);
+    }
+
+    #[test]
+    fn info_reports_the_agent_id_and_type() {
+        let handle = create_agent(BUSY_LOOP, r#", "id": "dashboard""#);
+
+        let mut out = ptr::null_mut();
+        assert_eq!(rust_agent_info(handle, &mut out), KorraStatus::Ok);
+        let info: serde_json::Value = serde_json::from_str(unsafe { CStr::from_ptr(out) }.to_str().unwrap()).unwrap();
+        unsafe { free(out as *mut c_void) };
+
+        assert_eq!(info["id"], "dashboard");
+        assert_eq!(info["agent_type"], "custom");
+        assert_eq!(info["has_proof"], false);
+        assert_eq!(info["state_size"], 0);
+        assert!(info["config_keys"].as_array().unwrap().contains(&serde_json::json!("id")));
+
+        assert_eq!(rust_agent_info(ptr::null_mut(), &mut out), KorraStatus::NullPointer);
+        let mut bogus = 0u64;
+        assert_eq!(rust_agent_info(&mut bogus as *mut u64 as *mut c_void, &mut out), KorraStatus::InvalidHandle);
+        rust_agent_destroy(handle);
+    }
+
+    #[test]
+    fn guest_traps_report_their_cause() {
+        let cases = [
+            ("unreachable", KorraStatus::TrapUnreachable),
+            ("(drop (i32.div_s (i32.const 1) (i32.const 0)))", KorraStatus::TrapDivisionByZero),
+            ("(drop (i32.load (i32.const 65536)))", KorraStatus::TrapMemoryOutOfBounds),
+        ];
+        for (body, expected) in cases {
+            let wat = format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                    (func (export "agent_run") (param i32 i32) (result i64)
+                        {}
+                        (i64.const 0)))"#,
+                body
+            );
+            let handle = create_agent(&wat, "");
+
+            let mut output = ptr::null_mut();
+            let mut output_size = 0;
+            let status = rust_agent_execute(handle, b"input".as_ptr(), 5, &mut output, &mut output_size);
+
+            assert_eq!(status, expected, "{}", body);
+            assert!(output.is_null());
+            rust_agent_destroy(handle);
+        }
+    }
+
+    /// Completion result sent back from `send_completion`, with the output address as an integer
+    type Completion = (KorraStatus, usize, usize);
+
+    extern "C" fn send_completion(status: KorraStatus, output: *mut u8, output_size: usize, user_data: *mut c_void) {
+        let sender = unsafe { &*(user_data as *const mpsc::Sender<Completion>) };
+        sender.send((status, output as usize, output_size)).unwrap();
+    }
+
+    #[test]
+    fn async_executions_on_one_handle_each_report_back() {
+        const ECHO_OR_TRAP: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+                (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120)) (then unreachable))
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len)))))"#;
+        let handle = create_agent(ECHO_OR_TRAP, "");
+        let (sender, completions) = mpsc::channel::<Completion>();
+        let user_data = &sender as *const _ as *mut c_void;
+
+        let inputs: [&[u8]; 4] = [b"job0", b"job1", b"xjob", b"job3"];
+        for input in inputs {
+            let status = rust_agent_execute_async(handle, input.as_ptr(), input.len(), Some(send_completion), user_data);
+            assert_eq!(status, KorraStatus::Ok);
+        }
+        // Queued executions keep the agent alive past its handle
+        rust_agent_destroy(handle);
+
+        let mut outputs = Vec::new();
+        let mut traps = 0;
+        for _ in inputs {
+            let (status, output, output_size) = completions.recv_timeout(Duration::from_secs(10)).unwrap();
+            match status {
+                KorraStatus::Ok => {
+                    let output = output as *mut u8;
+                    outputs.push(unsafe { slice::from_raw_parts(output, output_size) }.to_vec());
+                    rust_agent_free_output(output, output_size);
+                }
+                KorraStatus::TrapUnreachable => {
+                    assert_eq!((output, output_size), (0, 0));
+                    traps += 1;
+                }
+                status => panic!("unexpected status {:?}", status),
+            }
+        }
+        outputs.sort();
+        assert_eq!(outputs, [b"job0", b"job1", b"job3"]);
+        assert_eq!(traps, 1);
+        assert!(completions.try_recv().is_err());
+    }
+}
+
+/// Stand-ins for the C host callbacks, so unit tests link without the C side
+///
+/// Log lines are recorded per thread for tests to inspect, and allocations
+/// carry a size header so they can be released and counted.
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::alloc::{self, Layout};
+    use std::cell::{Cell, RefCell};
+    use std::ffi::{c_void, CStr};
+    use std::os::raw::{c_char, c_int};
+    use std::ptr;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    /// Bytes reserved ahead of each allocation to remember its size
+    const HEADER_SIZE: usize = 16;
+
+    /// Held by tests that change or depend on the process-wide log settings
+    static LOG_SETTINGS: Mutex<()> = Mutex::new(());
+
+    thread_local! {
+        static LOGS: RefCell<Vec<(c_int, String)>> = const { RefCell::new(Vec::new()) };
+        static LIVE_ALLOCATIONS: Cell<isize> = const { Cell::new(0) };
+    }
+
+    /// Serialize access to the log level and format across tests
+    pub(crate) fn lock_log_settings() -> MutexGuard<'static, ()> {
+        LOG_SETTINGS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drain the messages passed to `c_log_callback` on this thread
+    pub(crate) fn take_logs() -> Vec<(c_int, String)> {
+        LOGS.with(|logs| logs.take())
+    }
+
+    /// Buffers from `c_alloc_callback` on this thread not yet passed to `c_free_callback`
+    pub(crate) fn live_allocations() -> isize {
+        LIVE_ALLOCATIONS.with(Cell::get)
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size + HEADER_SIZE, HEADER_SIZE).expect("allocation too large")
+    }
+
+    #[no_mangle]
+    extern "C" fn c_log_callback(level: c_int, message: *const c_char) {
+        let message = unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned();
+        LOGS.with(|logs| logs.borrow_mut().push((level, message)));
+    }
+
+    #[no_mangle]
+    extern "C" fn c_alloc_callback(size: usize) -> *mut u8 {
+        let base = unsafe { alloc::alloc(layout(size)) };
+        if base.is_null() {
+            return ptr::null_mut();
+        }
+        LIVE_ALLOCATIONS.with(|live| live.set(live.get() + 1));
+        unsafe {
+            (base as *mut usize).write(size);
+            base.add(HEADER_SIZE)
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn c_free_callback(ptr: *mut c_void) {
+        if ptr.is_null() {
+            return;
+        }
+        LIVE_ALLOCATIONS.with(|live| live.set(live.get() - 1));
+        unsafe {
+            let base = (ptr as *mut u8).sub(HEADER_SIZE);
+            alloc::dealloc(base, layout((base as *const usize).read()));
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Host functions imported by agent modules
+//!
+//! All functions live in the `korra` import module. Each one needs a
+//! `Capabilities` grant; without it the import is linked but traps when called. Keys and values cross the
+//! boundary as length-prefixed buffers: a little-endian `u32` length followed
+//! by that many bytes.
+//!
+//! - `state_get(key_ptr: i32) -> i32`: returns a pointer to a length-prefixed copy
+//!   of the value, allocated through the guest's `alloc` export, or -1 if absent
+//! - `state_set(key_ptr: i32, value_ptr: i32) -> i32`: stores the value, returns 0,
+//!   or -1 if the write would exceed the state quota or the state log cannot record it
+//! - `state_delete(key_ptr: i32) -> i32`: returns 1 if the key existed, 0 otherwise,
+//!   or -1 if the state log cannot record the deletion
+//! - `emit_proof_event(label_ptr: i32, label_len: i32, data_ptr: i32, data_len: i32) -> i32`:
+//!   records a UTF-8 label and data as a proof event, returns 0, or -1 once the
+//!   execution has emitted `MAX_PROOF_EVENTS` events. Unlike the state functions,
+//!   it takes plain pointer and length pairs.
+//! - `random_fill(ptr: i32, len: i32) -> i32`: fills `len` bytes at `ptr` from a
+//!   ChaCha20 stream seeded by the agent id and input (see `seeded_rng` in
+//!   `wasm_host`), returns 0. The stream is reproducible, so outputs that use it
+//!   still give stable proofs.
+//! - `now_millis() -> i64`: milliseconds since the Unix epoch from the host's
+//!   `TimeSource`, or the agent's deterministic timestamp when it has one
+//!
+//! Every call is charged against the execution's fuel: `HOST_CALL_FUEL`, plus
+//! `HOST_BYTE_FUEL` for each key, value, label, or data byte it copies across
+//! the boundary. The charge is taken before the call has any effect, and a
+//! call the remaining fuel cannot cover traps as out of fuel. Charges are
+//! tallied per function in `HostFuel`, so the usage report can separate them
+//! from guest execution.
+
+use std::sync::MutexGuard;
+
+use rand_chacha::rand_core::RngCore;
+use wasmtime::{Caller, Linker, Memory, Trap};
+
+use crate::sandbox::wasm_host::{
+    log, read_guest, write_guest, Capabilities, GuestEvent, HostCall, HostState, EXPORT_ALLOC, EXPORT_MEMORY,
+};
+use crate::state::core::StateStore;
+
+/// Import module name for host functions
+const HOST_MODULE: &str = "korra";
+
+/// Size of the length prefix on guest buffers
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Most proof events one execution may emit
+pub const MAX_PROOF_EVENTS: usize = 1024;
+
+/// Fuel charged for every host function call
+pub const HOST_CALL_FUEL: u64 = 100;
+
+/// Fuel charged per byte a host function call copies across the boundary
+pub const HOST_BYTE_FUEL: u64 = 1;
+
+/// Register all host functions with the linker, denying those not granted
+pub(crate) fn add_to_linker(linker: &mut Linker<HostState>, capabilities: Capabilities) -> wasmtime::Result<()> {
+    if capabilities.contains(Capabilities::STATE_READ) {
+        linker.func_wrap(HOST_MODULE, "state_get", state_get)?;
+    } else {
+        linker.func_wrap(HOST_MODULE, "state_get", |_: i32| -> wasmtime::Result<i32> {
+            Err(denied("state_get", "STATE_READ"))
+        })?;
+    }
+
+    if capabilities.contains(Capabilities::STATE_WRITE) {
+        linker.func_wrap(HOST_MODULE, "state_set", state_set)?;
+        linker.func_wrap(HOST_MODULE, "state_delete", state_delete)?;
+    } else {
+        linker.func_wrap(HOST_MODULE, "state_set", |_: i32, _: i32| -> wasmtime::Result<i32> {
+            Err(denied("state_set", "STATE_WRITE"))
+        })?;
+        linker.func_wrap(HOST_MODULE, "state_delete", |_: i32| -> wasmtime::Result<i32> {
+            Err(denied("state_delete", "STATE_WRITE"))
+        })?;
+    }
+
+    if capabilities.contains(Capabilities::PROOF_EVENTS) {
+        linker.func_wrap(HOST_MODULE, "emit_proof_event", emit_proof_event)?;
+    } else {
+        linker.func_wrap(HOST_MODULE, "emit_proof_event", |_: i32, _: i32, _: i32, _: i32| -> wasmtime::Result<i32> {
+            Err(denied("emit_proof_event", "PROOF_EVENTS"))
+        })?;
+    }
+
+    if capabilities.contains(Capabilities::RANDOM) {
+        linker.func_wrap(HOST_MODULE, "random_fill", random_fill)?;
+    } else {
+        linker.func_wrap(HOST_MODULE, "random_fill", |_: i32, _: i32| -> wasmtime::Result<i32> {
+            Err(denied("random_fill", "RANDOM"))
+        })?;
+    }
+
+    if capabilities.contains(Capabilities::CLOCK) {
+        linker.func_wrap(HOST_MODULE, "now_millis", now_millis)?;
+    } else {
+        linker.func_wrap(HOST_MODULE, "now_millis", || -> wasmtime::Result<i64> {
+            Err(denied("now_millis", "CLOCK"))
+        })?;
+    }
+    Ok(())
+}
+
+/// Error trapping a call to a host function the guest was not granted
+fn denied(function: &str, capability: &str) -> wasmtime::Error {
+    let message = format!("Host function '{}' requires the {} capability", function, capability);
+    log::warn(&message);
+    wasmtime::Error::msg(message)
+}
+
+fn state_get(mut caller: Caller<'_, HostState>, key_ptr: i32) -> wasmtime::Result<i32> {
+    let key = read_key(&mut caller, key_ptr)?;
+    let value = lock_state(&caller)?.get(&key);
+    let copied = key.len() + value.as_ref().map_or(0, |v| v.len());
+    charge(&mut caller, HostCall::StateGet, copied)?;
+    match value {
+        Some(value) => write_prefixed(&mut caller, &value),
+        None => Ok(-1),
+    }
+}
+
+fn state_set(mut caller: Caller<'_, HostState>, key_ptr: i32, value_ptr: i32) -> wasmtime::Result<i32> {
+    let key = read_key(&mut caller, key_ptr)?;
+    let value = read_prefixed(&mut caller, value_ptr)?;
+    charge(&mut caller, HostCall::StateSet, key.len() + value.len())?;
+    match lock_state(&caller)?.set(&key, &value) {
+        Ok(()) => Ok(0),
+        Err(_) => Ok(-1),
+    }
+}
+
+fn state_delete(mut caller: Caller<'_, HostState>, key_ptr: i32) -> wasmtime::Result<i32> {
+    let key = read_key(&mut caller, key_ptr)?;
+    charge(&mut caller, HostCall::StateDelete, key.len())?;
+    match lock_state(&caller)?.delete(&key) {
+        Ok(removed) => Ok(removed as i32),
+        Err(_) => Ok(-1),
+    }
+}
+
+fn emit_proof_event(
+    mut caller: Caller<'_, HostState>,
+    label_ptr: i32,
+    label_len: i32,
+    data_ptr: i32,
+    data_len: i32,
+) -> wasmtime::Result<i32> {
+    if caller.data().events.len() >= MAX_PROOF_EVENTS {
+        charge(&mut caller, HostCall::EmitProofEvent, 0)?;
+        return Ok(-1);
+    }
+
+    // Check and pay for both buffers before copying either
+    let memory = guest_memory(&mut caller)?;
+    let memory_size = memory.data_size(&caller) as u64;
+    for (ptr, len) in [(label_ptr as u32, label_len as u32), (data_ptr as u32, data_len as u32)] {
+        if ptr as u64 + len as u64 > memory_size {
+            return Err(wasmtime::Error::msg(format!(
+                "Proof event buffer at {} with length {} is out of bounds", ptr, len
+            )));
+        }
+    }
+    charge(&mut caller, HostCall::EmitProofEvent, label_len as u32 as usize + data_len as u32 as usize)?;
+
+    let label = read_guest(&caller, &memory, label_ptr as u32, label_len as u32)?;
+    let label = String::from_utf8(label).map_err(|_| wasmtime::Error::msg("Proof event label is not valid UTF-8"))?;
+    let data = read_guest(&caller, &memory, data_ptr as u32, data_len as u32)?;
+    caller.data_mut().events.push(GuestEvent { label, data });
+    Ok(0)
+}
+
+fn random_fill(mut caller: Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<i32> {
+    let memory = guest_memory(&mut caller)?;
+    let len = usize::try_from(len).map_err(|_| wasmtime::Error::msg(format!("Invalid random_fill length {}", len)))?;
+    if len > memory.data_size(&caller) {
+        return Err(wasmtime::Error::msg(format!("random_fill length {} exceeds guest memory", len)));
+    }
+
+    charge(&mut caller, HostCall::RandomFill, len)?;
+    let mut buf = vec![0u8; len];
+    caller.data_mut().rng.fill_bytes(&mut buf);
+    write_guest(&mut caller, &memory, ptr, &buf)?;
+    Ok(0)
+}
+
+fn now_millis(mut caller: Caller<'_, HostState>) -> wasmtime::Result<i64> {
+    charge(&mut caller, HostCall::NowMillis, 0)?;
+    Ok(caller.data().clock.now_millis() as i64)
+}
+
+/// Take the fuel for a host call that copies `bytes` across the boundary
+///
+/// Traps as out of fuel, with the fuel drained, if the remainder cannot cover it.
+fn charge(caller: &mut Caller<'_, HostState>, call: HostCall, bytes: usize) -> wasmtime::Result<()> {
+    let cost = HOST_CALL_FUEL.saturating_add(HOST_BYTE_FUEL.saturating_mul(bytes as u64));
+    let remaining = caller.get_fuel()?;
+    caller.data_mut().host_fuel.record(call, cost.min(remaining));
+    if remaining < cost {
+        caller.set_fuel(0)?;
+        return Err(Trap::OutOfFuel.into());
+    }
+    caller.set_fuel(remaining - cost)
+}
+
+/// Lock the state store of the running agent
+fn lock_state<'a>(caller: &'a Caller<'_, HostState>) -> wasmtime::Result<MutexGuard<'a, StateStore>> {
+    caller
+        .data()
+        .state
+        .lock()
+        .map_err(|e| {
+            let message = format!("Failed to lock state: {}", e);
+            log::error(&message);
+            wasmtime::Error::msg(message)
+        })
+}
+
+/// Get the guest's exported memory
+fn guest_memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export(EXPORT_MEMORY)
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg(format!("Module does not export '{}'", EXPORT_MEMORY)))
+}
+
+/// Read a length-prefixed buffer from guest memory
+fn read_prefixed(caller: &mut Caller<'_, HostState>, ptr: i32) -> wasmtime::Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let prefix = read_guest(&*caller, &memory, ptr as u32, LENGTH_PREFIX_SIZE as u32)?;
+    let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    let data_ptr = (ptr as u32)
+        .checked_add(LENGTH_PREFIX_SIZE as u32)
+        .ok_or_else(|| wasmtime::Error::msg("Guest buffer pointer overflow"))?;
+    Ok(read_guest(&*caller, &memory, data_ptr, len)?)
+}
+
+/// Read a length-prefixed UTF-8 key from guest memory
+fn read_key(caller: &mut Caller<'_, HostState>, ptr: i32) -> wasmtime::Result<String> {
+    let bytes = read_prefixed(caller, ptr)?;
+    String::from_utf8(bytes).map_err(|_| wasmtime::Error::msg("State key is not valid UTF-8"))
+}
+
+/// Copy bytes into a fresh guest allocation as a length-prefixed buffer
+fn write_prefixed(caller: &mut Caller<'_, HostState>, data: &[u8]) -> wasmtime::Result<i32> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| wasmtime::Error::msg(format!("Value too large: {} bytes", data.len())))?;
+    let total = i32::try_from(data.len() + LENGTH_PREFIX_SIZE)
+        .map_err(|_| wasmtime::Error::msg(format!("Value too large: {} bytes", data.len())))?;
+
+    let alloc = caller
+        .get_export(EXPORT_ALLOC)
+        .and_then(|export| export.into_func())
+        .ok_or_else(|| wasmtime::Error::msg(format!("Module does not export '{}'", EXPORT_ALLOC)))?
+        .typed::<i32, i32>(&*caller)?;
+    let ptr = alloc.call(&mut *caller, total)?;
+
+    let mut buf = Vec::with_capacity(data.len() + LENGTH_PREFIX_SIZE);
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(data);
+
+    let memory = guest_memory(caller)?;
+    write_guest(&mut *caller, &memory, ptr, &buf)?;
+    Ok(ptr)
+}
@@ -0,0 +1,4 @@
+//! Sandboxed execution environment
+
+mod host_functions;
+pub mod wasm_host;
@@ -1,20 +1,59 @@
 //! WASM runtime isolation for agents
+//!
+//! Agent modules are executed with wasmtime. A module must export:
+//!
+//! - `memory`: the guest linear memory
+//! - `alloc(len: i32) -> i32`: reserve `len` bytes of guest memory for the input
+//! - `agent_run(ptr: i32, len: i32) -> i64`: process the input at `ptr` and return
+//!   the output location packed as `(out_ptr << 32) | out_len`
+//!
+//! Modules may export further entry points with the same signature as
+//! `agent_run`, which run through `WasmHost::call`.
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::fs;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(test)]
+use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use base64::{Engine as _, engine::general_purpose};
+use ed25519_dalek::{Signature, VerifyingKey};
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use wasmtime::{
+    AsContext, AsContextMut, Config, Engine, Instance, InstanceAllocationStrategy, InstancePre,
+    Linker, Memory, Module, PoolingAllocationConfig, ResourceLimiter,
+    Store, Trap, UpdateDeadline,
+};
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
 
 use crate::engine::agent::ExecutionContext;
+use crate::sandbox::host_functions;
 use crate::state::core::StateStore;
+use crate::LogScope;
 
 /// Error type for WASM host operations
 #[derive(Debug)]
 pub enum WasmHostError {
     ModuleLoadError(String),
     InstantiationError(String),
+    /// The module's imports or exports do not fit the host, so every attempt fails alike
+    LinkError(String),
     ExecutionError(String),
     MemoryError(String),
+    /// The guest trapped; the code says why
+    Trap(TrapCode, String),
 }
 
 impl fmt::Display for WasmHostError {
@@ -22,26 +61,607 @@ impl fmt::Display for WasmHostError {
         match self {
             WasmHostError::ModuleLoadError(msg) => write!(f, "Module load error: {}", msg),
             WasmHostError::InstantiationError(msg) => write!(f, "Instantiation error: {}", msg),
+            WasmHostError::LinkError(msg) => write!(f, "Link error: {}", msg),
             WasmHostError::ExecutionError(msg) => write!(f, "Execution error: {}", msg),
             WasmHostError::MemoryError(msg) => write!(f, "Memory error: {}", msg),
+            WasmHostError::Trap(code, msg) => write!(f, "Trap {}: {}", code.as_str(), msg),
         }
     }
 }
 
 impl Error for WasmHostError {}
 
+/// Cause of a guest trap
+///
+/// Timeouts, cancellation, fuel exhaustion, and memory limit hits are
+/// reported through their own errors rather than as traps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapCode {
+    /// The guest executed `unreachable`, e.g. a deliberate abort or panic
+    Unreachable,
+    /// A memory access fell outside linear memory
+    MemoryOutOfBounds,
+    IntegerDivisionByZero,
+    IntegerOverflow,
+    /// A float-to-integer conversion was out of range or NaN
+    BadConversionToInteger,
+    /// The guest recursed past the host's stack size limit
+    StackOverflow,
+    /// An indirect call hit a null or out of bounds table entry, or a mismatched signature
+    BadIndirectCall,
+    /// A host function the guest called failed
+    HostError,
+    /// Any other trap
+    Other,
+}
+
+impl TrapCode {
+    /// Classify a guest call error
+    fn of(error: &wasmtime::Error) -> Self {
+        match error.downcast_ref::<Trap>() {
+            Some(Trap::UnreachableCodeReached) => TrapCode::Unreachable,
+            Some(Trap::MemoryOutOfBounds) | Some(Trap::HeapMisaligned) => TrapCode::MemoryOutOfBounds,
+            Some(Trap::IntegerDivisionByZero) => TrapCode::IntegerDivisionByZero,
+            Some(Trap::IntegerOverflow) => TrapCode::IntegerOverflow,
+            Some(Trap::BadConversionToInteger) => TrapCode::BadConversionToInteger,
+            Some(Trap::StackOverflow) => TrapCode::StackOverflow,
+            Some(Trap::IndirectCallToNull) | Some(Trap::BadSignature) | Some(Trap::TableOutOfBounds) => {
+                TrapCode::BadIndirectCall
+            }
+            Some(_) => TrapCode::Other,
+            None => TrapCode::HostError,
+        }
+    }
+
+    /// Get the machine-readable name of the trap code, e.g. `integer_division_by_zero`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrapCode::Unreachable => "unreachable",
+            TrapCode::MemoryOutOfBounds => "memory_out_of_bounds",
+            TrapCode::IntegerDivisionByZero => "integer_division_by_zero",
+            TrapCode::IntegerOverflow => "integer_overflow",
+            TrapCode::BadConversionToInteger => "bad_conversion_to_integer",
+            TrapCode::StackOverflow => "stack_overflow",
+            TrapCode::BadIndirectCall => "bad_indirect_call",
+            TrapCode::HostError => "host_error",
+            TrapCode::Other => "other",
+        }
+    }
+}
+
 /// WASM memory limits
 const WASM_PAGE_SIZE: usize = 65536; // 64KB
 const WASM_MAX_MEMORY_PAGES: u32 = 100; // 6.4MB
 
+/// Guest export names used by the host ABI
+pub(crate) const EXPORT_MEMORY: &str = "memory";
+pub(crate) const EXPORT_ALLOC: &str = "alloc";
+const EXPORT_ENTRY: &str = "agent_run";
+
+/// Binary module preamble: the `\0asm` magic followed by the version 1 encoding
+const WASM_MAGIC: [u8; 4] = *b"\0asm";
+const WASM_VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+/// Module path reported for hosts created from in-memory bytes
+const IN_MEMORY_MODULE: &str = "<memory>";
+
+/// Maximum guest stdout/stderr captured per execution
+const WASI_OUTPUT_CAPACITY: usize = 64 * 1024;
+
+/// Interval at which the engines' epochs advance
+const EPOCH_TICK_MS: u64 = 10;
+
+/// WASM stack limits, in bytes
+///
+/// The maximum stays well inside the 2 MiB native stack Rust gives spawned
+/// threads, so a guest always traps before the host thread overflows.
+pub const DEFAULT_WASM_STACK_SIZE: usize = 512 * 1024;
+const MIN_WASM_STACK_SIZE: usize = 32 * 1024;
+const MAX_WASM_STACK_SIZE: usize = 1024 * 1024;
+
+/// Largest instance pool a host can use
+const MAX_INSTANCE_POOL_SIZE: u32 = 1000;
+
+/// Settings wasmtime fixes per engine rather than per store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct EngineKey {
+    stack_size: usize,
+    pool_size: Option<u32>,
+}
+
+/// An engine, with the slots bounding its live instances if it is pooled
+#[derive(Clone)]
+struct SharedEngine {
+    engine: Engine,
+    pool: Option<Arc<InstancePool>>,
+}
+
+/// Engine with the settings in `key`
+///
+/// Hosts share one engine per distinct key. A background thread advances
+/// every engine's epoch to drive epoch interruption.
+fn engine_for(key: EngineKey) -> Result<SharedEngine, WasmHostError> {
+    static ENGINES: OnceLock<Mutex<HashMap<EngineKey, SharedEngine>>> = OnceLock::new();
+    let engines = ENGINES.get_or_init(|| {
+        // Advance the epochs so guests stuck in tight loops still hit their deadline
+        thread::Builder::new()
+            .name("korra-epoch".to_string())
+            .spawn(|| loop {
+                thread::sleep(Duration::from_millis(EPOCH_TICK_MS));
+                if let Some(engines) = ENGINES.get() {
+                    for shared in engines.lock().unwrap_or_else(PoisonError::into_inner).values() {
+                        shared.engine.increment_epoch();
+                    }
+                }
+            })
+            .expect("Failed to spawn epoch ticker thread");
+        Mutex::new(HashMap::new())
+    });
+
+    let mut engines = engines.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(shared) = engines.get(&key) {
+        return Ok(shared.clone());
+    }
+
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    config.consume_fuel(true);
+    config.max_wasm_stack(key.stack_size);
+    if let Some(size) = key.pool_size {
+        let mut pooling = PoolingAllocationConfig::default();
+        pooling.total_core_instances(size);
+        pooling.total_memories(size);
+        pooling.total_tables(size);
+        config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+    }
+    let engine = Engine::new(&config).map_err(|e| {
+        WasmHostError::ModuleLoadError(format!("Failed to create engine: {}", e))
+    })?;
+
+    let shared = SharedEngine {
+        engine,
+        pool: key.pool_size.map(|size| Arc::new(InstancePool::new(size))),
+    };
+    engines.insert(key, shared.clone());
+    Ok(shared)
+}
+
+/// Bound on the live instances of a pooled engine
+///
+/// wasmtime fails to instantiate once every slot of its pool is in use, so
+/// executions wait here for a free slot instead.
+struct InstancePool {
+    free: Mutex<u32>,
+    freed: Condvar,
+}
+
+impl InstancePool {
+    fn new(size: u32) -> Self {
+        InstancePool {
+            free: Mutex::new(size),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait for a free slot and take it
+    fn checkout(self: &Arc<Self>) -> PoolSlot {
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap_or_else(PoisonError::into_inner);
+        }
+        *free -= 1;
+        PoolSlot(self.clone())
+    }
+}
+
+/// A taken pool slot, returned when dropped
+struct PoolSlot(Arc<InstancePool>);
+
+impl Drop for PoolSlot {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// A store with the module instantiated in it, ready to run
+struct Sandbox {
+    store: Store<HostState>,
+    instance: Instance,
+    output: Option<GuestOutput>,
+    // Declared after the store so the slot is only released once the store,
+    // and with it the pooled instance, is gone
+    _slot: Option<PoolSlot>,
+}
+
+/// Compiled modules keyed by engine settings and the SHA-256 of their bytes
+///
+/// wasmtime modules are reference counted, so cached entries are shared between
+/// hosts rather than copied. Compiling a small module costs milliseconds while a
+/// cache hit only hashes the bytes, so spinning up many agents from one module
+/// pays the compilation cost once. The cache holds at most
+/// `MODULE_CACHE_CAPACITY` modules, so hosts loading ever new modules do not
+/// grow it without bound.
+fn module_cache() -> &'static Mutex<ModuleCache> {
+    static CACHE: OnceLock<Mutex<ModuleCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(ModuleCache::default()))
+}
+
+/// Most compiled modules kept in the module cache
+const MODULE_CACHE_CAPACITY: usize = 64;
+
+/// Module cache that evicts the least recently used module once full
+#[derive(Default)]
+struct ModuleCache {
+    /// Compiled modules with the tick they were last used at
+    entries: HashMap<String, (Module, u64)>,
+    tick: u64,
+}
+
+impl ModuleCache {
+    fn get(&mut self, key: &str) -> Option<Module> {
+        self.tick += 1;
+        let (module, last_used) = self.entries.get_mut(key)?;
+        *last_used = self.tick;
+        Some(module.clone())
+    }
+
+    fn insert(&mut self, key: String, module: Module) {
+        self.tick += 1;
+        if self.entries.len() >= MODULE_CACHE_CAPACITY && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (module, self.tick));
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Resource limiter that rejects memory growth past the host's memory limit
+struct MemoryLimiter {
+    max_bytes: usize,
+    exceeded: bool,
+    peak_bytes: usize,
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        if desired > self.max_bytes {
+            // Trap instead of letting the guest see a failed grow, so the host can report it
+            self.exceeded = true;
+            return Err(wasmtime::Error::msg(format!(
+                "memory growth to {} bytes exceeds limit of {} bytes", desired, self.max_bytes
+            )));
+        }
+        self.peak_bytes = self.peak_bytes.max(desired);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Opt-in WASI capabilities granted to a guest
+///
+/// Nothing is inherited from the host by default: only the listed arguments,
+/// allowlisted environment variables, and preopened directories are visible.
+#[derive(Debug, Clone, Default)]
+pub struct WasiConfig {
+    args: Vec<String>,
+    env_allowlist: Vec<String>,
+    preopened_dirs: Vec<(String, String)>,
+}
+
+impl WasiConfig {
+    /// Create an empty WASI configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a command-line argument visible to the guest
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Pass a host environment variable through to the guest, if it is set
+    pub fn allow_env(mut self, name: &str) -> Self {
+        self.env_allowlist.push(name.to_string());
+        self
+    }
+
+    /// Preopen a host directory at the given guest path
+    pub fn preopen_dir(mut self, host_path: &str, guest_path: &str) -> Self {
+        self.preopened_dirs.push((host_path.to_string(), guest_path.to_string()));
+        self
+    }
+
+    /// Build a WASI context whose stdout/stderr are captured in memory
+    fn build(&self) -> Result<(WasiP1Ctx, GuestOutput), WasmHostError> {
+        let output = GuestOutput {
+            stdout: MemoryOutputPipe::new(WASI_OUTPUT_CAPACITY),
+            stderr: MemoryOutputPipe::new(WASI_OUTPUT_CAPACITY),
+        };
+
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .args(&self.args)
+            .stdout(output.stdout.clone())
+            .stderr(output.stderr.clone());
+
+        for name in &self.env_allowlist {
+            if let Ok(value) = std::env::var(name) {
+                builder.env(name, value);
+            }
+        }
+
+        for (host_path, guest_path) in &self.preopened_dirs {
+            builder
+                .preopened_dir(host_path, guest_path, DirPerms::all(), FilePerms::all())
+                .map_err(|e| {
+                    WasmHostError::InstantiationError(format!(
+                        "Failed to preopen {}: {}", host_path, e
+                    ))
+                })?;
+        }
+
+        Ok((builder.build_p1(), output))
+    }
+}
+
+/// Guest stdout/stderr captured during an execution
+struct GuestOutput {
+    stdout: MemoryOutputPipe,
+    stderr: MemoryOutputPipe,
+}
+
+impl GuestOutput {
+    /// Route captured output through the host log instead of the real stdout
+    fn flush_to_log(&self) {
+        for line in String::from_utf8_lossy(&self.stdout.contents()).lines() {
+            log::info(&format!("[guest stdout] {}", line));
+        }
+        for line in String::from_utf8_lossy(&self.stderr.contents()).lines() {
+            log::error(&format!("[guest stderr] {}", line));
+        }
+    }
+}
+
+/// Resources consumed by guest code during an execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// Fuel units burned, roughly one per instruction executed, plus host call charges
+    pub fuel_consumed: u64,
+    /// Part of `fuel_consumed` charged by host function calls
+    pub host_fuel: HostFuel,
+    /// Largest guest memory size reached, in bytes
+    pub peak_memory: usize,
+}
+
+impl ResourceUsage {
+    /// Fuel burned by guest instructions alone
+    pub fn guest_fuel(&self) -> u64 {
+        self.fuel_consumed.saturating_sub(self.host_fuel.total())
+    }
+}
+
+/// Host function a guest can call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostCall {
+    StateGet,
+    StateSet,
+    StateDelete,
+    EmitProofEvent,
+    RandomFill,
+    NowMillis,
+}
+
+impl HostCall {
+    /// Every host function, in import order
+    pub const ALL: [HostCall; 6] = [
+        HostCall::StateGet,
+        HostCall::StateSet,
+        HostCall::StateDelete,
+        HostCall::EmitProofEvent,
+        HostCall::RandomFill,
+        HostCall::NowMillis,
+    ];
+
+    /// Import name of the function
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HostCall::StateGet => "state_get",
+            HostCall::StateSet => "state_set",
+            HostCall::StateDelete => "state_delete",
+            HostCall::EmitProofEvent => "emit_proof_event",
+            HostCall::RandomFill => "random_fill",
+            HostCall::NowMillis => "now_millis",
+        }
+    }
+}
+
+/// Calls made and fuel charged by host functions, per function
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostFuel {
+    calls: [u64; HostCall::ALL.len()],
+    fuel: [u64; HostCall::ALL.len()],
+}
+
+impl HostFuel {
+    /// Number of calls made to `call`
+    pub fn calls(&self, call: HostCall) -> u64 {
+        self.calls[call as usize]
+    }
+
+    /// Fuel charged by calls to `call`
+    pub fn fuel(&self, call: HostCall) -> u64 {
+        self.fuel[call as usize]
+    }
+
+    /// Fuel charged by all host calls
+    pub fn total(&self) -> u64 {
+        self.fuel.iter().fold(0u64, |sum, fuel| sum.saturating_add(*fuel))
+    }
+
+    /// Fold another breakdown into this one
+    pub fn accumulate(&mut self, other: &HostFuel) {
+        for i in 0..HostCall::ALL.len() {
+            self.calls[i] = self.calls[i].saturating_add(other.calls[i]);
+            self.fuel[i] = self.fuel[i].saturating_add(other.fuel[i]);
+        }
+    }
+
+    /// Record one call to `call` charging `fuel`
+    pub(crate) fn record(&mut self, call: HostCall, fuel: u64) {
+        self.calls[call as usize] += 1;
+        self.fuel[call as usize] = self.fuel[call as usize].saturating_add(fuel);
+    }
+}
+
+/// Error raised inside the guest when an execution is cancelled
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Labelled claim a guest recorded with the `emit_proof_event` host function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestEvent {
+    pub label: String,
+    pub data: Vec<u8>,
+}
+
+/// Per-execution data held by the wasmtime store
+pub(crate) struct HostState {
+    limiter: MemoryLimiter,
+    cancelled: Arc<AtomicBool>,
+    elapsed_ticks: u64,
+    wasi: Option<WasiP1Ctx>,
+    pub(crate) state: Arc<Mutex<StateStore>>,
+    pub(crate) events: Vec<GuestEvent>,
+    agent_id: String,
+    pub(crate) rng: ChaCha20Rng,
+    pub(crate) clock: Arc<dyn TimeSource>,
+    pub(crate) host_fuel: HostFuel,
+}
+
+bitflags::bitflags! {
+    /// Host functions a guest is allowed to call
+    ///
+    /// Imports outside the granted set are still linked, so modules always
+    /// instantiate, but calling one traps naming the missing capability.
+    /// Config strings use the flag names, e.g. `"STATE_READ | PROOF_EVENTS"`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Capabilities: u32 {
+        /// `state_get`
+        const STATE_READ = 1 << 0;
+        /// `state_set` and `state_delete`
+        const STATE_WRITE = 1 << 1;
+        /// `emit_proof_event`
+        const PROOF_EVENTS = 1 << 2;
+        /// `random_fill`
+        const RANDOM = 1 << 3;
+        /// `now_millis`
+        const CLOCK = 1 << 4;
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::all()
+    }
+}
+
+impl Capabilities {
+    /// Parse flag names separated by `|`; an empty string grants nothing
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(names: &str) -> Result<Self, String> {
+        bitflags::parser::from_str(names).map_err(|e| format!("Invalid capabilities '{}': {}", names, e))
+    }
+}
+
+/// Clock read by guests through the `now_millis` host function
+pub trait TimeSource: Send + Sync {
+    /// Current time in milliseconds since the Unix epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// The real wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+/// A clock stopped at a fixed time, for tests and deterministic runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub u64);
+
+impl TimeSource for FixedClock {
+    fn now_millis(&self) -> u64 {
+        self.0
+    }
+}
+
 /// WASM host for secure agent execution
+///
+/// Clones share the compiled module but have their own limits. Every
+/// execution runs in a fresh instance, so no guest memory or globals carry
+/// over between executions; hosts with an instance pool recycle the
+/// instances' slots, which wasmtime resets between uses.
+///
+/// Instances themselves are never kept for a later execution. wasmtime can
+/// neither read nor reset globals and tables a module does not export, and
+/// linear memory cannot shrink back after the guest grows it, so a reused
+/// instance could leak guest state. Pooling slots and linking the module once
+/// take most of the cost out of instantiation instead.
+#[derive(Clone)]
 pub struct WasmHost {
     module_path: String,
+    module_hash: String,
     memory_limit: usize,
     execution_timeout_ms: u64,
-    // In a real implementation, this would use wasmtime or wasmer
-    // For this demo, we'll simulate the WASM execution
-    _simulated_state: Arc<Mutex<StateStore>>,
+    fuel_limit: Option<u64>,
+    engine_key: EngineKey,
+    engine: SharedEngine,
+    module: Module,
+    module_bytes: Arc<[u8]>,
+    /// Module linked against the host functions, built on first execution
+    instance_pre: OnceLock<InstancePre<HostState>>,
+    wasi: Option<WasiConfig>,
+    capabilities: Capabilities,
+    time_source: Arc<dyn TimeSource>,
+    /// Number of upcoming instantiations to fail as if under resource pressure
+    #[cfg(test)]
+    injected_failures: Arc<AtomicU32>,
 }
 
 impl WasmHost {
@@ -53,64 +673,1104 @@ impl WasmHost {
                 "Module file not found: {}", module_path
             )));
         }
-        
-        // In a real implementation, this would load and validate the WASM module
-        // For this demo, we'll just store the path
-        
+
+        let module_bytes = fs::read(module_path).map_err(|e| {
+            WasmHostError::ModuleLoadError(format!("Failed to read {}: {}", module_path, e))
+        })?;
+
+        // Text modules are assembled first; binary modules take the fast path
+        if module_path.ends_with(".wat") || is_wat_text(&module_bytes) {
+            let binary = parse_wat(&module_bytes, module_path)?;
+            return Self::load(&binary, module_path);
+        }
+        validate_header(&module_bytes, module_path)?;
+
+        Self::load(&module_bytes, module_path)
+    }
+
+    /// Create a new WASM host from an in-memory module
+    pub fn from_bytes(module_bytes: &[u8]) -> Result<Self, WasmHostError> {
+        if is_wat_text(module_bytes) {
+            let binary = parse_wat(module_bytes, IN_MEMORY_MODULE)?;
+            return Self::load(&binary, IN_MEMORY_MODULE);
+        }
+        Self::load(module_bytes, IN_MEMORY_MODULE)
+    }
+
+    /// Create a WASM host from module bytes carrying an Ed25519 signature
+    ///
+    /// The signature must cover `module_bytes` exactly and verify under
+    /// `public_key` (32 bytes); otherwise nothing is compiled and this fails
+    /// with `ModuleLoadError("signature verification failed")`. As with every
+    /// host, `module_hash` identifies the module in execution proofs.
+    pub fn new_verified(module_bytes: &[u8], signature: &[u8], public_key: &[u8]) -> Result<Self, WasmHostError> {
+        let verified = Signature::from_slice(signature).ok()
+            .zip(public_key.try_into().ok().and_then(|key| VerifyingKey::from_bytes(key).ok()))
+            .is_some_and(|(signature, key)| key.verify_strict(module_bytes, &signature).is_ok());
+        if !verified {
+            return Err(WasmHostError::ModuleLoadError("signature verification failed".to_string()));
+        }
+        Self::from_bytes(module_bytes)
+    }
+
+    /// Compile a module and build the host around it
+    fn load(module_bytes: &[u8], module_path: &str) -> Result<Self, WasmHostError> {
+        let engine_key = EngineKey { stack_size: DEFAULT_WASM_STACK_SIZE, pool_size: None };
+        let engine = engine_for(engine_key)?;
+        let module = Self::compile_cached(&engine.engine, engine_key, module_bytes, module_path)?;
+
         Ok(WasmHost {
             module_path: module_path.to_string(),
+            module_hash: general_purpose::STANDARD.encode(Sha256::digest(module_bytes)),
             memory_limit: (WASM_MAX_MEMORY_PAGES as usize) * WASM_PAGE_SIZE,
             execution_timeout_ms: 5000, // 5 seconds
-            _simulated_state: Arc::new(Mutex::new(StateStore::new())),
+            fuel_limit: None,
+            engine_key,
+            engine,
+            module,
+            module_bytes: Arc::from(module_bytes),
+            instance_pre: OnceLock::new(),
+            wasi: None,
+            capabilities: Capabilities::default(),
+            time_source: Arc::new(SystemClock),
+            #[cfg(test)]
+            injected_failures: Arc::new(AtomicU32::new(0)),
         })
     }
-    
+
+    /// Swap in the module at `module_path`, keeping limits and WASI settings
+    ///
+    /// Executions borrow the host, so any in flight finish on the old module
+    /// before the swap. On error the current module stays loaded.
+    pub fn reload(&mut self, module_path: &str) -> Result<(), WasmHostError> {
+        let loaded = WasmHost::new(module_path)?;
+        self.replace_module(loaded)
+    }
+
+    /// Swap in an in-memory module, keeping limits and WASI settings
+    pub fn reload_bytes(&mut self, module_bytes: &[u8]) -> Result<(), WasmHostError> {
+        let loaded = WasmHost::from_bytes(module_bytes)?;
+        self.replace_module(loaded)
+    }
+
+    fn replace_module(&mut self, mut loaded: WasmHost) -> Result<(), WasmHostError> {
+        loaded.switch_engine(self.engine_key)?;
+        log::info(&format!("Reloaded WASM module: {} -> {}", self.module_path, loaded.module_path));
+        self.module_path = loaded.module_path;
+        self.module_hash = loaded.module_hash;
+        self.module = loaded.module;
+        self.module_bytes = loaded.module_bytes;
+        self.instance_pre = OnceLock::new();
+        Ok(())
+    }
+
+    /// Compile a module for the engine with `engine_key`, reusing a previous compilation of identical bytes
+    fn compile_cached(
+        engine: &Engine,
+        engine_key: EngineKey,
+        module_bytes: &[u8],
+        module_path: &str,
+    ) -> Result<Module, WasmHostError> {
+        let key = format!("{:?}:{:x}", engine_key, Sha256::digest(module_bytes));
+
+        match module_cache().lock() {
+            Ok(mut cache) => {
+                if let Some(module) = cache.get(&key) {
+                    log::debug(&format!("Reusing cached compilation of {}", module_path));
+                    return Ok(module);
+                }
+            }
+            Err(_) => log::warn("Module cache is poisoned; compiling without it"),
+        }
+
+        // Compile the module up front so broken modules fail at load time
+        let module = Module::new(engine, module_bytes).map_err(|e| {
+            WasmHostError::ModuleLoadError(format!("Failed to compile {}: {}", module_path, e))
+        })?;
+
+        if let Ok(mut cache) = module_cache().lock() {
+            cache.insert(key, module.clone());
+        }
+
+        Ok(module)
+    }
+
+    /// Drop all cached module compilations
+    pub fn clear_module_cache() {
+        if let Ok(mut cache) = module_cache().lock() {
+            cache.clear();
+        }
+    }
+
+    /// Enable WASI for guests run by this host
+    pub fn with_wasi(mut self, config: WasiConfig) -> Self {
+        self.wasi = Some(config);
+        self.instance_pre = OnceLock::new();
+        self
+    }
+
+    /// Get the host functions guests of this host may call
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Restrict the host functions guests may call; all are granted by default
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+        self.instance_pre = OnceLock::new();
+    }
+
+    /// Serve `now_millis` from `source` instead of the wall clock
+    ///
+    /// Executions with a deterministic timestamp in their context ignore the
+    /// source and see that timestamp instead.
+    pub fn set_time_source(&mut self, source: Arc<dyn TimeSource>) {
+        self.time_source = source;
+    }
+
     /// Execute a WASM module with the given context
     pub fn execute(&self, context: &mut ExecutionContext) -> Result<Vec<u8>, WasmHostError> {
-        // In a real implementation, this would use wasmtime or wasmer to execute the WASM module
-        // For this demo, we'll simulate the execution
-        
+        self.run(context, Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Execute the guest export `export_name` instead of `agent_run`
+    ///
+    /// The export is called with the same signature and input and output
+    /// handling as `agent_run`. Fails with an `ExecutionError` naming the
+    /// export if the module does not export it.
+    pub fn call<'a>(&self, export_name: &'a str, context: &mut ExecutionContext<'a>) -> Result<Vec<u8>, WasmHostError> {
+        context.entry_point = Some(export_name);
+        self.execute(context)
+    }
+
+    /// Execute a WASM module, aborting with `ExecutionError("cancelled")` once `cancel` is set
+    ///
+    /// The flag is checked on every epoch tick, so cancellation takes effect
+    /// within a few tens of milliseconds even for guests stuck in a loop.
+    pub fn execute_cancellable(
+        &self,
+        context: &mut ExecutionContext,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<u8>, WasmHostError> {
+        let cancelled = Arc::new(AtomicBool::new(cancel.load(Ordering::Acquire)));
+        let done = AtomicBool::new(false);
+
+        // The store needs an owned flag, so mirror the caller's flag into it
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                while !done.load(Ordering::Acquire) {
+                    if cancel.load(Ordering::Acquire) {
+                        cancelled.store(true, Ordering::Release);
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(EPOCH_TICK_MS));
+                }
+            });
+
+            let result = self.run(context, cancelled.clone());
+            done.store(true, Ordering::Release);
+            result
+        })
+    }
+
+    fn run(&self, context: &mut ExecutionContext, cancelled: Arc<AtomicBool>) -> Result<Vec<u8>, WasmHostError> {
+        let _log_scope = LogScope::enter(context.agent_id);
+
         // Log execution start
-        log::info!("Executing WASM module: {}", self.module_path);
-        log::info!("Agent ID: {}", context.agent_id);
-        log::info!("Input size: {} bytes", context.input.len());
-        
-        // Simulate state access
-        let state = context.state.lock().map_err(|e| {
-            WasmHostError::ExecutionError(format!("Failed to lock state: {}", e))
-        })?;
-        
-        // In a real implementation, this would execute the WASM module
-        // For this demo, we'll just echo the input with a prefix
-        let mut result = Vec::new();
-        result.extend_from_slice(b"WASM output: ");
-        result.extend_from_slice(context.input);
-        
+        log::info(&format!("Executing WASM module: {}", self.module_path));
+        log::info(&format!("Input size: {} bytes", context.input.len()));
+
+        // Each execution gets a fresh instance so no guest state leaks between runs
+        let mut sandbox = self.instantiate(context, cancelled)?;
+
+        let entry = context.entry_point.unwrap_or(EXPORT_ENTRY);
+        let result = Self::run_entry(&mut sandbox.store, &sandbox.instance, entry, context.input);
+        context.usage = self.usage(&sandbox.store);
+        context.events = std::mem::take(&mut sandbox.store.data_mut().events);
+        if let Some(output) = &sandbox.output {
+            output.flush_to_log();
+        }
+        let result = result.inspect_err(|e| log::error(&format!("Execution failed: {}", e)))?;
+
         // Log execution end
-        log::info!("Execution completed, output size: {} bytes", result.len());
-        
+        log::info(&format!("Execution completed, output size: {} bytes", result.len()));
+
         Ok(result)
     }
-    
+
+    /// Execute a WASM module on each input in turn, reusing one instance
+    ///
+    /// `context.input` is ignored, while `context.entry_point` picks the
+    /// export to call as in `execute`. Guest memory and globals carry over between
+    /// inputs, while the timeout and fuel limit apply to each input separately.
+    /// Execution stops at the first failure, which is the last element returned.
+    pub fn execute_batch(
+        &self,
+        context: &mut ExecutionContext,
+        inputs: &[&[u8]],
+    ) -> Vec<Result<Vec<u8>, WasmHostError>> {
+        let (results, events) = self.execute_batch_with(context, inputs, || {})
+            .into_iter()
+            .unzip::<_, _, Vec<_>, Vec<Vec<GuestEvent>>>();
+        context.events = events.into_iter().flatten().collect();
+        results
+    }
+
+    /// Execute a batch like `execute_batch`, calling `before_input` before running each input
+    ///
+    /// Each result comes with the proof events its input emitted, and
+    /// `context.events` is left untouched.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn execute_batch_with(
+        &self,
+        context: &mut ExecutionContext,
+        inputs: &[&[u8]],
+        mut before_input: impl FnMut(),
+    ) -> Vec<(Result<Vec<u8>, WasmHostError>, Vec<GuestEvent>)> {
+        let _log_scope = LogScope::enter(context.agent_id);
+        log::info(&format!("Executing WASM module: {}", self.module_path));
+        log::info(&format!("Batch size: {} inputs", inputs.len()));
+
+        let Sandbox { mut store, instance, output: guest_output, _slot: slot } =
+            match self.instantiate(context, Arc::new(AtomicBool::new(false))) {
+                Ok(sandbox) => sandbox,
+                Err(e) => return vec![(Err(e), Vec::new())],
+            };
+
+        let entry = context.entry_point.unwrap_or(EXPORT_ENTRY);
+        let mut results = Vec::with_capacity(inputs.len());
+        context.usage = ResourceUsage::default();
+        for (index, input) in inputs.iter().enumerate() {
+            before_input();
+            let result = self.arm_limits(&mut store)
+                .and_then(|()| Self::run_entry(&mut store, &instance, entry, input));
+            let usage = self.usage(&store);
+            context.usage.fuel_consumed += usage.fuel_consumed;
+            context.usage.host_fuel.accumulate(&usage.host_fuel);
+            context.usage.peak_memory = usage.peak_memory;
+            if let Err(e) = &result {
+                log::error(&format!("Batch input {} failed: {}", index, e));
+            }
+            let failed = result.is_err();
+            results.push((result, std::mem::take(&mut store.data_mut().events)));
+            if failed {
+                break;
+            }
+        }
+        if let Some(output) = &guest_output {
+            output.flush_to_log();
+        }
+        drop(store);
+        drop(slot);
+
+        log::info(&format!("Batch completed, {} of {} inputs executed", results.len(), inputs.len()));
+
+        results
+    }
+
+    /// Fail the next `count` instantiations with a transient `InstantiationError`
+    #[cfg(test)]
+    pub(crate) fn inject_instantiation_failures(&self, count: u32) {
+        self.injected_failures.store(count, Ordering::Release);
+    }
+
+    /// Create a store for one execution and instantiate the module in it
+    ///
+    /// With an instance pool this waits for a free slot first.
+    fn instantiate(
+        &self,
+        context: &ExecutionContext,
+        cancelled: Arc<AtomicBool>,
+    ) -> Result<Sandbox, WasmHostError> {
+        #[cfg(test)]
+        if self.injected_failures.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1)).is_ok() {
+            return Err(WasmHostError::InstantiationError("Injected failure".to_string()));
+        }
+
+        let instance_pre = self.instance_pre()?;
+        let slot = self.engine.pool.as_ref().map(|pool| pool.checkout());
+        let (wasi, guest_output) = match &self.wasi {
+            Some(config) => {
+                let (ctx, output) = config.build()?;
+                (Some(ctx), Some(output))
+            }
+            None => (None, None),
+        };
+
+        let host_state = HostState {
+            limiter: MemoryLimiter {
+                max_bytes: self.memory_limit,
+                exceeded: false,
+                peak_bytes: 0,
+            },
+            cancelled,
+            elapsed_ticks: 0,
+            wasi,
+            state: context.state.clone(),
+            events: Vec::new(),
+            agent_id: context.agent_id.to_string(),
+            rng: seeded_rng(context.agent_id, context.input),
+            clock: match context.deterministic_timestamp {
+                Some(timestamp) => Arc::new(FixedClock(timestamp.saturating_mul(1000))),
+                None => self.time_source.clone(),
+            },
+            host_fuel: HostFuel::default(),
+        };
+        let mut store = Store::new(&self.engine.engine, host_state);
+        store.limiter(|state| &mut state.limiter);
+
+        // Check for cancellation on every tick and trap once the timeout elapses
+        let timeout_ticks = self.timeout_ticks();
+        store.epoch_deadline_callback(move |mut ctx| {
+            let state = ctx.data_mut();
+            if state.cancelled.load(Ordering::Acquire) {
+                return Err(Cancelled.into());
+            }
+            state.elapsed_ticks += 1;
+            if state.elapsed_ticks >= timeout_ticks {
+                return Err(Trap::Interrupt.into());
+            }
+            Ok(UpdateDeadline::Continue(1))
+        });
+        self.arm_limits(&mut store)?;
+
+        let instance = instance_pre.instantiate(&mut store).map_err(|e| {
+            // Limit hits and traps in the start function are reported like guest calls
+            if store.data().limiter.exceeded || e.downcast_ref::<Trap>().is_some() || is_cancelled(&e) {
+                map_call_error(&store, e, "Failed to instantiate module")
+            } else {
+                WasmHostError::InstantiationError(format!("Failed to instantiate module: {}", e))
+            }
+        })?;
+
+        Ok(Sandbox { store, instance, output: guest_output, _slot: slot })
+    }
+
+    /// Get the module linked against the host functions, linking it on first use
+    fn instance_pre(&self) -> Result<&InstancePre<HostState>, WasmHostError> {
+        if let Some(instance_pre) = self.instance_pre.get() {
+            return Ok(instance_pre);
+        }
+        let instance_pre = self.linker()?.instantiate_pre(&self.module).map_err(|e| {
+            WasmHostError::LinkError(format!("Failed to link module: {}", e))
+        })?;
+        Ok(self.instance_pre.get_or_init(|| instance_pre))
+    }
+
+    /// Resources consumed since the limits were last armed
+    fn usage(&self, store: &Store<HostState>) -> ResourceUsage {
+        let remaining = store.get_fuel().unwrap_or(0);
+        ResourceUsage {
+            fuel_consumed: self.fuel_limit.unwrap_or(u64::MAX) - remaining,
+            host_fuel: store.data().host_fuel,
+            peak_memory: store.data().limiter.peak_bytes,
+        }
+    }
+
+    /// Restart the timeout and refill the fuel before running guest code
+    fn arm_limits(&self, store: &mut Store<HostState>) -> Result<(), WasmHostError> {
+        store.data_mut().elapsed_ticks = 0;
+        store.data_mut().host_fuel = HostFuel::default();
+        store.set_epoch_deadline(1);
+        store.set_fuel(self.fuel_limit.unwrap_or(u64::MAX)).map_err(|e| {
+            WasmHostError::InstantiationError(format!("Failed to set fuel: {}", e))
+        })
+    }
+
+    /// Build the linker providing the host imports available to guests
+    fn linker(&self) -> Result<Linker<HostState>, WasmHostError> {
+        let mut linker = Linker::new(&self.engine.engine);
+
+        host_functions::add_to_linker(&mut linker, self.capabilities).map_err(|e| {
+            WasmHostError::LinkError(format!("Failed to link host functions: {}", e))
+        })?;
+
+        if self.wasi.is_some() {
+            preview1::add_to_linker_sync(&mut linker, |state: &mut HostState| {
+                state.wasi.as_mut().expect("WASI context missing for WASI-enabled host")
+            })
+            .map_err(|e| {
+                WasmHostError::LinkError(format!("Failed to link WASI: {}", e))
+            })?;
+        }
+
+        Ok(linker)
+    }
+
+    /// Copy the input into guest memory, call the `entry` export, and read back the output
+    fn run_entry(
+        store: &mut Store<HostState>,
+        instance: &Instance,
+        entry: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>, WasmHostError> {
+        // Every input gets its own random stream, even within a batch
+        let rng = seeded_rng(&store.data().agent_id, input);
+        store.data_mut().rng = rng;
+
+        let memory = instance.get_memory(&mut *store, EXPORT_MEMORY).ok_or_else(|| {
+            WasmHostError::LinkError(format!("Module does not export '{}'", EXPORT_MEMORY))
+        })?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut *store, EXPORT_ALLOC)
+            .map_err(|e| WasmHostError::LinkError(format!("Missing '{}' export: {}", EXPORT_ALLOC, e)))?;
+        let entry = instance
+            .get_func(&mut *store, entry)
+            .ok_or_else(|| WasmHostError::ExecutionError(format!("Module does not export function '{}'", entry)))?
+            .typed::<(i32, i32), i64>(&*store)
+            .map_err(|e| WasmHostError::ExecutionError(format!("Export '{}' has the wrong signature: {}", entry, e)))?;
+
+        let input_len = i32::try_from(input.len()).map_err(|_| {
+            WasmHostError::MemoryError(format!("Input too large: {} bytes", input.len()))
+        })?;
+
+        // Hand the input to the guest
+        let input_ptr = alloc.call(&mut *store, input_len).map_err(|e| {
+            map_call_error(store, e, "Guest allocation failed")
+        })?;
+        write_guest(&mut *store, &memory, input_ptr, input)?;
+
+        // Run the agent
+        let packed = entry.call(&mut *store, (input_ptr, input_len)).map_err(|e| {
+            map_call_error(store, e, "Guest trapped")
+        })?;
+
+        let out_ptr = ((packed as u64) >> 32) as u32;
+        let out_len = (packed as u64 & 0xFFFF_FFFF) as u32;
+        read_guest(&*store, &memory, out_ptr, out_len)
+    }
+
+    /// Number of epoch ticks before the configured timeout elapses
+    ///
+    /// One extra tick is added since the first tick may arrive almost immediately.
+    fn timeout_ticks(&self) -> u64 {
+        self.execution_timeout_ms.div_ceil(EPOCH_TICK_MS) + 1
+    }
+
+    /// Get the base64 SHA-256 of the loaded module's binary encoding
+    pub fn module_hash(&self) -> &str {
+        &self.module_hash
+    }
+
     /// Get the memory limit for this WASM host
     pub fn memory_limit(&self) -> usize {
         self.memory_limit
     }
-    
+
     /// Get the execution timeout for this WASM host
     pub fn execution_timeout_ms(&self) -> u64 {
         self.execution_timeout_ms
     }
-    
+
     /// Set the execution timeout for this WASM host
     pub fn set_execution_timeout_ms(&mut self, timeout_ms: u64) {
         self.execution_timeout_ms = timeout_ms;
     }
+
+    /// Set the memory limit for this WASM host, clamped to at least one page
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = limit.max(WASM_PAGE_SIZE);
+    }
+
+    /// Get the fuel limit for this WASM host, if any
+    pub fn fuel_limit(&self) -> Option<u64> {
+        self.fuel_limit
+    }
+
+    /// Limit each execution to `fuel` units, roughly one per instruction executed
+    pub fn set_fuel_limit(&mut self, fuel: u64) {
+        self.fuel_limit = Some(fuel);
+    }
+
+    /// Get the maximum WASM stack size for this host, in bytes
+    pub fn stack_size(&self) -> usize {
+        self.engine_key.stack_size
+    }
+
+    /// Set the maximum WASM stack size, clamped to between 32 KiB and 1 MiB
+    ///
+    /// Guests that recurse past the limit fail with
+    /// `WasmHostError::Trap(TrapCode::StackOverflow, ..)` instead of
+    /// overflowing the host's stack. Changing the limit recompiles
+    /// the module for an engine with that limit.
+    pub fn set_stack_size(&mut self, stack_size: usize) -> Result<(), WasmHostError> {
+        let stack_size = stack_size.clamp(MIN_WASM_STACK_SIZE, MAX_WASM_STACK_SIZE);
+        self.switch_engine(EngineKey { stack_size, ..self.engine_key })
+    }
+
+    /// Get the number of instance slots executions draw from, if pooling is on
+    pub fn instance_pool_size(&self) -> Option<u32> {
+        self.engine_key.pool_size
+    }
+
+    /// Run executions in a pool of `size` preallocated instance slots, or `None` for on-demand instances
+    ///
+    /// Pooled slots are reused, so instantiation skips most memory setup and
+    /// gets several times cheaper; wasmtime resets a slot's memory before
+    /// reuse. At most `size` executions run at once across all hosts with the
+    /// same stack and pool size, and any more wait for a free slot. Pooled
+    /// modules may define at most one memory and one table. The size is
+    /// clamped to between 1 and 1000, and changing it recompiles the module.
+    pub fn set_instance_pool_size(&mut self, size: Option<u32>) -> Result<(), WasmHostError> {
+        let pool_size = size.map(|size| size.clamp(1, MAX_INSTANCE_POOL_SIZE));
+        self.switch_engine(EngineKey { pool_size, ..self.engine_key })
+    }
+
+    /// Recompile the module for the engine with `engine_key`
+    fn switch_engine(&mut self, engine_key: EngineKey) -> Result<(), WasmHostError> {
+        if engine_key == self.engine_key {
+            return Ok(());
+        }
+        let engine = engine_for(engine_key)?;
+        self.module = Self::compile_cached(&engine.engine, engine_key, &self.module_bytes, &self.module_path)?;
+        self.engine = engine;
+        self.engine_key = engine_key;
+        self.instance_pre = OnceLock::new();
+        Ok(())
+    }
+}
+
+/// Whether the bytes look like a WebAssembly text module
+fn is_wat_text(module_bytes: &[u8]) -> bool {
+    module_bytes.trim_ascii_start().starts_with(b"(module")
+}
+
+/// Assemble a WebAssembly text module into its binary encoding
+fn parse_wat(module_bytes: &[u8], module_path: &str) -> Result<Vec<u8>, WasmHostError> {
+    wat::parse_bytes(module_bytes)
+        .map(|binary| binary.into_owned())
+        .map_err(|e| {
+            WasmHostError::ModuleLoadError(format!("Failed to parse {}: {}", module_path, e))
+        })
+}
+
+/// Check that the bytes start with the WebAssembly magic and a supported version
+fn validate_header(module_bytes: &[u8], module_path: &str) -> Result<(), WasmHostError> {
+    if module_bytes.len() < 8 || module_bytes[..4] != WASM_MAGIC {
+        return Err(WasmHostError::ModuleLoadError(format!(
+            "{} is not a WebAssembly module", module_path
+        )));
+    }
+    if module_bytes[4..8] != WASM_VERSION {
+        return Err(WasmHostError::ModuleLoadError(format!(
+            "{} has unsupported WebAssembly version {:?}", module_path, &module_bytes[4..8]
+        )));
+    }
+    Ok(())
+}
+
+/// Whether an error was raised by the epoch deadline
+fn is_timeout(error: &wasmtime::Error) -> bool {
+    matches!(error.downcast_ref::<Trap>(), Some(Trap::Interrupt))
+}
+
+/// Whether an error was raised by cancelling the execution
+fn is_cancelled(error: &wasmtime::Error) -> bool {
+    error.downcast_ref::<Cancelled>().is_some()
+}
+
+/// Whether an error was raised by running out of fuel
+fn is_out_of_fuel(error: &wasmtime::Error) -> bool {
+    matches!(error.downcast_ref::<Trap>(), Some(Trap::OutOfFuel))
+}
+
+/// Map an error from a guest call onto a host error
+fn map_call_error(store: &Store<HostState>, error: wasmtime::Error, context: &str) -> WasmHostError {
+    if store.data().limiter.exceeded {
+        WasmHostError::MemoryError(format!("{}: {}", context, error.root_cause()))
+    } else if is_timeout(&error) {
+        WasmHostError::ExecutionError("timeout".to_string())
+    } else if is_cancelled(&error) {
+        WasmHostError::ExecutionError("cancelled".to_string())
+    } else if is_out_of_fuel(&error) {
+        WasmHostError::ExecutionError(format!("{}: fuel exhausted", context))
+    } else {
+        WasmHostError::Trap(TrapCode::of(&error), format!("{}: {}", context, error.root_cause()))
+    }
+}
+
+/// Write bytes into guest memory at the given offset
+pub(crate) fn write_guest(
+    store: impl AsContextMut,
+    memory: &Memory,
+    ptr: i32,
+    data: &[u8],
+) -> Result<(), WasmHostError> {
+    memory.write(store, ptr as u32 as usize, data).map_err(|_| {
+        WasmHostError::MemoryError(format!(
+            "Guest buffer at {} too small for {} bytes", ptr as u32, data.len()
+        ))
+    })
+}
+
+/// Domain separator for the `random_fill` seed
+const RANDOM_SEED_DOMAIN: &[u8] = b"korra-random-v1";
+
+/// Seed the ChaCha20 stream behind `random_fill` for one input
+///
+/// The seed is `SHA-256(RANDOM_SEED_DOMAIN || 0x00 || agent_id || 0x00 ||
+/// SHA-256(input))`, so the same agent id and input always give the same
+/// stream. Changing this derivation changes every guest's random output.
+fn seeded_rng(agent_id: &str, input: &[u8]) -> ChaCha20Rng {
+    let seed = Sha256::new()
+        .chain_update(RANDOM_SEED_DOMAIN)
+        .chain_update([0])
+        .chain_update(agent_id.as_bytes())
+        .chain_update([0])
+        .chain_update(Sha256::digest(input))
+        .finalize();
+    ChaCha20Rng::from_seed(seed.into())
 }
 
-// Mock implementation of log crate
-mod log {
+/// Read bytes out of guest memory at the given offset
+///
+/// The range is checked against the memory size before anything is
+/// allocated, so a guest-supplied length cannot make the host allocate more
+/// than the guest's own memory.
+pub(crate) fn read_guest(
+    store: impl AsContext,
+    memory: &Memory,
+    ptr: u32,
+    len: u32,
+) -> Result<Vec<u8>, WasmHostError> {
+    let out_of_bounds = || WasmHostError::MemoryError(format!(
+        "Guest output at {} with length {} is out of bounds", ptr, len
+    ));
+    if ptr as u64 + len as u64 > memory.data_size(&store) as u64 {
+        return Err(out_of_bounds());
+    }
+
+    let mut buf = vec![0u8; len as usize];
+    memory.read(store, ptr as usize, &mut buf).map_err(|_| out_of_bounds())?;
+    Ok(buf)
+}
+
+/// Stand-in for the `log` crate that forwards to the host log callback
+pub(crate) mod log {
+    pub fn debug(msg: &str) {
+        crate::log_debug(msg);
+    }
+
     pub fn info(msg: &str) {
         crate::log_info(msg);
     }
-}
\ No newline at end of file
+
+    pub fn warn(msg: &str) {
+        crate::log_warn(msg);
+    }
+
+    pub fn error(msg: &str) {
+        crate::log_error(msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::engine::agent::AgentType;
+
+    /// Guest that spins forever without calling the host
+    const SPIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "alloc") (param i32) (result i32) (i32.const 0))
+        (func (export "agent_run") (param i32 i32) (result i64)
+            (loop $spin (br $spin))
+            (i64.const 0)))"#;
+
+    /// Bumps a private global and a memory byte, then grows memory by a page;
+    /// outputs the counters and the memory size it started with
+    const COUNTERS: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $runs (mut i32) (i32.const 0))
+        (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+        (func (export "agent_run") (param i32 i32) (result i64)
+            (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+            (i32.store8 (i32.const 0) (global.get $runs))
+            (i32.store8 (i32.const 1) (i32.add (i32.load8_u (i32.const 1)) (i32.const 1)))
+            (i32.store8 (i32.const 2) (memory.grow (i32.const 1)))
+            (i64.const 3)))"#;
+
+    fn context(input: &[u8]) -> ExecutionContext<'_> {
+        ExecutionContext {
+            agent_id: "test-agent",
+            agent_type: AgentType::Custom,
+            input,
+            state: Arc::new(Mutex::new(StateStore::new())),
+            usage: ResourceUsage::default(),
+            proof_hasher: None,
+            events: Vec::new(),
+            deterministic_timestamp: None,
+            entry_point: None,
+        }
+    }
+
+    fn run(host: &WasmHost, input: &[u8]) -> Result<Vec<u8>, WasmHostError> {
+        host.execute(&mut context(input))
+    }
+
+    /// Module file in the temp directory, removed when dropped
+    struct TempModule(PathBuf);
+
+    impl TempModule {
+        fn new(extension: &str, contents: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("korra-{}.{}", uuid::Uuid::new_v4(), extension));
+            fs::write(&path, contents).unwrap();
+            TempModule(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempModule {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn spinning_guest_times_out() {
+        let mut host = WasmHost::from_bytes(SPIN.as_bytes()).unwrap();
+        host.set_execution_timeout_ms(100);
+
+        let started = Instant::now();
+        let result = run(&host, b"input");
+        let elapsed = started.elapsed();
+
+        assert!(matches!(result, Err(WasmHostError::ExecutionError(msg)) if msg == "timeout"));
+        assert!(elapsed >= Duration::from_millis(100), "aborted early after {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "aborted late after {:?}", elapsed);
+    }
+
+    #[test]
+    fn guest_state_never_carries_over_between_executions() {
+        let mut pooled = WasmHost::from_bytes(COUNTERS.as_bytes()).unwrap();
+        pooled.set_instance_pool_size(Some(1)).unwrap();
+
+        for host in [WasmHost::from_bytes(COUNTERS.as_bytes()).unwrap(), pooled] {
+            for _ in 0..3 {
+                assert_eq!(run(&host, b"input").unwrap(), [1, 1, 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn module_cache_evicts_the_least_recently_used_module() {
+        let host = WasmHost::from_bytes(SPIN.as_bytes()).unwrap();
+        let mut cache = ModuleCache::default();
+        for i in 0..MODULE_CACHE_CAPACITY {
+            cache.insert(i.to_string(), host.module.clone());
+        }
+
+        assert!(cache.get("0").is_some());
+        cache.insert("new".to_string(), host.module.clone());
+
+        assert_eq!(cache.entries.len(), MODULE_CACHE_CAPACITY);
+        assert!(cache.get("0").is_some());
+        assert!(cache.get("1").is_none());
+        assert!(cache.get("new").is_some());
+    }
+
+    #[test]
+    fn unbounded_recursion_traps_with_stack_overflow() {
+        const RECURSE: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func $recurse (param i32) (result i32)
+                (i32.add (call $recurse (i32.add (local.get 0) (i32.const 1))) (i32.const 1)))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (i64.extend_i32_u (call $recurse (i32.const 0)))))"#;
+        let mut host = WasmHost::from_bytes(RECURSE.as_bytes()).unwrap();
+        host.set_stack_size(64 * 1024).unwrap();
+
+        let result = run(&host, b"input");
+        assert!(matches!(result, Err(WasmHostError::Trap(TrapCode::StackOverflow, _))), "{:?}", result);
+    }
+
+    #[test]
+    fn growing_past_the_memory_limit_is_a_memory_error() {
+        const GROW: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (memory.grow (i32.const 4)))
+                (i64.const 0)))"#;
+        let mut host = WasmHost::from_bytes(GROW.as_bytes()).unwrap();
+        host.set_memory_limit(2 * WASM_PAGE_SIZE);
+
+        let result = run(&host, b"input");
+        assert!(matches!(result, Err(WasmHostError::MemoryError(_))), "{:?}", result);
+
+        host.set_memory_limit(0);
+        assert_eq!(host.memory_limit(), WASM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn guest_writes_and_reads_back_its_own_state() {
+        const STATE_ROUND_TRIP: &str = r#"(module
+            (import "korra" "state_set" (func $state_set (param i32 i32) (result i32)))
+            (import "korra" "state_get" (func $state_get (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\03\00\00\00key")
+            (data (i32.const 16) "\05\00\00\00value")
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (local $found i32)
+                (drop (call $state_set (i32.const 0) (i32.const 16)))
+                (local.set $found (call $state_get (i32.const 0)))
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (i32.add (local.get $found) (i32.const 4))) (i64.const 32))
+                    (i64.extend_i32_u (i32.load (local.get $found))))))"#;
+        let host = WasmHost::from_bytes(STATE_ROUND_TRIP.as_bytes()).unwrap();
+        let mut context = context(b"input");
+
+        assert_eq!(host.execute(&mut context).unwrap(), b"value");
+        assert_eq!(context.state.lock().unwrap().get("key"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn files_without_the_wasm_header_are_rejected() {
+        let empty = TempModule::new("wasm", b"");
+        let text = TempModule::new("wasm", b"just some notes");
+        let valid = TempModule::new("wasm", &wat::parse_str(SPIN).unwrap());
+
+        for module in [&empty, &text] {
+            let result = WasmHost::new(module.path());
+            assert!(
+                matches!(&result, Err(WasmHostError::ModuleLoadError(msg)) if msg.contains("not a WebAssembly module")),
+                "{:?}", result.err()
+            );
+        }
+        assert!(WasmHost::new(valid.path()).is_ok());
+    }
+
+    #[test]
+    fn wat_text_modules_load_and_run() {
+        const ECHO: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param $ptr i32) (param $len i32) (result i64)
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len)))))"#;
+        let module = TempModule::new("wat", ECHO.as_bytes());
+
+        let host = WasmHost::new(module.path()).unwrap();
+        assert_eq!(run(&host, b"hello").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn execution_errors_are_logged_at_error_level() {
+        const TRAP: &str = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param i32 i32) (result i64) unreachable))"#;
+        let _settings = crate::test_support::lock_log_settings();
+        let host = WasmHost::from_bytes(TRAP.as_bytes()).unwrap();
+        crate::test_support::take_logs();
+
+        assert!(run(&host, b"input").is_err());
+
+        let logs = crate::test_support::take_logs();
+        assert!(
+            logs.iter().any(|(level, line)| *level == crate::LOG_LEVEL_ERROR && line.contains("Execution failed")),
+            "{:?}", logs
+        );
+    }
+
+    #[test]
+    fn state_writes_need_the_state_write_capability() {
+        const WRITE_STATE: &str = r#"(module
+            (import "korra" "state_set" (func $state_set (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\03\00\00\00key")
+            (data (i32.const 16) "\05\00\00\00value")
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $state_set (i32.const 0) (i32.const 16)))
+                (i64.const 0)))"#;
+        let mut host = WasmHost::from_bytes(WRITE_STATE.as_bytes()).unwrap();
+
+        host.set_capabilities(Capabilities::STATE_READ);
+        let mut denied = context(b"input");
+        let error = host.execute(&mut denied).unwrap_err();
+        assert!(error.to_string().contains("requires the STATE_WRITE capability"), "{}", error);
+        assert_eq!(denied.state.lock().unwrap().get("key"), None);
+
+        host.set_capabilities(Capabilities::STATE_READ | Capabilities::STATE_WRITE);
+        let mut granted = context(b"input");
+        host.execute(&mut granted).unwrap();
+        assert_eq!(granted.state.lock().unwrap().get("key"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn random_streams_are_reproducible_per_input() {
+        const RANDOM_BYTES: &str = r#"(module
+            (import "korra" "random_fill" (func $random_fill (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $random_fill (i32.const 0) (i32.const 32)))
+                (i64.const 32)))"#;
+        let host = WasmHost::from_bytes(RANDOM_BYTES.as_bytes()).unwrap();
+
+        let first = run(&host, b"input").unwrap();
+        assert_eq!(first.len(), 32);
+        assert_eq!(run(&host, b"input").unwrap(), first);
+        assert_ne!(run(&host, b"other input").unwrap(), first);
+    }
+
+    #[test]
+    fn guests_read_the_configured_time_source() {
+        const READ_CLOCK: &str = r#"(module
+            (import "korra" "now_millis" (func $now_millis (result i64)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (i64.store (i32.const 0) (call $now_millis))
+                (i64.const 8)))"#;
+        let mut host = WasmHost::from_bytes(READ_CLOCK.as_bytes()).unwrap();
+        host.set_time_source(Arc::new(FixedClock(1_234_567)));
+
+        assert_eq!(run(&host, b"input").unwrap(), 1_234_567u64.to_le_bytes());
+
+        let mut deterministic = context(b"input");
+        deterministic.deterministic_timestamp = Some(1_700_000_000);
+        assert_eq!(host.execute(&mut deterministic).unwrap(), 1_700_000_000_000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn only_correctly_signed_modules_load() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let module = SPIN.as_bytes();
+        let signature = key.sign(module).to_bytes();
+
+        let host = WasmHost::new_verified(module, &signature, &public_key).unwrap();
+        assert_eq!(host.module_hash(), WasmHost::from_bytes(module).unwrap().module_hash());
+
+        let tampered = format!("{} ", SPIN);
+        let other_key = SigningKey::from_bytes(&[4u8; 32]).verifying_key().to_bytes();
+        for result in [
+            WasmHost::new_verified(tampered.as_bytes(), &signature, &public_key),
+            WasmHost::new_verified(module, &signature, &other_key),
+            WasmHost::new_verified(module, &signature[..10], &public_key),
+        ] {
+            assert!(
+                matches!(&result, Err(WasmHostError::ModuleLoadError(msg)) if msg == "signature verification failed"),
+                "{:?}", result.err()
+            );
+        }
+    }
+
+    #[test]
+    fn module_hashes_identify_the_file_contents() {
+        let spin = TempModule::new("wat", SPIN.as_bytes());
+        let counters = TempModule::new("wat", COUNTERS.as_bytes());
+
+        let first = WasmHost::new(spin.path()).unwrap();
+        let second = WasmHost::new(spin.path()).unwrap();
+        let other = WasmHost::new(counters.path()).unwrap();
+
+        assert_eq!(first.module_hash(), second.module_hash());
+        assert_ne!(first.module_hash(), other.module_hash());
+        assert_eq!(first.module_hash().len(), 44);
+    }
+
+    #[test]
+    fn oversized_length_prefixes_are_rejected_before_allocating() {
+        const HUGE_VALUE: &str = r#"(module
+            (import "korra" "state_set" (func $state_set (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\03\00\00\00key")
+            (data (i32.const 16) "\ff\ff\ff\ff")
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $state_set (i32.const 0) (i32.const 16)))
+                (i64.const 0)))"#;
+        let host = WasmHost::from_bytes(HUGE_VALUE.as_bytes()).unwrap();
+        let mut context = context(b"input");
+
+        let error = host.execute(&mut context).unwrap_err();
+        assert!(error.to_string().contains("out of bounds"), "{}", error);
+        assert_eq!(context.state.lock().unwrap().get("key"), None);
+    }
+
+    #[test]
+    fn proof_events_are_bounds_checked_and_paid_for_before_copying() {
+        const LARGE_EVENT: &str = r#"(module
+            (import "korra" "emit_proof_event" (func $emit (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (drop (call $emit (i32.const 0) (i32.const 0) (i32.const 0) (i32.load (i32.const 0))))
+                (i64.const 0)))"#;
+        let mut host = WasmHost::from_bytes(LARGE_EVENT.as_bytes()).unwrap();
+
+        let mut out_of_bounds = context(&[0xff, 0xff, 0xff, 0xff]);
+        let error = host.execute(&mut out_of_bounds).unwrap_err();
+        assert!(error.to_string().contains("out of bounds"), "{}", error);
+        assert!(out_of_bounds.events.is_empty());
+
+        host.set_fuel_limit(10_000);
+        let large = 60_000u32.to_le_bytes();
+        let mut unaffordable = context(&large);
+        let error = host.execute(&mut unaffordable).unwrap_err();
+        assert!(error.to_string().contains("fuel exhausted"), "{}", error);
+        assert!(unaffordable.events.is_empty());
+    }
+
+    #[test]
+    fn guest_traps_are_classified_by_cause() {
+        let cases = [
+            ("unreachable", TrapCode::Unreachable),
+            ("(drop (i32.div_s (i32.const 1) (i32.const 0)))", TrapCode::IntegerDivisionByZero),
+            ("(drop (i32.load (i32.const 65536)))", TrapCode::MemoryOutOfBounds),
+        ];
+        for (body, expected) in cases {
+            let wat = format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                    (func (export "agent_run") (param i32 i32) (result i64)
+                        {}
+                        (i64.const 0)))"#,
+                body
+            );
+            let host = WasmHost::from_bytes(wat.as_bytes()).unwrap();
+
+            let result = run(&host, b"input");
+            assert!(matches!(result, Err(WasmHostError::Trap(code, _)) if code == expected), "{}: {:?}", body, result);
+        }
+    }
+
+    #[test]
+    fn call_runs_the_named_export() {
+        const TWO_EXPORTS: &str = r#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 100) "ready")
+            (data (i32.const 110) "ran")
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "init") (param i32 i32) (result i64)
+                (i64.const 429496729605))
+            (func (export "agent_run") (param i32 i32) (result i64)
+                (i64.const 472446402563)))"#;
+        let host = WasmHost::from_bytes(TWO_EXPORTS.as_bytes()).unwrap();
+
+        assert_eq!(host.call("init", &mut context(b"input")).unwrap(), b"ready");
+        assert_eq!(run(&host, b"input").unwrap(), b"ran");
+
+        let result = host.call("missing", &mut context(b"input"));
+        assert!(
+            matches!(&result, Err(WasmHostError::ExecutionError(msg)) if msg.contains("'missing'")),
+            "{:?}",
+            result
+        );
+    }
+}
@@ -1,149 +1,1843 @@
 //! State store and snapshot logic
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{mpsc, Arc, RwLock, Weak};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use base64::{Engine as _, engine::general_purpose};
+use im::HashMap;
+
+use crate::state::cipher::ValueCipher;
+use crate::state::log::{LoggedValue, LogRecord, StateLog};
+
+/// Persistent map of values: clones share structure, so snapshots are cheap
+/// and only diverge from the live map as entries are mutated
+type Values = HashMap<String, StoredValue>;
+
+/// A value as held in memory, logged, and snapshotted
+#[derive(Clone)]
+enum StoredValue {
+    Raw(Arc<[u8]>),
+    Zstd { frame: Arc<[u8]>, raw_len: usize },
+}
+
+impl StoredValue {
+    /// Store `value`, compressed if it reaches `threshold` and compression helps
+    fn encode(value: &[u8], threshold: Option<usize>) -> Self {
+        if threshold.is_some_and(|min| value.len() >= min) {
+            if let Ok(frame) = zstd::bulk::compress(value, 0) {
+                if frame.len() < value.len() {
+                    return StoredValue::Zstd { frame: Arc::from(frame), raw_len: value.len() };
+                }
+            }
+        }
+        StoredValue::Raw(Arc::from(value))
+    }
+    
+    /// Rebuild a value read back from the log, checking compressed frames
+    fn from_logged(value: LoggedValue) -> io::Result<Self> {
+        match value {
+            LoggedValue::Raw(bytes) => Ok(StoredValue::Raw(Arc::from(bytes))),
+            LoggedValue::Zstd(frame) => {
+                let raw_len = zstd::stream::decode_all(&frame[..])?.len();
+                Ok(StoredValue::Zstd { frame: Arc::from(frame), raw_len })
+            }
+        }
+    }
+    
+    /// Get the value's bytes, decompressing if needed
+    fn to_vec(&self) -> io::Result<Vec<u8>> {
+        match self {
+            StoredValue::Raw(bytes) => Ok(bytes.to_vec()),
+            StoredValue::Zstd { frame, .. } => zstd::stream::decode_all(&frame[..]),
+        }
+    }
+    
+    /// Get the value's bytes, logging and skipping a frame that fails to decompress
+    ///
+    /// Frames are checked on write and replay, so this only fails if memory
+    /// was corrupted; the value then reads as absent.
+    fn read(&self, key: &str) -> Option<Vec<u8>> {
+        match self.to_vec() {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                crate::log_warn(&format!("Skipping unreadable compressed value for key '{}': {}", key, e));
+                None
+            }
+        }
+    }
+    
+    /// Length of the value itself
+    fn len(&self) -> usize {
+        match self {
+            StoredValue::Raw(bytes) => bytes.len(),
+            StoredValue::Zstd { raw_len, .. } => *raw_len,
+        }
+    }
+    
+    /// Bytes the value occupies as stored
+    fn stored_len(&self) -> usize {
+        match self {
+            StoredValue::Raw(bytes) => bytes.len(),
+            StoredValue::Zstd { frame, .. } => frame.len(),
+        }
+    }
+    
+    /// Address of the stored bytes, shared by every clone of this value
+    fn as_ptr(&self) -> *const u8 {
+        match self {
+            StoredValue::Raw(bytes) => bytes.as_ptr(),
+            StoredValue::Zstd { frame, .. } => frame.as_ptr(),
+        }
+    }
+    
+    /// Whether both refer to the same stored bytes, without comparing contents
+    fn ptr_eq(&self, other: &StoredValue) -> bool {
+        match (self, other) {
+            (StoredValue::Raw(a), StoredValue::Raw(b)) => Arc::ptr_eq(a, b),
+            (StoredValue::Zstd { frame: a, .. }, StoredValue::Zstd { frame: b, .. }) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+    
+    /// The log record that sets `key` to this value
+    fn record<'a>(&'a self, key: &'a str) -> LogRecord<'a> {
+        match self {
+            StoredValue::Raw(bytes) => LogRecord::Set(key, bytes),
+            StoredValue::Zstd { frame, .. } => LogRecord::SetCompressed(key, frame),
+        }
+    }
+}
+
+/// Bytes held by a store's entries before and after compression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Size of all keys and uncompressed values, as counted against the quota
+    pub raw_bytes: usize,
+    /// Size of all keys and values as actually stored
+    pub stored_bytes: usize,
+    /// Number of values held compressed
+    pub compressed_values: usize,
+}
+
+/// Error type for state store operations
+#[derive(Debug)]
+pub enum StateError {
+    QuotaExceeded(String),
+    LockPoisoned(String),
+    InvalidNamespace(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::QuotaExceeded(msg) => write!(f, "Quota exceeded: {}", msg),
+            StateError::LockPoisoned(msg) => write!(f, "State lock poisoned: {}", msg),
+            StateError::InvalidNamespace(msg) => write!(f, "Invalid namespace: {}", msg),
+            StateError::Io(e) => write!(f, "Failed to write state log: {}", e),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl From<StateError> for String {
+    fn from(e: StateError) -> Self {
+        e.to_string()
+    }
+}
+
 /// State store for agent state
+///
+/// Stores created with `new` live only in memory. Stores created with `open`
+/// also write every mutation ahead to a log file before applying it, and
+/// recover from it on reopen; a mutation the log cannot record fails with
+/// `StateError::Io` and leaves the store unchanged. Snapshots are never
+/// persisted.
+///
+/// Keys set with a TTL read as absent once they expire. Expired keys are
+/// purged lazily on the next mutation, or eagerly with `purge_expired`.
 pub struct StateStore {
-    values: HashMap<String, Vec<u8>>,
+    values: Values,
+    expirations: HashMap<String, u64>,
     snapshots: Vec<StateSnapshot>,
     snapshot_limit: usize,
+    next_snapshot_id: u64,
+    log: Option<StateLog>,
+    persistence_error: Option<io::Error>,
+    subscribers: Vec<Subscriber>,
+    max_bytes: Option<usize>,
+    used_bytes: usize,
+    memory_bytes: usize,
+    compression_threshold: Option<usize>,
+}
+
+/// A change to a key, delivered to subscribers after it is visible to `get`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateChange {
+    /// The key was set to a new value
+    Set(String),
+    /// The key was deleted, cleared, or expired
+    Delete(String),
+}
+
+impl StateChange {
+    /// Get the key that changed
+    pub fn key(&self) -> &str {
+        match self {
+            StateChange::Set(key) | StateChange::Delete(key) => key,
+        }
+    }
+}
+
+/// Keys that differ between a snapshot and the current state
+///
+/// Each list is sorted by key. A key absent from the snapshot and present now
+/// is added, even with an empty value. A key present on both sides is
+/// modified if its bytes differ, including changes to or from an empty
+/// value, and unchanged otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Keys missing from the snapshot, with their current values
+    pub added: Vec<(String, Vec<u8>)>,
+    /// Keys missing from the current state, with their snapshot values
+    pub removed: Vec<(String, Vec<u8>)>,
+    /// Keys whose value changed, with their old and new values
+    pub modified: Vec<(String, Vec<u8>, Vec<u8>)>,
+}
+
+impl StateDiff {
+    /// Whether the state matches the snapshot
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Subscriber to changes on keys starting with `prefix`
+struct Subscriber {
+    prefix: String,
+    sender: mpsc::Sender<StateChange>,
+    /// Gone once the `Subscription` is dropped, even if no change ever matches
+    alive: Weak<()>,
+}
+
+/// Changes delivered to one subscription, as returned by `subscribe`
+///
+/// Dereferences to the channel receiver. Dropping it ends the subscription,
+/// and the store forgets it on its next change or subscription.
+pub struct Subscription {
+    receiver: mpsc::Receiver<StateChange>,
+    _alive: Arc<()>,
+}
+
+impl Deref for Subscription {
+    type Target = mpsc::Receiver<StateChange>;
+    
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
 }
 
 /// State snapshot for rollback
-struct StateSnapshot {
+#[derive(Clone)]
+pub(crate) struct StateSnapshot {
+    id: u64,
+    name: Option<String>,
     timestamp: u64,
-    values: HashMap<String, Vec<u8>>,
+    values: Values,
+    used_bytes: usize,
+    memory_bytes: usize,
+    remaining_ttls: HashMap<String, u64>,
 }
 
 impl StateStore {
+    /// Id returned by `create_snapshot` when snapshots are disabled
+    pub const NO_SNAPSHOT: u64 = 0;
+    
+    /// Estimated bytes each entry costs beyond its key and value, counted by `memory_usage`
+    pub const ENTRY_OVERHEAD: usize = 64;
+    
     /// Create a new state store
     pub fn new() -> Self {
         StateStore {
             values: HashMap::new(),
+            expirations: HashMap::new(),
             snapshots: Vec::new(),
             snapshot_limit: 10, // Keep up to 10 snapshots
+            next_snapshot_id: 1,
+            log: None,
+            persistence_error: None,
+            subscribers: Vec::new(),
+            max_bytes: None,
+            used_bytes: 0,
+            memory_bytes: 0,
+            compression_threshold: None,
+        }
+    }
+    
+    /// Create a new state store that keeps at most `limit` snapshots
+    ///
+    /// Snapshots share structure with the live map, so each one costs memory
+    /// proportional to the entries changed since it was taken rather than the
+    /// whole store. A limit of 0 disables snapshots entirely.
+    pub fn with_snapshot_limit(limit: usize) -> Self {
+        StateStore {
+            snapshot_limit: limit,
+            ..Self::new()
+        }
+    }
+    
+    /// Create a new state store holding at most `max_bytes` of keys and values
+    pub fn with_max_bytes(max_bytes: usize) -> Self {
+        StateStore {
+            max_bytes: Some(max_bytes),
+            ..Self::new()
+        }
+    }
+    
+    /// Copy the store for independent use
+    ///
+    /// The copy shares structure with this store, so it is cheap to make and
+    /// only diverges as either side is modified. It keeps the entries, expiry
+    /// times, quota, snapshot limit, and compression threshold, but not
+    /// snapshots, subscribers, or persistence.
+    pub fn fork(&self) -> StateStore {
+        StateStore {
+            values: self.values.clone(),
+            expirations: self.expirations.clone(),
+            snapshot_limit: self.snapshot_limit,
+            max_bytes: self.max_bytes,
+            used_bytes: self.used_bytes,
+            memory_bytes: self.memory_bytes,
+            compression_threshold: self.compression_threshold,
+            ..Self::new()
+        }
+    }
+    
+    /// Get the byte quota, if any
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+    
+    /// Get the total size of all stored keys and values
+    ///
+    /// Values count at their uncompressed size; see `compression_stats` for
+    /// the size actually stored.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+    
+    /// Get the estimated memory held by the live entries
+    ///
+    /// Counts each key, each value as stored (so compressed values count at
+    /// their compressed size), and `ENTRY_OVERHEAD` per entry. Kept up to date
+    /// on every mutation, so this is cheap to call.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_bytes
+    }
+    
+    /// Get the estimated memory held only by snapshots
+    ///
+    /// Entries a snapshot shares with the live store or an earlier snapshot
+    /// are counted once, under whichever holds them first. Unlike
+    /// `memory_usage`, this walks every snapshot.
+    pub fn snapshot_memory_usage(&self) -> usize {
+        let mut seen = std::collections::HashSet::new();
+        let mut total = 0;
+        for snapshot in &self.snapshots {
+            for (key, value) in snapshot.values.iter() {
+                let live = self.values.get(key).is_some_and(|v| v.ptr_eq(value));
+                if !live && seen.insert(value.as_ptr()) {
+                    total += entry_memory(key, value);
+                }
+            }
+        }
+        total
+    }
+    
+    /// Get the minimum size of values stored compressed, if compression is on
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression_threshold
+    }
+    
+    /// Compress values of at least `threshold` bytes with zstd, or `None` to store values raw
+    ///
+    /// Applies to values written from now on, which stay compressed in
+    /// snapshots and the backing log. A value is only kept compressed if that
+    /// makes it smaller. Reads are unaffected either way.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compression_threshold = threshold;
+    }
+    
+    /// Get the size of the entries before and after compression
+    pub fn compression_stats(&self) -> CompressionStats {
+        let mut stats = CompressionStats { raw_bytes: self.used_bytes, ..CompressionStats::default() };
+        for (key, value) in self.values.iter() {
+            stats.stored_bytes += entry_size(key, value.stored_len());
+            if let StoredValue::Zstd { .. } = value {
+                stats.compressed_values += 1;
+            }
+        }
+        stats
+    }
+    
+    /// Open a persistent state store backed by the log file at `path`
+    ///
+    /// The file is created if missing; otherwise all previously written keys
+    /// are recovered from it.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), None)
+    }
+    
+    /// Open a persistent state store whose values are encrypted on disk with `key`
+    ///
+    /// Each value is sealed with a fresh nonce and authenticated; keys and
+    /// expiry times stay readable. Opening an existing log with a different
+    /// key, or one written unencrypted, fails with `InvalidData` rather than
+    /// returning garbage. Values are plaintext in memory and snapshots.
+    pub fn open_encrypted(path: impl AsRef<Path>, key: &[u8]) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), Some(ValueCipher::new(key)))
+    }
+    
+    fn open_with(path: &Path, cipher: Option<ValueCipher>) -> io::Result<Self> {
+        let (log, replayed) = StateLog::open(path, cipher)?;
+        let values = replayed.values.into_iter()
+            .map(|(k, v)| StoredValue::from_logged(v).map(|v| (k, v)))
+            .collect::<io::Result<Values>>()?;
+        Ok(StateStore {
+            used_bytes: values.iter().map(|(k, v)| entry_size(k, v.len())).sum(),
+            memory_bytes: values.iter().map(|(k, v)| entry_memory(k, v)).sum(),
+            values,
+            expirations: replayed.expirations.into_iter().collect(),
+            log: Some(log),
+            ..Self::new()
+        })
+    }
+    
+    /// Whether this store persists its values to disk
+    pub fn is_persistent(&self) -> bool {
+        self.log.is_some()
+    }
+    
+    /// Get the first error hit while writing to the backing log outside a mutation, if any
+    ///
+    /// Mutations report their own log failures. This covers the writes made
+    /// along the way: purging expired keys and rewriting the log after a
+    /// rollback, which keep the in-memory state authoritative when they fail.
+    pub fn persistence_error(&self) -> Option<&io::Error> {
+        self.persistence_error.as_ref()
+    }
+    
+    /// Record a mutation in the backing log, if there is one
+    fn persist(&mut self, record: LogRecord<'_>) -> Result<(), StateError> {
+        match &mut self.log {
+            Some(log) => log.append(record).map_err(StateError::Io),
+            None => Ok(()),
+        }
+    }
+    
+    /// Record mutations that must recover all together, if there is a backing log
+    fn persist_batch<'a>(&mut self, records: impl IntoIterator<Item = LogRecord<'a>>) -> Result<(), StateError> {
+        match &mut self.log {
+            Some(log) => log.append_batch(records).map_err(StateError::Io),
+            None => Ok(()),
         }
     }
     
+    /// Replace the backing log with the current values, if there is one
+    fn persist_all(&mut self) {
+        if let Err(e) = self.flush() {
+            self.persistence_error.get_or_insert(e);
+        }
+    }
+    
+    /// Bring the backing log fully up to date with the in-memory state
+    ///
+    /// Rewrites the log from the current values, which also repairs any
+    /// earlier failed write and clears `persistence_error`. Does nothing for
+    /// in-memory stores.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(log) = &mut self.log {
+            let values = self.values.iter().map(|(k, v)| v.record(k));
+            let expirations = self.expirations.iter().map(|(k, at)| (k.as_str(), *at));
+            log.rewrite(values, expirations)?;
+            self.persistence_error = None;
+        }
+        Ok(())
+    }
+    
+    /// Subscribe to changes on every key starting with `key_or_prefix`
+    ///
+    /// An exact key also matches longer keys it prefixes. Subscriptions end
+    /// when the `Subscription` is dropped.
+    pub fn subscribe(&mut self, key_or_prefix: &str) -> Subscription {
+        self.subscribers.retain(|sub| sub.alive.strong_count() > 0);
+        
+        let (sender, receiver) = mpsc::channel();
+        let alive = Arc::new(());
+        self.subscribers.push(Subscriber {
+            prefix: key_or_prefix.to_string(),
+            sender,
+            alive: Arc::downgrade(&alive),
+        });
+        Subscription { receiver, _alive: alive }
+    }
+    
+    /// Notify matching subscribers, forgetting every subscriber that has been dropped
+    fn notify(&mut self, change: StateChange) {
+        self.subscribers.retain(|sub| {
+            sub.alive.strong_count() > 0
+                && (!change.key().starts_with(&sub.prefix) || sub.sender.send(change.clone()).is_ok())
+        });
+    }
+    
     /// Set a value in the state store
-    pub fn set(&mut self, key: &str, value: &[u8]) {
-        self.values.insert(key.to_string(), value.to_vec());
+    ///
+    /// Fails without changing anything if the write would exceed the quota.
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), StateError> {
+        self.write(key, value, None)
+    }
+    
+    /// Set a value that expires `ttl_secs` seconds from now
+    pub fn set_with_ttl(&mut self, key: &str, value: &[u8], ttl_secs: u64) -> Result<(), StateError> {
+        let expires_at = current_timestamp().saturating_add(ttl_secs);
+        self.write(key, value, Some(expires_at))
+    }
+    
+    /// Set a value only if the current one equals `expected`, with `None` meaning absent
+    ///
+    /// Returns whether the value was written. An expired key counts as absent.
+    /// Fails without changing anything if the write would exceed the quota.
+    pub fn compare_and_swap(&mut self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool, StateError> {
+        if self.get(key).as_deref() != expected {
+            return Ok(false);
+        }
+        self.set(key, new)?;
+        Ok(true)
+    }
+    
+    /// Set a value and its expiry, logging both as one mutation before applying them
+    fn write(&mut self, key: &str, value: &[u8], expires_at: Option<u64>) -> Result<(), StateError> {
+        self.purge_expired();
+        let op = [TransactionOp::Set(key.to_string(), value.to_vec())];
+        self.check_quota(&op, false)?;
+        
+        let stored = StoredValue::encode(value, self.compression_threshold);
+        match expires_at {
+            Some(expires_at) => self.persist_batch([stored.record(key), LogRecord::Expire(key, expires_at)])?,
+            None => self.persist(stored.record(key))?,
+        }
+        match expires_at {
+            Some(expires_at) => self.expirations.insert(key.to_string(), expires_at),
+            None => self.expirations.remove(key),
+        };
+        self.insert_value(key, stored);
+        self.notify(StateChange::Set(key.to_string()));
+        Ok(())
+    }
+    
+    /// Insert a value, keeping the byte counts in step
+    fn insert_value(&mut self, key: &str, value: StoredValue) {
+        self.used_bytes += entry_size(key, value.len());
+        self.memory_bytes += entry_memory(key, &value);
+        if let Some(old) = self.values.insert(key.to_string(), value) {
+            self.used_bytes -= entry_size(key, old.len());
+            self.memory_bytes -= entry_memory(key, &old);
+        }
+    }
+    
+    /// Remove a value, keeping the byte counts in step
+    fn remove_value(&mut self, key: &str) -> bool {
+        match self.values.remove(key) {
+            Some(old) => {
+                self.used_bytes -= entry_size(key, old.len());
+                self.memory_bytes -= entry_memory(key, &old);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Check that applying `ops` would stay within the quota
+    ///
+    /// With `from_empty` the ops are assumed to start from a cleared store.
+    fn check_quota(&self, ops: &[TransactionOp], from_empty: bool) -> Result<(), StateError> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(());
+        };
+        
+        let mut pending: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+        let mut total = if from_empty { 0 } else { self.used_bytes };
+        for op in ops {
+            let key = op.key();
+            let old = match pending.get(key) {
+                Some(size) => *size,
+                None if from_empty => 0,
+                None => self.values.get(key).map_or(0, |v| entry_size(key, v.len())),
+            };
+            let new = match op {
+                TransactionOp::Set(_, value) => entry_size(key, value.len()),
+                TransactionOp::Delete(_) => 0,
+            };
+            pending.insert(key, new);
+            total = total - old + new;
+        }
+        
+        if total > max_bytes {
+            return Err(StateError::QuotaExceeded(format!(
+                "{} bytes needed, limit is {}", total, max_bytes
+            )));
+        }
+        Ok(())
     }
     
     /// Get a value from the state store
     pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        self.values.get(key).cloned()
+        if self.is_expired(key, current_timestamp()) {
+            return None;
+        }
+        self.values.get(key).and_then(|v| v.read(key))
     }
     
-    /// Delete a value from the state store
-    pub fn delete(&mut self, key: &str) -> bool {
-        self.values.remove(key).is_some()
+    /// Get several values at once, leaving out keys that are absent or expired
+    pub fn get_many(&self, keys: &[&str]) -> std::collections::HashMap<String, Vec<u8>> {
+        keys.iter()
+            .filter_map(|key| self.get(key).map(|value| (key.to_string(), value)))
+            .collect()
+    }
+    
+    /// Set several values as one update
+    ///
+    /// Fails without changing anything if the values would exceed the quota.
+    pub fn set_many(&mut self, pairs: &[(&str, &[u8])]) -> Result<(), StateError> {
+        let ops: Vec<TransactionOp> = pairs.iter()
+            .map(|(key, value)| TransactionOp::Set(key.to_string(), value.to_vec()))
+            .collect();
+        self.purge_expired();
+        self.check_quota(&ops, false)?;
+        self.apply(ops, false)
+    }
+    
+    /// Get the seconds left before a key expires, or `None` if it has no TTL
+    pub fn ttl(&self, key: &str) -> Option<u64> {
+        let expires_at = *self.expirations.get(key)?;
+        Some(expires_at.saturating_sub(current_timestamp()))
+    }
+    
+    /// Remove every expired key, returning how many were removed
+    pub fn purge_expired(&mut self) -> usize {
+        let now = current_timestamp();
+        let expired: Vec<String> = self.expirations.iter()
+            .filter(|(_, expires_at)| now >= **expires_at)
+            .map(|(key, _)| key.clone())
+            .collect();
+        
+        // A key the log cannot drop stays in memory, still reading as absent
+        let mut count = 0;
+        for key in expired {
+            if let Err(StateError::Io(e)) = self.persist(LogRecord::Delete(&key)) {
+                self.persistence_error.get_or_insert(e);
+                break;
+            }
+            self.expirations.remove(&key);
+            self.remove_value(&key);
+            self.notify(StateChange::Delete(key));
+            count += 1;
+        }
+        
+        count
+    }
+    
+    /// Whether a key has a TTL that has run out
+    fn is_expired(&self, key: &str, now: u64) -> bool {
+        self.expirations.get(key).is_some_and(|expires_at| now >= *expires_at)
+    }
+    
+    /// Delete a value from the state store, returning whether it existed
+    pub fn delete(&mut self, key: &str) -> Result<bool, StateError> {
+        self.purge_expired();
+        let existed = self.values.contains_key(key);
+        if existed {
+            self.persist(LogRecord::Delete(key))?;
+            self.expirations.remove(key);
+            self.remove_value(key);
+            self.notify(StateChange::Delete(key.to_string()));
+        }
+        Ok(existed)
+    }
+    
+    /// Apply several updates atomically
+    ///
+    /// Updates made through the `Transaction` are buffered and only applied
+    /// once `f` returns `Ok`; if it returns an error, or the updates would
+    /// exceed the quota, the store is untouched.
+    pub fn transaction<T, E: From<StateError>>(&mut self, f: impl FnOnce(&mut Transaction<'_>) -> Result<T, E>) -> Result<T, E> {
+        let mut tx = Transaction {
+            store: self,
+            ops: Vec::new(),
+        };
+        let result = f(&mut tx)?;
+        let ops = tx.ops;
+        
+        self.purge_expired();
+        self.check_quota(&ops, false)?;
+        self.apply(ops, false)?;
+        
+        Ok(result)
+    }
+    
+    /// Apply updates already checked against the quota, optionally clearing the store first
+    ///
+    /// The updates are logged as one batch, so a crash never leaves only
+    /// some of them on disk.
+    fn apply(&mut self, ops: Vec<TransactionOp>, clear_first: bool) -> Result<(), StateError> {
+        let ops: Vec<(String, Option<StoredValue>)> = ops.into_iter()
+            .map(|op| match op {
+                TransactionOp::Set(key, value) => {
+                    let stored = StoredValue::encode(&value, self.compression_threshold);
+                    (key, Some(stored))
+                }
+                TransactionOp::Delete(key) => (key, None),
+            })
+            .collect();
+        let clear = clear_first.then_some(LogRecord::Clear);
+        self.persist_batch(clear.into_iter().chain(ops.iter().map(|(key, value)| match value {
+            Some(value) => value.record(key),
+            None => LogRecord::Delete(key),
+        })))?;
+        
+        if clear_first {
+            self.clear_values();
+        }
+        for (key, value) in ops {
+            self.expirations.remove(&key);
+            match value {
+                Some(value) => {
+                    self.insert_value(&key, value);
+                    self.notify(StateChange::Set(key));
+                }
+                None => {
+                    if self.remove_value(&key) {
+                        self.notify(StateChange::Delete(key));
+                    }
+                }
+            }
+        }
+        Ok(())
     }
     
     /// Create a snapshot of the current state
+    ///
+    /// Returns the snapshot's id, which is unique and increases with every
+    /// snapshot, so snapshots taken within the same second stay distinct. When
+    /// snapshots are disabled this does nothing and returns `NO_SNAPSHOT`.
     pub fn create_snapshot(&mut self) -> u64 {
-        // Get current timestamp
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        
-        // Create snapshot
-        let snapshot = StateSnapshot {
-            timestamp,
-            values: self.values.clone(),
-        };
+        self.push_snapshot(None)
+    }
+    
+    /// Create a snapshot that can later be restored by name
+    ///
+    /// Reusing a name makes `rollback_named` pick the newest snapshot with it.
+    pub fn create_named_snapshot(&mut self, name: &str) -> u64 {
+        self.push_snapshot(Some(name.to_string()))
+    }
+    
+    fn push_snapshot(&mut self, name: Option<String>) -> u64 {
+        if self.snapshot_limit == 0 {
+            return Self::NO_SNAPSHOT;
+        }
+        
+        // Get the next snapshot id
+        let id = self.next_snapshot_id;
+        self.next_snapshot_id += 1;
         
         // Add snapshot to list
+        let snapshot = self.capture(id, name);
         self.snapshots.push(snapshot);
         
         // Trim snapshots if needed
+        self.trim_snapshots();
+        
+        id
+    }
+    
+    /// Capture the current state without adding it to the snapshot list
+    ///
+    /// Used to undo an aborted execution even when snapshots are disabled.
+    pub(crate) fn checkpoint(&mut self) -> StateSnapshot {
+        self.capture(Self::NO_SNAPSHOT, None)
+    }
+    
+    /// Restore a state captured by `checkpoint`, leaving snapshots untouched
+    pub(crate) fn restore_checkpoint(&mut self, checkpoint: &StateSnapshot) {
+        self.load_snapshot(checkpoint);
+    }
+    
+    fn capture(&mut self, id: u64, name: Option<String>) -> StateSnapshot {
+        self.purge_expired();
+        let timestamp = current_timestamp();
+        
+        // The values clone shares structure instead of copying, and TTLs are
+        // recorded as time remaining so rollback restores them
+        StateSnapshot {
+            id,
+            name,
+            timestamp,
+            values: self.values.clone(),
+            used_bytes: self.used_bytes,
+            memory_bytes: self.memory_bytes,
+            remaining_ttls: self.expirations.iter()
+                .map(|(key, expires_at)| (key.clone(), expires_at.saturating_sub(timestamp)))
+                .collect(),
+        }
+    }
+    
+    /// Get the maximum number of snapshots kept
+    pub fn snapshot_limit(&self) -> usize {
+        self.snapshot_limit
+    }
+    
+    /// Set the maximum number of snapshots kept, evicting the oldest if lowered
+    pub fn set_snapshot_limit(&mut self, limit: usize) {
+        self.snapshot_limit = limit;
+        self.trim_snapshots();
+    }
+    
+    /// Drop the oldest snapshots until the limit is respected
+    fn trim_snapshots(&mut self) {
         if self.snapshots.len() > self.snapshot_limit {
-            self.snapshots.remove(0);
+            let excess = self.snapshots.len() - self.snapshot_limit;
+            self.snapshots.drain(..excess);
         }
+    }
+    
+    /// List what changed since the snapshot with the given id
+    ///
+    /// Returns `None` if no such snapshot exists. Expired keys count as absent.
+    pub fn diff(&self, id: u64) -> Option<StateDiff> {
+        let snapshot = self.snapshots.iter().find(|s| s.id == id)?;
+        let now = current_timestamp();
+        let mut diff = StateDiff::default();
         
-        timestamp
+        for (key, value) in self.values.iter().filter(|(key, _)| !self.is_expired(key, now)) {
+            match snapshot.values.get(key) {
+                None => diff.added.extend(value.read(key).map(|value| (key.clone(), value))),
+                Some(old) if !old.ptr_eq(value) => {
+                    if let (Some(old), Some(value)) = (old.read(key), value.read(key)) {
+                        if old != value {
+                            diff.modified.push((key.clone(), old, value));
+                        }
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, old) in snapshot.values.iter() {
+            if !self.values.contains_key(key) || self.is_expired(key, now) {
+                diff.removed.extend(old.read(key).map(|old| (key.clone(), old)));
+            }
+        }
+        
+        diff.added.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.removed.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.modified.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(diff)
     }
     
-    /// Rollback to a previous snapshot
-    pub fn rollback(&mut self, timestamp: u64) -> bool {
-        // Find snapshot with timestamp
-        if let Some(idx) = self.snapshots.iter().position(|s| s.timestamp == timestamp) {
-            // Restore state from snapshot
-            self.values = self.snapshots[idx].values.clone();
-            
-            // Remove all snapshots after this one
-            self.snapshots.truncate(idx + 1);
-            
-            true
-        } else {
-            false
+    /// Rollback to the snapshot with the given id
+    pub fn rollback(&mut self, id: u64) -> bool {
+        match self.snapshots.iter().position(|s| s.id == id) {
+            Some(idx) => self.restore(idx),
+            None => false,
+        }
+    }
+    
+    /// Rollback to the newest snapshot with the given name
+    pub fn rollback_named(&mut self, name: &str) -> bool {
+        match self.snapshots.iter().rposition(|s| s.name.as_deref() == Some(name)) {
+            Some(idx) => self.restore(idx),
+            None => false,
+        }
+    }
+    
+    /// Rollback to the newest snapshot taken at the given Unix timestamp
+    pub fn rollback_to_timestamp(&mut self, timestamp: u64) -> bool {
+        match self.snapshots.iter().rposition(|s| s.timestamp == timestamp) {
+            Some(idx) => self.restore(idx),
+            None => false,
+        }
+    }
+    
+    /// Restore the snapshot at `idx` and drop every snapshot after it
+    fn restore(&mut self, idx: usize) -> bool {
+        // Remove all snapshots after this one
+        self.snapshots.truncate(idx + 1);
+        let snapshot = self.snapshots[idx].clone();
+        self.load_snapshot(&snapshot);
+        
+        true
+    }
+    
+    /// Replace the current state with a snapshot's
+    fn load_snapshot(&mut self, snapshot: &StateSnapshot) {
+        // Restore state from snapshot, restarting each TTL from its remaining time
+        let now = current_timestamp();
+        let previous = std::mem::replace(&mut self.values, snapshot.values.clone());
+        self.used_bytes = snapshot.used_bytes;
+        self.memory_bytes = snapshot.memory_bytes;
+        self.expirations = snapshot.remaining_ttls.iter()
+            .map(|(key, remaining)| (key.clone(), now.saturating_add(*remaining)))
+            .collect();
+        self.persist_all();
+        
+        // Tell subscribers about every key the rollback touched
+        if !self.subscribers.is_empty() {
+            let mut changes: Vec<StateChange> = previous.keys()
+                .filter(|key| !self.values.contains_key(*key))
+                .map(|key| StateChange::Delete(key.clone()))
+                .collect();
+            changes.extend(self.values.iter()
+                .filter(|(key, value)| previous.get(*key).is_none_or(|old| !old.ptr_eq(value)))
+                .map(|(key, _)| StateChange::Set(key.clone())));
+            for change in changes {
+                self.notify(change);
+            }
         }
     }
     
     /// Get all keys in the state store
     pub fn keys(&self) -> Vec<String> {
-        self.values.keys().cloned().collect()
+        let now = current_timestamp();
+        self.values.keys()
+            .filter(|key| !self.is_expired(key, now))
+            .cloned()
+            .collect()
     }
     
-    /// Get the number of entries in the state store
+    /// Get all keys starting with `prefix`, in sorted order
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let now = current_timestamp();
+        let mut keys: Vec<String> = self.values.keys()
+            .filter(|key| key.starts_with(prefix) && !self.is_expired(key, now))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+    
+    /// Get a view of the keys under `prefix`, isolated from every other namespace
+    ///
+    /// Keys are stored as `prefix:key`, so they still show up in `keys`,
+    /// snapshots, and exports of the whole store. Fails if `prefix` contains
+    /// the separator, since `a:b` and `a` could then address the same keys.
+    pub fn namespace(&mut self, prefix: &str) -> Result<NamespacedStore<'_>, StateError> {
+        if prefix.contains(NamespacedStore::SEPARATOR) {
+            return Err(StateError::InvalidNamespace(format!(
+                "'{}' contains '{}'", prefix, NamespacedStore::SEPARATOR
+            )));
+        }
+        Ok(NamespacedStore {
+            store: self,
+            prefix: format!("{}{}", prefix, NamespacedStore::SEPARATOR),
+        })
+    }
+    
+    /// Get all entries whose key starts with `prefix`, sorted by key
+    pub fn scan_prefix(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        let now = current_timestamp();
+        let mut entries: Vec<(String, Vec<u8>)> = self.values.iter()
+            .filter(|(key, _)| key.starts_with(prefix) && !self.is_expired(key, now))
+            .filter_map(|(key, value)| Some((key.clone(), value.read(key)?)))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+    
+    /// Get the number of unexpired entries in the state store
     pub fn size(&self) -> usize {
-        self.values.len()
+        self.values.len() - self.expired_count()
+    }
+    
+    /// Get the number of expired entries that have not been purged yet
+    pub fn expired_count(&self) -> usize {
+        let now = current_timestamp();
+        self.expirations.iter()
+            .filter(|(key, expires_at)| now >= **expires_at && self.values.contains_key(*key))
+            .count()
     }
     
     /// Clear all values in the state store
-    pub fn clear(&mut self) {
-        self.values.clear();
+    pub fn clear(&mut self) -> Result<(), StateError> {
+        self.persist(LogRecord::Clear)?;
+        self.clear_values();
+        Ok(())
+    }
+    
+    /// Remove every value from memory, notifying subscribers
+    fn clear_values(&mut self) {
+        let previous = std::mem::take(&mut self.values);
+        self.used_bytes = 0;
+        self.memory_bytes = 0;
+        self.expirations.clear();
+        if !self.subscribers.is_empty() {
+            for key in previous.keys() {
+                self.notify(StateChange::Delete(key.clone()));
+            }
+        }
+    }
+    
+    /// Export every unexpired value as a JSON object of base64 strings keyed by key
+    ///
+    /// TTLs and snapshots are not included.
+    pub fn export_json(&self) -> String {
+        let now = current_timestamp();
+        let entries: serde_json::Map<String, serde_json::Value> = self.values.iter()
+            .filter(|(key, _)| !self.is_expired(key, now))
+            .filter_map(|(key, value)| Some((key.clone(), general_purpose::STANDARD.encode(value.read(key)?).into())))
+            .collect();
+        serde_json::Value::Object(entries).to_string()
+    }
+    
+    /// Import values written by `export_json`, optionally clearing the store first
+    ///
+    /// Returns false without changing anything if the JSON is malformed, the
+    /// values would exceed the quota, or the log cannot record them.
+    pub fn import_json(&mut self, json: &str, clear_first: bool) -> bool {
+        let v: serde_json::Value = match serde_json::from_str(json) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        let Some(object) = v.as_object() else {
+            return false;
+        };
+        
+        let mut ops = Vec::with_capacity(object.len());
+        for (key, value) in object {
+            let decoded = value.as_str().and_then(|s| general_purpose::STANDARD.decode(s).ok());
+            match decoded {
+                Some(bytes) => ops.push(TransactionOp::Set(key.clone(), bytes)),
+                None => return false,
+            }
+        }
+        
+        self.purge_expired();
+        if self.check_quota(&ops, clear_first).is_err() {
+            return false;
+        }
+        self.apply(ops, clear_first).is_ok()
     }
     
     /// Get all available snapshot timestamps
     pub fn snapshot_timestamps(&self) -> Vec<u64> {
         self.snapshots.iter().map(|s| s.timestamp).collect()
     }
+    
+    /// Get all available snapshot ids, oldest first
+    pub fn snapshot_ids(&self) -> Vec<u64> {
+        self.snapshots.iter().map(|s| s.id).collect()
+    }
+    
+    /// Get the names of all named snapshots, oldest first
+    pub fn snapshot_names(&self) -> Vec<String> {
+        self.snapshots.iter().filter_map(|s| s.name.clone()).collect()
+    }
+}
+
+/// Buffered updates for `StateStore::transaction`
+pub struct Transaction<'a> {
+    store: &'a StateStore,
+    ops: Vec<TransactionOp>,
+}
+
+/// A single buffered update
+enum TransactionOp {
+    Set(String, Vec<u8>),
+    Delete(String),
+}
+
+impl TransactionOp {
+    /// Get the key this update applies to
+    fn key(&self) -> &str {
+        match self {
+            TransactionOp::Set(key, _) | TransactionOp::Delete(key) => key,
+        }
+    }
+}
+
+impl Transaction<'_> {
+    /// Set a value when the transaction commits
+    pub fn set(&mut self, key: &str, value: &[u8]) {
+        self.ops.push(TransactionOp::Set(key.to_string(), value.to_vec()));
+    }
+    
+    /// Delete a value when the transaction commits
+    pub fn delete(&mut self, key: &str) {
+        self.ops.push(TransactionOp::Delete(key.to_string()));
+    }
+    
+    /// Get a value as it will be once the transaction commits
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        for op in self.ops.iter().rev() {
+            match op {
+                TransactionOp::Set(k, value) if k == key => return Some(value.clone()),
+                TransactionOp::Delete(k) if k == key => return None,
+                _ => {}
+            }
+        }
+        self.store.get(key)
+    }
+}
+
+/// Bytes an entry counts against the quota
+fn entry_size(key: &str, value_len: usize) -> usize {
+    key.len() + value_len
+}
+
+/// Estimated memory an entry holds
+fn entry_memory(key: &str, value: &StoredValue) -> usize {
+    key.len() + value.stored_len() + StateStore::ENTRY_OVERHEAD
+}
+
+/// Current Unix time in seconds
+fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// View of a `StateStore` that prefixes every key with a namespace
+///
+/// Created by `StateStore::namespace`.
+pub struct NamespacedStore<'a> {
+    store: &'a mut StateStore,
+    prefix: String,
+}
+
+impl NamespacedStore<'_> {
+    /// Separator between the namespace and the key in the underlying store
+    pub const SEPARATOR: char = ':';
+    
+    /// Get the prefix added to keys, including the separator
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+    
+    fn full_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+    
+    /// Set a value in this namespace
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), StateError> {
+        let key = self.full_key(key);
+        self.store.set(&key, value)
+    }
+    
+    /// Set a value in this namespace that expires `ttl_secs` seconds from now
+    pub fn set_with_ttl(&mut self, key: &str, value: &[u8], ttl_secs: u64) -> Result<(), StateError> {
+        let key = self.full_key(key);
+        self.store.set_with_ttl(&key, value, ttl_secs)
+    }
+    
+    /// Get a value from this namespace
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.store.get(&self.full_key(key))
+    }
+    
+    /// Delete a value from this namespace, returning whether it existed
+    pub fn delete(&mut self, key: &str) -> Result<bool, StateError> {
+        let key = self.full_key(key);
+        self.store.delete(&key)
+    }
+    
+    /// Get the keys in this namespace, without the prefix, in sorted order
+    pub fn keys(&self) -> Vec<String> {
+        self.store.keys_with_prefix(&self.prefix).into_iter()
+            .map(|key| key[self.prefix.len()..].to_string())
+            .collect()
+    }
+    
+    /// Get the number of unexpired entries in this namespace
+    pub fn size(&self) -> usize {
+        self.store.keys_with_prefix(&self.prefix).len()
+    }
+    
+    /// Remove every value in this namespace, leaving the rest of the store alone
+    ///
+    /// Returns how many values were removed. The removals are applied and
+    /// logged as one update.
+    pub fn clear(&mut self) -> Result<usize, StateError> {
+        self.store.purge_expired();
+        let ops: Vec<TransactionOp> = self.store.keys_with_prefix(&self.prefix).into_iter()
+            .map(TransactionOp::Delete)
+            .collect();
+        let count = ops.len();
+        if count > 0 {
+            self.store.apply(ops, false)?;
+        }
+        Ok(count)
+    }
+}
+
+impl Default for StateStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Thread-safe state store
+///
+/// Reads take a shared lock, so concurrent readers only wait on writers.
 pub struct ConcurrentStateStore {
-    inner: Arc<Mutex<StateStore>>,
+    inner: Arc<RwLock<StateStore>>,
 }
 
 impl ConcurrentStateStore {
     /// Create a new concurrent state store
     pub fn new() -> Self {
         ConcurrentStateStore {
-            inner: Arc::new(Mutex::new(StateStore::new())),
+            inner: Arc::new(RwLock::new(StateStore::new())),
         }
     }
     
     /// Set a value in the state store
     pub fn set(&self, key: &str, value: &[u8]) -> Result<(), String> {
-        let mut store = self.inner.lock().map_err(|e| e.to_string())?;
-        store.set(key, value);
-        Ok(())
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        store.set(key, value).map_err(|e| e.to_string())
+    }
+    
+    /// Set a value only if the current one equals `expected`, as one step under the write lock
+    pub fn compare_and_swap(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool, StateError> {
+        let mut store = self.inner.write().map_err(|e| StateError::LockPoisoned(e.to_string()))?;
+        store.compare_and_swap(key, expected, new)
     }
     
     /// Get a value from the state store
     pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
-        let store = self.inner.lock().map_err(|e| e.to_string())?;
+        let store = self.inner.read().map_err(|e| e.to_string())?;
         Ok(store.get(key))
     }
     
+    /// Get several values under one read lock
+    pub fn get_many(&self, keys: &[&str]) -> Result<std::collections::HashMap<String, Vec<u8>>, String> {
+        let store = self.inner.read().map_err(|e| e.to_string())?;
+        Ok(store.get_many(keys))
+    }
+    
+    /// Set several values under one write lock; readers never see some without the rest
+    pub fn set_many(&self, pairs: &[(&str, &[u8])]) -> Result<(), String> {
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        store.set_many(pairs).map_err(|e| e.to_string())
+    }
+    
+    /// Apply several updates atomically; readers never see a partial update
+    pub fn transaction<T, E: From<StateError>>(&self, f: impl FnOnce(&mut Transaction<'_>) -> Result<T, E>) -> Result<Result<T, E>, String> {
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        Ok(store.transaction(f))
+    }
+    
+    /// Delete a value from the state store
+    pub fn delete(&self, key: &str) -> Result<bool, String> {
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        store.delete(key).map_err(|e| e.to_string())
+    }
+    
+    /// Clear all values in the state store
+    pub fn clear(&self) -> Result<(), String> {
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        store.clear().map_err(|e| e.to_string())
+    }
+    
+    /// Get all keys in the state store
+    pub fn keys(&self) -> Result<Vec<String>, String> {
+        let store = self.inner.read().map_err(|e| e.to_string())?;
+        Ok(store.keys())
+    }
+    
+    /// Get the number of unexpired entries in the state store
+    pub fn size(&self) -> Result<usize, String> {
+        let store = self.inner.read().map_err(|e| e.to_string())?;
+        Ok(store.size())
+    }
+    
+    /// Get all available snapshot timestamps
+    pub fn snapshot_timestamps(&self) -> Result<Vec<u64>, String> {
+        let store = self.inner.read().map_err(|e| e.to_string())?;
+        Ok(store.snapshot_timestamps())
+    }
+    
+    /// Subscribe to changes on every key starting with `key_or_prefix`
+    pub fn subscribe(&self, key_or_prefix: &str) -> Result<Subscription, String> {
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        Ok(store.subscribe(key_or_prefix))
+    }
+    
     /// Create a snapshot of the current state
     pub fn create_snapshot(&self) -> Result<u64, String> {
-        let mut store = self.inner.lock().map_err(|e| e.to_string())?;
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
         Ok(store.create_snapshot())
     }
     
-    /// Rollback to a previous snapshot
-    pub fn rollback(&self, timestamp: u64) -> Result<bool, String> {
-        let mut store = self.inner.lock().map_err(|e| e.to_string())?;
-        Ok(store.rollback(timestamp))
+    /// Rollback to the snapshot with the given id
+    pub fn rollback(&self, id: u64) -> Result<bool, String> {
+        let mut store = self.inner.write().map_err(|e| e.to_string())?;
+        Ok(store.rollback(id))
     }
     
     /// Get the underlying state store
-    pub fn inner(&self) -> Arc<Mutex<StateStore>> {
+    pub fn inner(&self) -> Arc<RwLock<StateStore>> {
         self.inner.clone()
     }
-}
\ No newline at end of file
+}
+
+impl Default for ConcurrentStateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Log file in the temp directory, removed when dropped
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            TempLog(std::env::temp_dir().join(format!("korra-{}-{}.log", name, uuid::Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(self.0.with_extension("tmp"));
+        }
+    }
+
+    fn file_len(path: &Path) -> u64 {
+        fs::metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn crash_mid_batch_recovers_the_state_before_it() {
+        let log = TempLog::new("crash");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set("a", b"1").unwrap();
+        store.set("b", b"2").unwrap();
+        let before_batch = file_len(&log.0);
+        store.set_many(&[("a", b"changed"), ("c", b"3")]).unwrap();
+        let after_batch = file_len(&log.0);
+        drop(store);
+
+        // The process died halfway through writing the batch
+        let file = OpenOptions::new().write(true).open(&log.0).unwrap();
+        file.set_len(before_batch + (after_batch - before_batch) / 2).unwrap();
+        drop(file);
+
+        let mut store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.get("a"), Some(b"1".to_vec()));
+        assert_eq!(store.get("b"), Some(b"2".to_vec()));
+        assert_eq!(store.get("c"), None);
+        assert_eq!(file_len(&log.0), before_batch);
+
+        // Writes after recovery land on a record boundary and survive the next reopen
+        store.set("d", b"4").unwrap();
+        store.delete("b").unwrap();
+        drop(store);
+        let store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.get("a"), Some(b"1".to_vec()));
+        assert_eq!(store.get("b"), None);
+        assert_eq!(store.get("d"), Some(b"4".to_vec()));
+        assert_eq!(store.keys().len(), 2);
+    }
+
+    #[test]
+    fn crash_mid_record_drops_only_that_record() {
+        let log = TempLog::new("torn");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set("kept", b"value").unwrap();
+        let before_set = file_len(&log.0);
+        store.set("torn", b"a value that never fully reached the disk").unwrap();
+        drop(store);
+
+        let file = OpenOptions::new().write(true).open(&log.0).unwrap();
+        file.set_len(before_set + 7).unwrap();
+        drop(file);
+
+        let mut store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.keys(), vec!["kept".to_string()]);
+        store.set("after", b"crash").unwrap();
+        drop(store);
+
+        let store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.get("kept"), Some(b"value".to_vec()));
+        assert_eq!(store.get("after"), Some(b"crash".to_vec()));
+        assert_eq!(store.get("torn"), None);
+    }
+
+    #[test]
+    fn encrypted_store_hides_values_on_disk() {
+        let log = TempLog::new("encrypted");
+        let mut store = StateStore::open_encrypted(&log.0, b"correct key").unwrap();
+        store.set("secret", b"plaintext that must not hit the disk").unwrap();
+        drop(store);
+
+        let data = fs::read(&log.0).unwrap();
+        let needle = b"plaintext that must not hit the disk";
+        assert!(!data.windows(needle.len()).any(|window| window == needle));
+
+        let store = StateStore::open_encrypted(&log.0, b"correct key").unwrap();
+        assert_eq!(store.get("secret"), Some(needle.to_vec()));
+    }
+
+    #[test]
+    fn encrypted_store_rejects_the_wrong_key() {
+        let log = TempLog::new("wrong-key");
+        let mut store = StateStore::open_encrypted(&log.0, b"correct key").unwrap();
+        store.set("secret", b"value").unwrap();
+        drop(store);
+
+        let wrong_key = StateStore::open_encrypted(&log.0, b"wrong key").err().unwrap();
+        assert_eq!(wrong_key.kind(), io::ErrorKind::InvalidData);
+        let no_key = StateStore::open(&log.0).err().unwrap();
+        assert_eq!(no_key.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unknown_opcode_fails_the_open() {
+        let log = TempLog::new("corrupt");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set("a", b"1").unwrap();
+        drop(store);
+
+        let mut data = fs::read(&log.0).unwrap();
+        data.push(0xEE);
+        fs::write(&log.0, &data).unwrap();
+
+        let error = StateStore::open(&log.0).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn corrupted_compressed_values_read_as_absent() {
+        let mut store = StateStore::new();
+        store.set("intact", b"value").unwrap();
+        store.values.insert(
+            "corrupted".to_string(),
+            StoredValue::Zstd { frame: Arc::from(&b"not a zstd frame"[..]), raw_len: 5 },
+        );
+
+        assert_eq!(store.get("corrupted"), None);
+        assert_eq!(store.scan_prefix(""), vec![("intact".to_string(), b"value".to_vec())]);
+        assert!(!store.export_json().contains("corrupted"));
+    }
+
+    #[test]
+    fn diff_counts_empty_values_like_any_other() {
+        let mut store = StateStore::new();
+        store.set("emptied", b"value").unwrap();
+        store.set("filled", b"").unwrap();
+        store.set("same", b"").unwrap();
+        store.set("gone", b"old").unwrap();
+        store.set("gone-empty", b"").unwrap();
+        let snapshot = store.create_snapshot();
+
+        store.set("emptied", b"").unwrap();
+        store.set("filled", b"value").unwrap();
+        store.set("same", b"").unwrap();
+        store.set("new", b"").unwrap();
+        store.delete("gone").unwrap();
+        store.delete("gone-empty").unwrap();
+
+        let diff = store.diff(snapshot).unwrap();
+        assert_eq!(diff.added, vec![("new".to_string(), Vec::new())]);
+        assert_eq!(diff.modified, vec![
+            ("emptied".to_string(), b"value".to_vec(), Vec::new()),
+            ("filled".to_string(), Vec::new(), b"value".to_vec()),
+        ]);
+        assert_eq!(diff.removed, vec![
+            ("gone".to_string(), b"old".to_vec()),
+            ("gone-empty".to_string(), Vec::new()),
+        ]);
+    }
+
+    #[test]
+    fn concurrent_compare_and_swap_reports_state_errors() {
+        let store = ConcurrentStateStore::new();
+        assert!(store.compare_and_swap("k", None, b"a").unwrap());
+        assert!(!store.compare_and_swap("k", None, b"b").unwrap());
+        assert!(store.compare_and_swap("k", Some(b"a"), b"b").unwrap());
+        assert_eq!(store.get("k").unwrap(), Some(b"b".to_vec()));
+
+        let inner = store.inner();
+        let _ = std::thread::spawn(move || {
+            let _guard = inner.write().unwrap();
+            panic!("poison the lock");
+        }).join();
+        assert!(matches!(store.compare_and_swap("k", Some(b"b"), b"c"), Err(StateError::LockPoisoned(_))));
+    }
+
+    #[test]
+    fn namespaces_stay_isolated() {
+        let mut store = StateStore::new();
+        store.namespace("cache").unwrap().set("k", b"cached").unwrap();
+        store.namespace("session").unwrap().set("k", b"user").unwrap();
+
+        assert_eq!(store.namespace("cache").unwrap().get("k"), Some(b"cached".to_vec()));
+        assert_eq!(store.namespace("cache").unwrap().clear().unwrap(), 1);
+        assert_eq!(store.namespace("cache").unwrap().size(), 0);
+        assert_eq!(store.namespace("session").unwrap().keys(), vec!["k".to_string()]);
+        assert_eq!(store.get("session:k"), Some(b"user".to_vec()));
+    }
+
+    #[test]
+    fn namespaces_cannot_contain_the_separator() {
+        let mut store = StateStore::new();
+        store.namespace("a").unwrap().set("b:c", b"value").unwrap();
+
+        assert!(matches!(store.namespace("a:b"), Err(StateError::InvalidNamespace(_))));
+        assert_eq!(store.keys(), vec!["a:b:c".to_string()]);
+    }
+
+    #[test]
+    fn reopened_store_recovers_every_key() {
+        let log = TempLog::new("reopen");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set("kept", b"1").unwrap();
+        store.set("overwritten", b"old").unwrap();
+        store.set("overwritten", b"new").unwrap();
+        store.set("deleted", b"3").unwrap();
+        store.delete("deleted").unwrap();
+        drop(store);
+
+        let store = StateStore::open(&log.0).unwrap();
+        assert!(store.is_persistent());
+        assert_eq!(store.get("kept"), Some(b"1".to_vec()));
+        assert_eq!(store.get("overwritten"), Some(b"new".to_vec()));
+        assert_eq!(store.get("deleted"), None);
+        assert_eq!(store.size(), 2);
+    }
+
+    #[test]
+    fn keys_are_readable_until_their_ttl_runs_out() {
+        let mut store = StateStore::new();
+        store.set_with_ttl("session", b"token", 60).unwrap();
+        store.set("permanent", b"value").unwrap();
+        assert_eq!(store.get("session"), Some(b"token".to_vec()));
+        assert!(store.ttl("session").is_some_and(|ttl| ttl > 0 && ttl <= 60));
+
+        let snapshot = store.create_snapshot();
+        store.delete("session").unwrap();
+        assert!(store.rollback(snapshot));
+        assert!(store.ttl("session").is_some());
+
+        // Let the TTL run out without waiting a minute
+        store.expirations.insert("session".to_string(), current_timestamp());
+        assert_eq!(store.get("session"), None);
+        assert_eq!(store.size(), 1);
+        assert_eq!(store.expired_count(), 1);
+        assert_eq!(store.purge_expired(), 1);
+        assert_eq!(store.expired_count(), 0);
+        assert_eq!(store.keys(), vec!["permanent".to_string()]);
+    }
+
+    #[test]
+    fn snapshots_within_one_second_stay_distinct() {
+        let mut store = StateStore::new();
+        store.set("k", b"first").unwrap();
+        let first = store.create_named_snapshot("first");
+        store.set("k", b"second").unwrap();
+        let second = store.create_snapshot();
+        store.set("k", b"third").unwrap();
+
+        assert_ne!(first, second);
+        assert!(store.rollback(second));
+        assert_eq!(store.get("k"), Some(b"second".to_vec()));
+        assert!(store.rollback_named("first"));
+        assert_eq!(store.get("k"), Some(b"first".to_vec()));
+        assert!(!store.rollback(second));
+    }
+
+    #[test]
+    fn snapshot_limit_evicts_the_oldest_snapshots() {
+        let mut store = StateStore::with_snapshot_limit(2);
+        let ids: Vec<u64> = (0..3).map(|_| store.create_snapshot()).collect();
+        assert_eq!(store.snapshot_ids(), ids[1..]);
+
+        store.set_snapshot_limit(1);
+        assert_eq!(store.snapshot_ids(), ids[2..]);
+
+        store.set_snapshot_limit(0);
+        assert_eq!(store.create_snapshot(), StateStore::NO_SNAPSHOT);
+        assert!(store.snapshot_ids().is_empty());
+    }
+
+    #[test]
+    fn failed_transactions_leave_the_store_untouched() {
+        let mut store = StateStore::new();
+        store.set("balance", b"10").unwrap();
+        store.set("pending", b"yes").unwrap();
+        let before = store.export_json();
+
+        let result: Result<(), String> = store.transaction(|tx| {
+            tx.set("balance", b"0");
+            tx.delete("pending");
+            assert_eq!(tx.get("balance"), Some(b"0".to_vec()));
+            Err("insufficient funds".to_string())
+        });
+        assert_eq!(result, Err("insufficient funds".to_string()));
+        assert_eq!(store.export_json(), before);
+
+        let result: Result<(), String> = store.transaction(|tx| {
+            tx.set("balance", b"0");
+            tx.delete("pending");
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(store.get("balance"), Some(b"0".to_vec()));
+        assert_eq!(store.get("pending"), None);
+    }
+
+    #[test]
+    fn prefix_scans_return_sorted_matches_only() {
+        let mut store = StateStore::new();
+        for key in ["session:12:b", "session:123:b", "session:123:a", "session:1", "sessions", "other"] {
+            store.set(key, key.as_bytes()).unwrap();
+        }
+
+        assert_eq!(store.keys_with_prefix("session:123:"), vec!["session:123:a", "session:123:b"]);
+        assert_eq!(store.keys_with_prefix("session:12"), vec!["session:123:a", "session:123:b", "session:12:b"]);
+        assert_eq!(store.scan_prefix("session:1"), vec![
+            ("session:1".to_string(), b"session:1".to_vec()),
+            ("session:123:a".to_string(), b"session:123:a".to_vec()),
+            ("session:123:b".to_string(), b"session:123:b".to_vec()),
+            ("session:12:b".to_string(), b"session:12:b".to_vec()),
+        ]);
+        assert!(store.scan_prefix("missing").is_empty());
+    }
+
+    #[test]
+    fn subscribers_only_hear_about_matching_keys() {
+        let mut store = StateStore::new();
+        let orders = store.subscribe("orders:");
+        let dropped = store.subscribe("orders:");
+        drop(dropped);
+
+        store.set("orders:1", b"new").unwrap();
+        store.set("users:1", b"alice").unwrap();
+        store.delete("orders:1").unwrap();
+        store.delete("users:1").unwrap();
+
+        let changes: Vec<StateChange> = orders.try_iter().collect();
+        assert_eq!(changes, vec![
+            StateChange::Set("orders:1".to_string()),
+            StateChange::Delete("orders:1".to_string()),
+        ]);
+        assert_eq!(store.subscribers.len(), 1);
+    }
+
+    #[test]
+    fn export_import_round_trips_binary_values() {
+        let mut store = StateStore::new();
+        store.set("binary", &[0, 159, 146, 150, 255]).unwrap();
+        store.set("text", b"hello").unwrap();
+        store.set("empty", b"").unwrap();
+        let exported = store.export_json();
+
+        let mut restored = StateStore::new();
+        restored.set("stale", b"gone").unwrap();
+        assert!(restored.import_json(&exported, true));
+        assert_eq!(restored.export_json(), exported);
+        assert_eq!(restored.get("binary"), Some(vec![0, 159, 146, 150, 255]));
+        assert_eq!(restored.get("stale"), None);
+
+        assert!(!restored.import_json("{\"bad\": \"not base64!\"}", false));
+        assert_eq!(restored.export_json(), exported);
+    }
+
+    #[test]
+    fn quota_counts_overwrites_and_deletes() {
+        let mut store = StateStore::with_max_bytes(10);
+        store.set("a", b"123456789").unwrap();
+        assert_eq!(store.used_bytes(), 10);
+        assert!(matches!(store.set("b", b""), Err(StateError::QuotaExceeded(_))));
+        assert_eq!(store.get("b"), None);
+
+        store.set("a", b"1234").unwrap();
+        assert_eq!(store.used_bytes(), 5);
+        store.set("b", b"123").unwrap();
+        assert!(matches!(store.set("c", b"12"), Err(StateError::QuotaExceeded(_))));
+
+        store.delete("a").unwrap();
+        assert_eq!(store.used_bytes(), 4);
+        store.set("c", b"12").unwrap();
+        assert_eq!(store.used_bytes(), 7);
+    }
+
+    #[test]
+    fn concurrent_wrappers_work_across_threads() {
+        let store = ConcurrentStateStore::new();
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                let store = &store;
+                scope.spawn(move || {
+                    for i in 0..25 {
+                        store.set(&format!("{}:{}", t, i), b"v").unwrap();
+                    }
+                    assert!(store.delete(&format!("{}:0", t)).unwrap());
+                    assert!(!store.delete(&format!("{}:0", t)).unwrap());
+                });
+            }
+        });
+
+        assert_eq!(store.size().unwrap(), 96);
+        assert_eq!(store.keys().unwrap().len(), 96);
+        store.create_snapshot().unwrap();
+        assert_eq!(store.snapshot_timestamps().unwrap().len(), 1);
+        store.clear().unwrap();
+        assert_eq!(store.size().unwrap(), 0);
+    }
+
+    #[test]
+    fn memory_usage_tracks_insertions_and_deletions() {
+        let overhead = StateStore::ENTRY_OVERHEAD;
+        let mut store = StateStore::new();
+        assert_eq!(store.memory_usage(), 0);
+
+        store.set("a", b"123").unwrap();
+        store.set("bb", b"12345").unwrap();
+        assert_eq!(store.memory_usage(), (1 + 3 + overhead) + (2 + 5 + overhead));
+
+        store.set("a", b"1").unwrap();
+        store.delete("bb").unwrap();
+        assert_eq!(store.memory_usage(), 1 + 1 + overhead);
+        assert_eq!(store.snapshot_memory_usage(), 0);
+
+        store.create_snapshot();
+        assert_eq!(store.snapshot_memory_usage(), 0);
+        store.set("a", b"12").unwrap();
+        assert_eq!(store.memory_usage(), 1 + 2 + overhead);
+        assert_eq!(store.snapshot_memory_usage(), 1 + 1 + overhead);
+    }
+
+    #[test]
+    fn readers_never_see_half_a_batch() {
+        let store = ConcurrentStateStore::new();
+        store.set_many(&[("left", b"0"), ("right", b"0")]).unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 1..200u32 {
+                    let value = i.to_string();
+                    store.set_many(&[("left", value.as_bytes()), ("right", value.as_bytes())]).unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..200 {
+                    let values = store.get_many(&["left", "right", "missing"]).unwrap();
+                    assert_eq!(values.len(), 2);
+                    assert_eq!(values["left"], values["right"]);
+                }
+            });
+        });
+
+        assert_eq!(store.get("left").unwrap(), Some(b"199".to_vec()));
+    }
+
+    #[test]
+    fn failed_log_writes_leave_the_store_unchanged() {
+        let log = TempLog::new("failing");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set("a", b"1").unwrap();
+        let changes = store.subscribe("");
+        store.log.as_mut().unwrap().fail_appends().unwrap();
+
+        assert!(matches!(store.set("a", b"2"), Err(StateError::Io(_))));
+        assert!(matches!(store.set("b", b"2"), Err(StateError::Io(_))));
+        assert!(matches!(store.delete("a"), Err(StateError::Io(_))));
+        assert!(matches!(store.set_many(&[("a", b"3"), ("c", b"3")]), Err(StateError::Io(_))));
+        assert!(matches!(store.clear(), Err(StateError::Io(_))));
+        assert_eq!(store.get("a"), Some(b"1".to_vec()));
+        assert_eq!(store.keys(), vec!["a".to_string()]);
+        assert!(changes.try_recv().is_err());
+
+        drop(store);
+        let store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.get("a"), Some(b"1".to_vec()));
+        assert_eq!(store.size(), 1);
+    }
+
+    #[test]
+    fn expiries_without_a_value_never_skew_the_size() {
+        let log = TempLog::new("ghost-expiry");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set("a", b"1").unwrap();
+        store.log.as_mut().unwrap().append(LogRecord::Expire("ghost", 1)).unwrap();
+        drop(store);
+
+        let mut store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.ttl("ghost"), None);
+        assert_eq!(store.size(), 1);
+
+        store.expirations.insert("ghost".to_string(), 1);
+        assert_eq!(store.expired_count(), 0);
+        assert_eq!(store.size(), 1);
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_forgotten_even_without_matching_changes() {
+        let mut store = StateStore::new();
+        let quiet = store.subscribe("never-changes:");
+        let busy = store.subscribe("busy:");
+        drop(quiet);
+
+        store.set("busy:1", b"v").unwrap();
+        assert_eq!(store.subscribers.len(), 1);
+        assert_eq!(busy.try_recv(), Ok(StateChange::Set("busy:1".to_string())));
+
+        drop(busy);
+        let _fresh = store.subscribe("fresh:");
+        assert_eq!(store.subscribers.len(), 1);
+    }
+
+    #[test]
+    fn compressible_values_are_stored_compressed() {
+        let value = vec![b'a'; 10_000];
+        let mut store = StateStore::new();
+        store.set_compression_threshold(Some(64));
+        store.set("big", &value).unwrap();
+        store.set("small", b"tiny").unwrap();
+
+        assert_eq!(store.get("big"), Some(value.clone()));
+        let stats = store.compression_stats();
+        assert_eq!(stats.raw_bytes, 3 + 10_000 + 5 + 4);
+        assert_eq!(stats.compressed_values, 1);
+        assert!(stats.stored_bytes < stats.raw_bytes / 10, "{:?}", stats);
+    }
+
+    #[test]
+    fn compressed_values_stay_compressed_across_a_reopen() {
+        let value = vec![b'a'; 10_000];
+        let log = TempLog::new("compressed");
+        let mut store = StateStore::open(&log.0).unwrap();
+        store.set_compression_threshold(Some(64));
+        store.set("big", &value).unwrap();
+        drop(store);
+        assert!(file_len(&log.0) < 1_000);
+
+        let store = StateStore::open(&log.0).unwrap();
+        assert_eq!(store.get("big"), Some(value));
+        let stats = store.compression_stats();
+        assert_eq!(stats.compressed_values, 1);
+        assert!(stats.stored_bytes < stats.raw_bytes / 10, "{:?}", stats);
+    }
+}
@@ -0,0 +1,357 @@
+//! Append-only log backing persistent state stores
+//!
+//! Each record is an opcode byte followed by length-prefixed fields, with
+//! lengths encoded as little-endian `u32`:
+//!
+//! - `SET`: key, value (clears any expiry on the key)
+//! - `DELETE`: key
+//! - `CLEAR`: no fields
+//! - `EXPIRE`: key, then the expiry as a little-endian `u64` Unix timestamp
+//! - `SET_ZSTD`: key, zstd frame holding the value (clears any expiry on the key)
+//! - `KEY_CHECK`: a constant sealed with the log's key
+//! - `BEGIN`, `COMMIT`: no fields; bracket records that apply all together
+//!
+//! Encrypted logs start with a `KEY_CHECK` record, so opening one with the
+//! wrong key fails even before any value is read. Their `SET` and `SET_ZSTD`
+//! values are sealed by `ValueCipher` with the opcode and key as context;
+//! keys, expiries, and record boundaries stay in the clear.
+//!
+//! Every append is fsynced before the in-memory store changes. A torn record
+//! at the end of the log (from a crash mid-write), or a batch with no
+//! `COMMIT`, is cut off when the log is opened, so the store reopens with
+//! every fully written mutation and none of a partly written one, and later
+//! appends start on a record boundary. An unknown opcode means the log is
+//! corrupt, and fails the open with `InvalidData`.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::state::cipher::ValueCipher;
+
+const OP_SET: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_CLEAR: u8 = 3;
+const OP_EXPIRE: u8 = 4;
+const OP_SET_ZSTD: u8 = 5;
+const OP_KEY_CHECK: u8 = 6;
+const OP_BEGIN: u8 = 7;
+const OP_COMMIT: u8 = 8;
+
+/// Plaintext sealed in the `KEY_CHECK` record
+const KEY_CHECK_PLAINTEXT: &[u8] = b"korra-state-log";
+
+/// A single mutation recorded in the log
+pub(crate) enum LogRecord<'a> {
+    Set(&'a str, &'a [u8]),
+    SetCompressed(&'a str, &'a [u8]),
+    Delete(&'a str),
+    Clear,
+    Expire(&'a str, u64),
+}
+
+/// A value as written to the log
+pub(crate) enum LoggedValue {
+    Raw(Vec<u8>),
+    Zstd(Vec<u8>),
+}
+
+/// Values and expiries recovered from a log
+#[derive(Default)]
+pub(crate) struct Replayed {
+    pub(crate) values: HashMap<String, LoggedValue>,
+    pub(crate) expirations: HashMap<String, u64>,
+    /// Length of the log up to the end of its last complete record or batch
+    valid_len: usize,
+}
+
+/// Open append-only log file
+pub(crate) struct StateLog {
+    path: PathBuf,
+    file: File,
+    cipher: Option<ValueCipher>,
+}
+
+impl StateLog {
+    /// Open or create the log at `path`, returning it with the replayed values
+    ///
+    /// With a cipher the log must be encrypted with the same key, or be empty;
+    /// anything else fails with `InvalidData`.
+    pub(crate) fn open(path: &Path, cipher: Option<ValueCipher>) -> io::Result<(Self, Replayed)> {
+        let data = match File::open(path) {
+            Ok(mut file) => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                data
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let replayed = replay(&data, cipher.as_ref())?;
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if replayed.valid_len < data.len() {
+            crate::log_warn(&format!(
+                "Discarding {} bytes of incomplete records at the end of {}",
+                data.len() - replayed.valid_len,
+                path.display()
+            ));
+            file.set_len(replayed.valid_len as u64)?;
+            file.sync_data()?;
+        }
+
+        let mut log = StateLog { path: path.to_path_buf(), file, cipher };
+        if replayed.valid_len == 0 && log.cipher.is_some() {
+            let mut buf = Vec::new();
+            log.encode_key_check(&mut buf);
+            log.file.write_all(&buf)?;
+            log.file.sync_data()?;
+        }
+        Ok((log, replayed))
+    }
+
+    /// Append a record and flush it to disk
+    pub(crate) fn append(&mut self, record: LogRecord<'_>) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode(&mut buf, record, self.cipher.as_ref());
+        self.file.write_all(&buf)?;
+        self.file.sync_data()
+    }
+
+    /// Append records that replay all together or not at all, and flush them to disk
+    pub(crate) fn append_batch<'a>(&mut self, records: impl IntoIterator<Item = LogRecord<'a>>) -> io::Result<()> {
+        let mut buf = vec![OP_BEGIN];
+        for record in records {
+            encode(&mut buf, record, self.cipher.as_ref());
+        }
+        buf.push(OP_COMMIT);
+        self.file.write_all(&buf)?;
+        self.file.sync_data()
+    }
+
+    /// Make every later append fail, as a failing disk would
+    #[cfg(test)]
+    pub(crate) fn fail_appends(&mut self) -> io::Result<()> {
+        self.file = File::open(&self.path)?;
+        Ok(())
+    }
+
+    /// Serialize the `KEY_CHECK` record, if the log is encrypted
+    fn encode_key_check(&self, buf: &mut Vec<u8>) {
+        if let Some(cipher) = &self.cipher {
+            buf.push(OP_KEY_CHECK);
+            put_field(buf, &cipher.seal(&[OP_KEY_CHECK], KEY_CHECK_PLAINTEXT));
+        }
+    }
+
+    /// Replace the log with one holding exactly the `values` set records and `expirations`
+    ///
+    /// The new log is written beside the old one and renamed over it, so a
+    /// crash leaves either the old or the new contents intact.
+    pub(crate) fn rewrite<'a>(
+        &mut self,
+        values: impl IntoIterator<Item = LogRecord<'a>>,
+        expirations: impl IntoIterator<Item = (&'a str, u64)>,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.encode_key_check(&mut buf);
+        for record in values {
+            encode(&mut buf, record, self.cipher.as_ref());
+        }
+        for (key, expires_at) in expirations {
+            encode(&mut buf, LogRecord::Expire(key, expires_at), None);
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&buf)?;
+        tmp.sync_data()?;
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent_dir(&self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Flush the directory entry of `path`, so a rename onto it survives a crash
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Serialize a record onto the end of `buf`, sealing values with `cipher` if given
+fn encode(buf: &mut Vec<u8>, record: LogRecord<'_>, cipher: Option<&ValueCipher>) {
+    match record {
+        LogRecord::Set(key, value) => put_value(buf, OP_SET, key, value, cipher),
+        LogRecord::SetCompressed(key, frame) => put_value(buf, OP_SET_ZSTD, key, frame, cipher),
+        LogRecord::Delete(key) => {
+            buf.push(OP_DELETE);
+            put_field(buf, key.as_bytes());
+        }
+        LogRecord::Clear => buf.push(OP_CLEAR),
+        LogRecord::Expire(key, expires_at) => {
+            buf.push(OP_EXPIRE);
+            put_field(buf, key.as_bytes());
+            buf.extend_from_slice(&expires_at.to_le_bytes());
+        }
+    }
+}
+
+fn put_value(buf: &mut Vec<u8>, op: u8, key: &str, value: &[u8], cipher: Option<&ValueCipher>) {
+    buf.push(op);
+    put_field(buf, key.as_bytes());
+    match cipher {
+        Some(cipher) => put_field(buf, &cipher.seal(&value_context(op, key), value)),
+        None => put_field(buf, value),
+    }
+}
+
+/// Context a value is sealed under, tying it to its opcode and key
+fn value_context(op: u8, key: &str) -> Vec<u8> {
+    let mut context = vec![op];
+    context.extend_from_slice(key.as_bytes());
+    context
+}
+
+fn put_field(buf: &mut Vec<u8>, field: &[u8]) {
+    buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    buf.extend_from_slice(field);
+}
+
+/// A mutation read back from the log
+enum Replay {
+    Set(String, LoggedValue),
+    Delete(String),
+    Clear,
+    Expire(String, u64),
+}
+
+impl Replay {
+    fn apply(self, state: &mut Replayed) {
+        match self {
+            Replay::Set(key, value) => {
+                state.expirations.remove(&key);
+                state.values.insert(key, value);
+            }
+            Replay::Delete(key) => {
+                state.expirations.remove(&key);
+                state.values.remove(&key);
+            }
+            Replay::Clear => {
+                state.values.clear();
+                state.expirations.clear();
+            }
+            // An expiry only ever follows its key's value; one without it is dropped
+            Replay::Expire(key, expires_at) => {
+                if state.values.contains_key(&key) {
+                    state.expirations.insert(key, expires_at);
+                }
+            }
+        }
+    }
+}
+
+/// Rebuild the state described by a log, stopping at the first incomplete record
+///
+/// Fails if the log's encryption does not match `cipher` or it holds an
+/// unknown opcode. `valid_len` of the result marks where the complete
+/// records end, leaving out any open batch.
+fn replay(log: &[u8], cipher: Option<&ValueCipher>) -> io::Result<Replayed> {
+    let mut data = log;
+    let mut state = Replayed::default();
+    if cipher.is_some() && data.first().is_some_and(|op| *op != OP_KEY_CHECK) {
+        return Err(invalid_data("State log is not encrypted"));
+    }
+
+    // Records of an open batch, applied only once its COMMIT is read
+    let mut batch: Option<Vec<Replay>> = None;
+    while let Some((&op, rest)) = data.split_first() {
+        data = rest;
+        let record = match op {
+            OP_SET | OP_SET_ZSTD => {
+                let Some(key) = take_key(&mut data) else { break };
+                let Some(value) = take_field(&mut data) else { break };
+                let value = match cipher {
+                    Some(cipher) => cipher
+                        .open(&value_context(op, &key), value)
+                        .ok_or_else(|| invalid_data(&format!("State log value for '{}' failed authentication", key)))?,
+                    None => value.to_vec(),
+                };
+                let value = if op == OP_SET { LoggedValue::Raw(value) } else { LoggedValue::Zstd(value) };
+                Replay::Set(key, value)
+            }
+            OP_KEY_CHECK => {
+                let Some(sealed) = take_field(&mut data) else { break };
+                let Some(cipher) = cipher else {
+                    return Err(invalid_data("State log is encrypted"));
+                };
+                if cipher.open(&[OP_KEY_CHECK], sealed).as_deref() != Some(KEY_CHECK_PLAINTEXT) {
+                    return Err(invalid_data("Wrong key for encrypted state log"));
+                }
+                state.valid_len = log.len() - data.len();
+                continue;
+            }
+            OP_DELETE => {
+                let Some(key) = take_key(&mut data) else { break };
+                Replay::Delete(key)
+            }
+            OP_CLEAR => Replay::Clear,
+            OP_EXPIRE => {
+                let Some(key) = take_key(&mut data) else { break };
+                let Some(expires_at) = take_u64(&mut data) else { break };
+                Replay::Expire(key, expires_at)
+            }
+            OP_BEGIN => {
+                batch = Some(Vec::new());
+                continue;
+            }
+            OP_COMMIT => {
+                for record in batch.take().unwrap_or_default() {
+                    record.apply(&mut state);
+                }
+                state.valid_len = log.len() - data.len();
+                continue;
+            }
+            _ => {
+                let offset = log.len() - data.len() - 1;
+                return Err(invalid_data(&format!("Unknown state log opcode {} at offset {}", op, offset)));
+            }
+        };
+        match &mut batch {
+            Some(records) => records.push(record),
+            None => {
+                record.apply(&mut state);
+                state.valid_len = log.len() - data.len();
+            }
+        }
+    }
+
+    Ok(state)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn take_field<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len_bytes: [u8; 4] = data.get(..4)?.try_into().ok()?;
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    let field = data.get(4..4 + len)?;
+    *data = &data[4 + len..];
+    Some(field)
+}
+
+fn take_u64(data: &mut &[u8]) -> Option<u64> {
+    let bytes: [u8; 8] = data.get(..8)?.try_into().ok()?;
+    *data = &data[8..];
+    Some(u64::from_le_bytes(bytes))
+}
+
+fn take_key(data: &mut &[u8]) -> Option<String> {
+    String::from_utf8(take_field(data)?.to_vec()).ok()
+}
@@ -0,0 +1,5 @@
+//! Agent state management
+
+mod cipher;
+pub mod core;
+mod log;
@@ -5,6 +5,12 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::verifier::proof::ExecutionProof;
 
+/// Reputation regained by a node for each round it agrees with consensus
+const REPUTATION_REWARD: f32 = 0.05;
+
+/// Factor applied to a node's reputation for each round it disagrees
+const REPUTATION_DECAY: f32 = 0.5;
+
 /// Consensus validation result
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConsensusResult {
@@ -13,12 +19,100 @@ pub enum ConsensusResult {
     Uncertain,
 }
 
+/// How a consensus verdict was reached, for diagnosing agents that won't finalize
+///
+/// Weights are effective weights, after reputation and conflict discounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsensusReport {
+    /// The verdict, as returned by `validate`
+    pub result: ConsensusResult,
+    /// Weight of all active nodes, whether or not they reported
+    pub total_weight: f32,
+    /// Proof hash with the most weight behind it
+    pub winning_hash: Option<String>,
+    /// Weight behind `winning_hash`
+    pub winning_weight: f32,
+    /// `winning_weight` over `total_weight`
+    pub consensus_ratio: f32,
+    /// Nodes whose proofs were counted, sorted by ID
+    pub participating_nodes: Vec<String>,
+    /// Participating nodes that reported a hash other than `winning_hash`, sorted by ID
+    pub dissenting_nodes: Vec<String>,
+}
+
+impl Default for ConsensusReport {
+    fn default() -> Self {
+        ConsensusReport {
+            result: ConsensusResult::Uncertain,
+            total_weight: 0.0,
+            winning_hash: None,
+            winning_weight: 0.0,
+            consensus_ratio: 0.0,
+            participating_nodes: Vec::new(),
+            dissenting_nodes: Vec::new(),
+        }
+    }
+}
+
+/// Rule deciding whether the heaviest proof hash has enough weight
+///
+/// All modes measure agreement as the weight behind one proof hash over the
+/// total weight of active nodes, including nodes that have not reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConsensusMode {
+    /// The heaviest hash must reach the consensus threshold. A coalition
+    /// holding just over the threshold decides the outcome.
+    #[default]
+    Plurality,
+    /// The heaviest hash needs at least two thirds of the weight, or the
+    /// threshold if that is higher.
+    SuperMajority,
+    /// The heaviest hash needs strictly more than two thirds of the weight,
+    /// or the threshold if that is higher, and conflicting nodes never count
+    /// towards agreement. As long as faulty nodes hold less than a third of
+    /// the weight, no two different hashes can both be accepted.
+    ByzantineFaultTolerant,
+}
+
+impl ConsensusMode {
+    /// Get the name used in serialized validators
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsensusMode::Plurality => "plurality",
+            ConsensusMode::SuperMajority => "super_majority",
+            ConsensusMode::ByzantineFaultTolerant => "byzantine_fault_tolerant",
+        }
+    }
+    
+    /// Parse a mode from its serialized name
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(mode: &str) -> Option<Self> {
+        match mode {
+            "plurality" => Some(ConsensusMode::Plurality),
+            "super_majority" => Some(ConsensusMode::SuperMajority),
+            "byzantine_fault_tolerant" => Some(ConsensusMode::ByzantineFaultTolerant),
+            _ => None,
+        }
+    }
+    
+    /// Whether `weight` out of `total_weight` is agreement under this mode
+    fn accepts(&self, weight: f32, total_weight: f32, threshold: f32) -> bool {
+        let reaches_threshold = weight / total_weight >= threshold;
+        match self {
+            ConsensusMode::Plurality => reaches_threshold,
+            ConsensusMode::SuperMajority => reaches_threshold && weight * 3.0 >= total_weight * 2.0,
+            ConsensusMode::ByzantineFaultTolerant => reaches_threshold && weight * 3.0 > total_weight * 2.0,
+        }
+    }
+}
+
 /// Validator node info
 #[derive(Debug, Clone)]
 pub struct ValidatorNode {
     node_id: String,
     weight: u32,
     last_seen: u64,
+    reputation: f32, // 0.0 to 1.0
 }
 
 impl ValidatorNode {
@@ -27,19 +121,14 @@ impl ValidatorNode {
         ValidatorNode {
             node_id: node_id.to_string(),
             weight,
-            last_seen: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
+            last_seen: current_timestamp(),
+            reputation: 1.0,
         }
     }
     
     /// Update the last seen timestamp
     pub fn update_last_seen(&mut self) {
-        self.last_seen = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        self.last_seen = current_timestamp();
     }
     
     /// Get the node ID
@@ -56,6 +145,20 @@ impl ValidatorNode {
     pub fn last_seen(&self) -> u64 {
         self.last_seen
     }
+    
+    /// Get the reputation scaling the node's weight, from 0.0 to 1.0
+    pub fn reputation(&self) -> f32 {
+        self.reputation
+    }
+    
+    /// Raise the reputation after agreeing with consensus, otherwise decay it
+    fn record_round(&mut self, agreed: bool) {
+        self.reputation = if agreed {
+            (self.reputation + REPUTATION_REWARD).min(1.0)
+        } else {
+            self.reputation * REPUTATION_DECAY
+        };
+    }
 }
 
 /// Lightweight consensus validator
@@ -63,6 +166,13 @@ pub struct ConsensusValidator {
     nodes: HashMap<String, ValidatorNode>,
     proofs: HashMap<String, HashMap<String, ExecutionProof>>,
     required_consensus: f32, // 0.0 to 1.0
+    stale_after_secs: Option<u64>,
+    conflicts: HashMap<String, HashSet<String>>,
+    conflict_discount: f32, // 0.0 to 1.0
+    min_nodes: usize,
+    node_keys: HashMap<String, String>,
+    agent_thresholds: HashMap<String, f32>,
+    mode: ConsensusMode,
 }
 
 impl ConsensusValidator {
@@ -71,7 +181,14 @@ impl ConsensusValidator {
         ConsensusValidator {
             nodes: HashMap::new(),
             proofs: HashMap::new(),
-            required_consensus: required_consensus.max(0.0).min(1.0),
+            required_consensus: required_consensus.clamp(0.0, 1.0),
+            stale_after_secs: None,
+            conflicts: HashMap::new(),
+            conflict_discount: 0.0,
+            min_nodes: 1,
+            node_keys: HashMap::new(),
+            agent_thresholds: HashMap::new(),
+            mode: ConsensusMode::default(),
         }
     }
     
@@ -82,80 +199,318 @@ impl ConsensusValidator {
     
     /// Remove a validator node
     pub fn remove_node(&mut self, node_id: &str) -> bool {
+        self.node_keys.remove(node_id);
         self.nodes.remove(node_id).is_some()
     }
     
-    /// Add an execution proof from a node
+    /// Change an existing node's weight, keeping its proofs and reputation
+    ///
+    /// Returns false if the node is unknown.
+    pub fn set_node_weight(&mut self, node_id: &str, weight: u32) -> bool {
+        match self.nodes.get_mut(node_id) {
+            Some(node) => {
+                node.weight = weight;
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Register a node's base64-encoded Ed25519 public key
+    ///
+    /// Once registered, proofs from the node are only accepted if they are
+    /// signed with this key. Returns false if the node is unknown.
+    pub fn register_node_key(&mut self, node_id: &str, public_key: &str) -> bool {
+        if !self.nodes.contains_key(node_id) {
+            return false;
+        }
+        self.node_keys.insert(node_id.to_string(), public_key.to_string());
+        true
+    }
+    
+    /// Remove nodes not seen within `max_age_secs`, dropping their proofs
+    ///
+    /// Returns the IDs of the evicted nodes.
+    pub fn evict_stale(&mut self, max_age_secs: u64) -> Vec<String> {
+        let cutoff = current_timestamp().saturating_sub(max_age_secs);
+        let evicted: Vec<String> = self.nodes.values()
+            .filter(|n| n.last_seen < cutoff)
+            .map(|n| n.node_id.clone())
+            .collect();
+        
+        for node_id in &evicted {
+            self.nodes.remove(node_id);
+            self.node_keys.remove(node_id);
+            for agent_proofs in self.proofs.values_mut() {
+                agent_proofs.remove(node_id);
+            }
+            for conflicting in self.conflicts.values_mut() {
+                conflicting.remove(node_id);
+            }
+        }
+        
+        evicted
+    }
+    
+    /// Add an execution proof from a node, replacing its previous proof for the agent
+    ///
+    /// Proofs carry a fresh nonce, so their hashes differ even when a node
+    /// reruns the same execution. A node is only flagged as conflicting when it
+    /// reports a different output for an input it already reported.
     pub fn add_proof(&mut self, node_id: &str, proof: ExecutionProof) -> bool {
         // Check if node exists
         if !self.nodes.contains_key(node_id) {
             return false;
         }
         
+        // Nodes with a registered key must sign their proofs with it
+        if let Some(key) = self.node_keys.get(node_id) {
+            if proof.public_key() != Some(key.as_str()) || !proof.verify_signature() {
+                return false;
+            }
+        }
+        
         // Update node's last seen timestamp
         if let Some(node) = self.nodes.get_mut(node_id) {
             node.update_last_seen();
         }
         
         // Get or create the proof map for this agent
+        let agent_id = proof.agent_id().to_string();
         let agent_proofs = self.proofs
-            .entry(proof.agent_id().to_string())
-            .or_insert_with(HashMap::new);
+            .entry(agent_id.clone())
+            .or_default();
         
-        // Add the proof
-        agent_proofs.insert(node_id.to_string(), proof);
+        // Add the proof, flagging the node if it contradicts its previous one
+        if let Some(previous) = agent_proofs.insert(node_id.to_string(), proof) {
+            let current = &agent_proofs[node_id];
+            if previous.input_hash() == current.input_hash() && previous.output_hash() != current.output_hash() {
+                self.conflicts.entry(agent_id).or_default().insert(node_id.to_string());
+            }
+        }
         
         true
     }
     
     /// Validate consensus for an agent
     pub fn validate(&self, agent_id: &str) -> ConsensusResult {
+        self.report(agent_id, None).result
+    }
+    
+    /// Validate consensus counting only proofs made in the last `window_secs`
+    ///
+    /// Older proofs are treated as absent. Nodes past the stale cutoff set by
+    /// `set_stale_after_secs` are still excluded, so both node liveness and
+    /// proof freshness apply.
+    pub fn validate_within(&self, agent_id: &str, window_secs: u64) -> ConsensusResult {
+        self.report(agent_id, Some(window_secs)).result
+    }
+    
+    /// Validate an agent's round and, once it is valid, settle it
+    ///
+    /// On a `Valid` result, nodes that reported the agreed proof hash gain
+    /// reputation while nodes that reported another hash or conflicting proofs
+    /// lose it, and the agent's proofs and conflicts are cleared for the next
+    /// round. Any other result changes nothing.
+    pub fn finalize(&mut self, agent_id: &str) -> ConsensusResult {
+        let report = self.report(agent_id, None);
+        let agreed = match (report.result, report.winning_hash) {
+            (ConsensusResult::Valid, Some(hash)) => hash,
+            (result, _) => return result,
+        };
+        
+        let proofs = self.proofs.remove(agent_id).unwrap_or_default();
+        let conflicted = self.conflicts.remove(agent_id).unwrap_or_default();
+        for (node_id, proof) in &proofs {
+            if let Some(node) = self.nodes.get_mut(node_id) {
+                node.record_round(proof.proof_hash() == agreed && !conflicted.contains(node_id));
+            }
+        }
+        
+        ConsensusResult::Valid
+    }
+    
+    /// Validate consensus for an agent, explaining how the verdict was reached
+    pub fn validate_detailed(&self, agent_id: &str) -> ConsensusReport {
+        self.report(agent_id, None)
+    }
+    
+    /// Tally an agent's proofs into a report
+    ///
+    /// With a window, proofs timestamped before `now - window_secs` are skipped.
+    fn report(&self, agent_id: &str, window_secs: Option<u64>) -> ConsensusReport {
+        let mut report = ConsensusReport::default();
+        
         // Get proofs for this agent
         let agent_proofs = match self.proofs.get(agent_id) {
             Some(p) => p,
-            None => return ConsensusResult::Uncertain,
+            None => return report,
         };
         
-        // Count the total weight of all nodes
-        let total_weight: u32 = self.nodes.values().map(|n| n.weight).sum();
-        if total_weight == 0 {
-            return ConsensusResult::Uncertain;
+        let now = current_timestamp();
+        let oldest = window_secs.map_or(0, |window| now.saturating_sub(window));
+        
+        // Count the total weight of all active nodes
+        report.total_weight = self.nodes.values()
+            .filter(|n| !self.is_stale(n, now))
+            .map(|n| self.effective_weight(n, agent_id))
+            .sum();
+        if report.total_weight <= 0.0 {
+            return report;
+        }
+        
+        // Group fresh, unexpired proofs from active nodes by proof hash
+        let mut hash_groups: HashMap<&str, Vec<&String>> = HashMap::new();
+        let live_proofs = agent_proofs.iter().filter(|(node_id, proof)| {
+            proof.timestamp() >= oldest
+                && !proof.is_expired(now)
+                && self.nodes.get(*node_id).is_some_and(|n| !self.is_stale(n, now))
+        });
+        for (node_id, proof) in live_proofs {
+            hash_groups.entry(proof.proof_hash()).or_default().push(node_id);
         }
+        report.participating_nodes = hash_groups.values().flatten().map(|id| id.to_string()).collect();
+        report.participating_nodes.sort();
         
-        // Group proofs by proof hash
-        let mut hash_groups: HashMap<String, HashSet<String>> = HashMap::new();
-        for (node_id, proof) in agent_proofs {
-            hash_groups.entry(proof.proof_hash().to_string())
-                .or_insert_with(HashSet::new)
-                .insert(node_id.clone());
+        // Too few reports is not consensus, however much weight they carry
+        if report.participating_nodes.len() < self.min_nodes {
+            return report;
         }
         
-        // Find the hash with the most weight
-        let mut max_weight = 0;
-        let mut max_hash = String::new();
+        // Find the hash with the most weight; BFT never counts conflicting nodes
+        let bft = self.mode == ConsensusMode::ByzantineFaultTolerant;
         for (hash, node_ids) in &hash_groups {
-            let weight: u32 = node_ids.iter()
-                .filter_map(|id| self.nodes.get(id))
-                .map(|n| n.weight)
+            let weight: f32 = node_ids.iter()
+                .filter_map(|id| self.nodes.get(*id))
+                .filter(|n| !(bft && self.is_conflicted(n, agent_id)))
+                .map(|n| self.effective_weight(n, agent_id))
                 .sum();
             
-            if weight > max_weight {
-                max_weight = weight;
-                max_hash = hash.clone();
+            if weight > report.winning_weight {
+                report.winning_weight = weight;
+                report.winning_hash = Some(hash.to_string());
             }
         }
+        report.consensus_ratio = report.winning_weight / report.total_weight;
+        report.dissenting_nodes = hash_groups.iter()
+            .filter(|(hash, _)| report.winning_hash.as_deref() != Some(**hash))
+            .flat_map(|(_, node_ids)| node_ids.iter().map(|id| id.to_string()))
+            .collect();
+        report.dissenting_nodes.sort();
         
-        // Calculate consensus percentage
-        let consensus = max_weight as f32 / total_weight as f32;
-        
-        // Determine result based on consensus threshold
-        if consensus >= self.required_consensus {
+        // Determine result based on the consensus mode and threshold
+        report.result = if self.mode.accepts(report.winning_weight, report.total_weight, self.agent_threshold(agent_id)) {
             ConsensusResult::Valid
-        } else if consensus > 0.0 {
+        } else if report.winning_weight > 0.0 {
             ConsensusResult::Uncertain
         } else {
             ConsensusResult::Invalid
+        };
+        report
+    }
+    
+    /// Get the nodes that submitted conflicting proofs for an agent, sorted by ID
+    pub fn conflicts(&self, agent_id: &str) -> Vec<String> {
+        let mut node_ids: Vec<String> = self.conflicts
+            .get(agent_id)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default();
+        node_ids.sort();
+        node_ids
+    }
+    
+    /// Serialize the node table, collected proofs, and settings to JSON
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<serde_json::Value> = self.nodes.values()
+            .map(|n| serde_json::json!({
+                "node_id": n.node_id,
+                "weight": n.weight,
+                "last_seen": n.last_seen,
+                "reputation": n.reputation,
+            }))
+            .collect();
+        
+        let proofs: serde_json::Map<String, serde_json::Value> = self.proofs.iter()
+            .map(|(agent_id, agent_proofs)| {
+                let by_node: serde_json::Map<String, serde_json::Value> = agent_proofs.iter()
+                    .map(|(node_id, proof)| (node_id.clone(), proof.to_json_value()))
+                    .collect();
+                (agent_id.clone(), by_node.into())
+            })
+            .collect();
+        
+        let conflicts: serde_json::Map<String, serde_json::Value> = self.conflicts.keys()
+            .map(|agent_id| (agent_id.clone(), self.conflicts(agent_id).into()))
+            .collect();
+        
+        serde_json::json!({
+            "required_consensus": self.required_consensus,
+            "stale_after_secs": self.stale_after_secs,
+            "conflict_discount": self.conflict_discount,
+            "min_nodes": self.min_nodes,
+            "mode": self.mode.as_str(),
+            "nodes": nodes,
+            "proofs": proofs,
+            "conflicts": conflicts,
+            "node_keys": self.node_keys,
+            "agent_thresholds": self.agent_thresholds,
+        }).to_string()
+    }
+    
+    /// Restore a validator from JSON produced by `to_json`
+    pub fn from_json(json: &str) -> Option<Self> {
+        let v: serde_json::Value = serde_json::from_str(json).ok()?;
+        
+        let mut validator = ConsensusValidator::new(v["required_consensus"].as_f64()? as f32);
+        validator.stale_after_secs = v["stale_after_secs"].as_u64();
+        validator.conflict_discount = v["conflict_discount"].as_f64()? as f32;
+        validator.min_nodes = v["min_nodes"].as_u64()? as usize;
+        if let Some(mode) = v["mode"].as_str() {
+            validator.mode = ConsensusMode::from_str(mode)?;
         }
+        
+        for node in v["nodes"].as_array()? {
+            let node_id = node["node_id"].as_str()?;
+            validator.nodes.insert(node_id.to_string(), ValidatorNode {
+                node_id: node_id.to_string(),
+                weight: u32::try_from(node["weight"].as_u64()?).ok()?,
+                last_seen: node["last_seen"].as_u64()?,
+                reputation: node["reputation"].as_f64().map_or(1.0, |r| (r as f32).clamp(0.0, 1.0)),
+            });
+        }
+        
+        for (agent_id, by_node) in v["proofs"].as_object()? {
+            let agent_proofs = validator.proofs.entry(agent_id.clone()).or_default();
+            for (node_id, proof) in by_node.as_object()? {
+                agent_proofs.insert(node_id.clone(), ExecutionProof::from_json_value(proof)?);
+            }
+        }
+        
+        for (agent_id, node_ids) in v["conflicts"].as_object()? {
+            let conflicting = validator.conflicts.entry(agent_id.clone()).or_default();
+            for node_id in node_ids.as_array()? {
+                conflicting.insert(node_id.as_str()?.to_string());
+            }
+        }
+        
+        if let Some(node_keys) = v["node_keys"].as_object() {
+            for (node_id, key) in node_keys {
+                validator.node_keys.insert(node_id.clone(), key.as_str()?.to_string());
+            }
+        }
+        
+        if let Some(agent_thresholds) = v["agent_thresholds"].as_object() {
+            for (agent_id, threshold) in agent_thresholds {
+                validator.set_agent_threshold(agent_id, threshold.as_f64()? as f32);
+            }
+        }
+        
+        Some(validator)
+    }
+    
+    /// Get a node's reputation, or `None` if the node is unknown
+    pub fn reputation(&self, node_id: &str) -> Option<f32> {
+        self.nodes.get(node_id).map(|n| n.reputation)
     }
     
     /// Get all known validator nodes
@@ -170,6 +525,362 @@ impl ConsensusValidator {
     
     /// Set the required consensus threshold
     pub fn set_required_consensus(&mut self, consensus: f32) {
-        self.required_consensus = consensus.max(0.0).min(1.0);
+        self.required_consensus = consensus.clamp(0.0, 1.0);
     }
-}
\ No newline at end of file
+    
+    /// Get the consensus mode
+    pub fn mode(&self) -> ConsensusMode {
+        self.mode
+    }
+    
+    /// Set the rule used to decide consensus
+    pub fn set_mode(&mut self, mode: ConsensusMode) {
+        self.mode = mode;
+    }
+    
+    /// Get the consensus threshold applied to an agent
+    ///
+    /// This is the agent's override if one is set, otherwise the global threshold.
+    pub fn agent_threshold(&self, agent_id: &str) -> f32 {
+        self.agent_thresholds
+            .get(agent_id)
+            .copied()
+            .unwrap_or(self.required_consensus)
+    }
+    
+    /// Override the required consensus threshold for a single agent
+    pub fn set_agent_threshold(&mut self, agent_id: &str, threshold: f32) {
+        self.agent_thresholds.insert(agent_id.to_string(), threshold.clamp(0.0, 1.0));
+    }
+    
+    /// Get the minimum number of nodes that must report before consensus is reached
+    pub fn min_nodes(&self) -> usize {
+        self.min_nodes
+    }
+    
+    /// Set the minimum number of nodes that must report before consensus is reached
+    pub fn set_min_nodes(&mut self, min_nodes: usize) {
+        self.min_nodes = min_nodes;
+    }
+    
+    /// Skip nodes not seen within the given age during validation, or `None` to count all nodes
+    pub fn set_stale_after_secs(&mut self, max_age_secs: Option<u64>) {
+        self.stale_after_secs = max_age_secs;
+    }
+    
+    /// Set the fraction of weight removed from conflicting nodes during validation
+    ///
+    /// 0.0 (the default) counts conflicting nodes in full; 1.0 ignores them.
+    pub fn set_conflict_discount(&mut self, discount: f32) {
+        self.conflict_discount = discount.clamp(0.0, 1.0);
+    }
+    
+    /// Weight a node contributes to an agent's consensus
+    ///
+    /// This is its weight scaled by its reputation, less any conflict discount.
+    fn effective_weight(&self, node: &ValidatorNode, agent_id: &str) -> f32 {
+        let weight = node.weight as f32 * node.reputation;
+        if self.is_conflicted(node, agent_id) {
+            weight * (1.0 - self.conflict_discount)
+        } else {
+            weight
+        }
+    }
+    
+    /// Whether a node has submitted conflicting proofs for an agent
+    fn is_conflicted(&self, node: &ValidatorNode, agent_id: &str) -> bool {
+        self.conflicts
+            .get(agent_id)
+            .is_some_and(|ids| ids.contains(&node.node_id))
+    }
+    
+    /// Whether validation skips a node because it has not been seen recently
+    fn is_stale(&self, node: &ValidatorNode, now: u64) -> bool {
+        match self.stale_after_secs {
+            Some(max_age) => node.last_seen < now.saturating_sub(max_age),
+            None => false,
+        }
+    }
+}
+
+/// Current Unix time in seconds
+fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_reporting_two_outputs_for_one_input_is_flagged() {
+        let mut validator = ConsensusValidator::new(0.5);
+        validator.add_node("honest", 1);
+        validator.add_node("flip-flop", 1);
+
+        // Reruns get a fresh nonce, and later executions a new input
+        validator.add_proof("honest", ExecutionProof::new("agent", b"input", b"output"));
+        validator.add_proof("honest", ExecutionProof::new("agent", b"input", b"output"));
+        validator.add_proof("honest", ExecutionProof::new("agent", b"next input", b"next output"));
+
+        validator.add_proof("flip-flop", ExecutionProof::new("agent", b"input", b"output"));
+        validator.add_proof("flip-flop", ExecutionProof::new("agent", b"input", b"forged output"));
+
+        assert_eq!(validator.conflicts("agent"), vec!["flip-flop".to_string()]);
+    }
+
+    /// Proof of the shared test execution with the given output, made `age_secs` ago
+    ///
+    /// Deterministic proofs of the same execution share a proof hash, so nodes
+    /// reporting the same output agree.
+    fn proof_aged(output: &[u8], age_secs: u64) -> ExecutionProof {
+        ExecutionProof::new_deterministic("agent", b"input", output, current_timestamp() - age_secs, None)
+    }
+
+    #[test]
+    fn expired_proofs_carry_no_weight() {
+        let mut validator = ConsensusValidator::new(0.5);
+        validator.add_node("a", 1);
+        validator.add_node("b", 1);
+
+        let mut fresh = proof_aged(b"output", 0);
+        fresh.set_ttl_secs(Some(600));
+        validator.add_proof("a", fresh.clone());
+        validator.add_proof("b", fresh);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+
+        let mut expired = proof_aged(b"output", 120);
+        expired.set_ttl_secs(Some(60));
+        validator.add_proof("a", expired.clone());
+        validator.add_proof("b", expired);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+        assert!(validator.validate_detailed("agent").participating_nodes.is_empty());
+    }
+
+    #[test]
+    fn stale_nodes_stop_counting_towards_consensus() {
+        let mut validator = ConsensusValidator::new(0.9);
+        for node_id in ["a", "b", "silent"] {
+            validator.add_node(node_id, 1);
+        }
+        let proof = proof_aged(b"output", 0);
+        validator.add_proof("a", proof.clone());
+        validator.add_proof("b", proof);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+
+        validator.nodes.get_mut("silent").unwrap().last_seen = current_timestamp() - 120;
+        validator.set_stale_after_secs(Some(60));
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+
+        validator.set_stale_after_secs(None);
+        assert_eq!(validator.evict_stale(60), vec!["silent".to_string()]);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+        assert!(!validator.nodes().contains_key("silent"));
+    }
+
+    #[test]
+    fn consensus_waits_for_the_minimum_node_count() {
+        let mut validator = ConsensusValidator::new(0.5);
+        validator.add_node("heavy", 10);
+        validator.add_node("light", 1);
+        validator.add_node("idle", 1);
+        validator.set_min_nodes(2);
+
+        let proof = proof_aged(b"output", 0);
+        validator.add_proof("heavy", proof.clone());
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+
+        validator.add_proof("light", proof);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+    }
+
+    #[test]
+    fn restored_validator_reaches_the_same_verdicts() {
+        let mut validator = ConsensusValidator::new(0.6);
+        validator.add_node("a", 3);
+        validator.add_node("b", 2);
+        validator.add_node("c", 1);
+        validator.set_agent_threshold("strict", 0.9);
+
+        let agreed = proof_aged(b"output", 0);
+        validator.add_proof("a", agreed.clone());
+        validator.add_proof("b", agreed);
+        validator.add_proof("c", proof_aged(b"other", 0));
+
+        let restored = ConsensusValidator::from_json(&validator.to_json()).unwrap();
+
+        assert_eq!(restored.validate_detailed("agent"), validator.validate_detailed("agent"));
+        assert_eq!(restored.agent_threshold("strict"), 0.9);
+        for (node_id, node) in validator.nodes() {
+            let restored_node = &restored.nodes()[node_id];
+            assert_eq!(restored_node.weight(), node.weight());
+            assert_eq!(restored_node.last_seen(), node.last_seen());
+        }
+    }
+
+    #[test]
+    fn proofs_not_signed_with_the_registered_key_are_rejected() {
+        use ed25519_dalek::SigningKey;
+
+        let node_key = SigningKey::from_bytes(&[1u8; 32]);
+        let forger_key = SigningKey::from_bytes(&[2u8; 32]);
+        let mut validator = ConsensusValidator::new(0.5);
+        validator.add_node("a", 1);
+        let mut genuine = proof_aged(b"output", 0);
+        genuine.sign(&node_key);
+        assert!(validator.register_node_key("a", genuine.public_key().unwrap()));
+
+        let mut forged = proof_aged(b"forged output", 0);
+        forged.sign(&forger_key);
+        assert!(!validator.add_proof("a", forged));
+        assert!(!validator.add_proof("a", proof_aged(b"unsigned output", 0)));
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+
+        assert!(validator.add_proof("a", genuine));
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+    }
+
+    #[test]
+    fn agent_thresholds_resolve_independently() {
+        let mut validator = ConsensusValidator::new(0.5);
+        for node_id in ["a", "b", "c"] {
+            validator.add_node(node_id, 1);
+        }
+        validator.set_agent_threshold("coordinator", 0.9);
+        validator.set_agent_threshold("analyzer", 0.6);
+
+        let now = current_timestamp();
+        for agent_id in ["coordinator", "analyzer", "default"] {
+            let proof = ExecutionProof::new_deterministic(agent_id, b"input", b"output", now, None);
+            validator.add_proof("a", proof.clone());
+            validator.add_proof("b", proof);
+        }
+
+        assert_eq!(validator.validate("coordinator"), ConsensusResult::Uncertain);
+        assert_eq!(validator.validate("analyzer"), ConsensusResult::Valid);
+        assert_eq!(validator.validate("default"), ConsensusResult::Valid);
+
+        validator.set_agent_threshold("analyzer", 1.5);
+        assert_eq!(validator.agent_threshold("analyzer"), 1.0);
+    }
+
+    #[test]
+    fn byzantine_mode_rejects_what_plurality_accepts() {
+        let mut validator = ConsensusValidator::new(0.5);
+        for node_id in ["a", "b", "c", "d", "e"] {
+            validator.add_node(node_id, 1);
+        }
+        let agreed = proof_aged(b"output", 0);
+        for node_id in ["a", "b", "c"] {
+            validator.add_proof(node_id, agreed.clone());
+        }
+        validator.add_proof("d", proof_aged(b"forged output", 0));
+
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+        validator.set_mode(ConsensusMode::SuperMajority);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+        validator.set_mode(ConsensusMode::ByzantineFaultTolerant);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+    }
+
+    #[test]
+    fn exactly_two_thirds_is_a_super_majority_but_not_byzantine_agreement() {
+        let mut validator = ConsensusValidator::new(0.5);
+        for node_id in ["a", "b", "c"] {
+            validator.add_node(node_id, 1);
+        }
+        let agreed = proof_aged(b"output", 0);
+        validator.add_proof("a", agreed.clone());
+        validator.add_proof("b", agreed);
+
+        validator.set_mode(ConsensusMode::SuperMajority);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+        validator.set_mode(ConsensusMode::ByzantineFaultTolerant);
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+    }
+
+    #[test]
+    fn dishonest_nodes_lose_influence_over_rounds() {
+        let mut validator = ConsensusValidator::new(0.5);
+        for node_id in ["a", "b", "liar"] {
+            validator.add_node(node_id, 1);
+        }
+
+        let honest = proof_aged(b"output", 0);
+        let forged = proof_aged(b"forged output", 0);
+        let mut reputations = Vec::new();
+        for _ in 0..3 {
+            validator.add_proof("a", honest.clone());
+            validator.add_proof("b", honest.clone());
+            validator.add_proof("liar", forged.clone());
+            assert_eq!(validator.finalize("agent"), ConsensusResult::Valid);
+            reputations.push(validator.reputation("liar").unwrap());
+        }
+        assert_eq!(reputations, vec![0.5, 0.25, 0.125]);
+        assert_eq!(validator.reputation("a"), Some(1.0));
+
+        // Equal nominal weights, but the liar's vote no longer matches an honest one
+        validator.add_proof("a", honest.clone());
+        validator.add_proof("liar", forged);
+        let report = validator.validate_detailed("agent");
+        assert_eq!(report.winning_hash.as_deref(), Some(honest.proof_hash()));
+        assert_eq!(report.dissenting_nodes, vec!["liar".to_string()]);
+    }
+
+    #[test]
+    fn proofs_outside_the_window_are_not_tallied() {
+        let mut validator = ConsensusValidator::new(0.5);
+        for node_id in ["a", "b", "c"] {
+            validator.add_node(node_id, 1);
+        }
+        let old = proof_aged(b"old output", 3600);
+        validator.add_proof("a", old.clone());
+        validator.add_proof("b", old);
+        validator.add_proof("c", proof_aged(b"new output", 0));
+
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+        assert_eq!(validator.validate_within("agent", 60), ConsensusResult::Uncertain);
+        assert_eq!(validator.validate_within("agent", 7200), ConsensusResult::Valid);
+    }
+
+    #[test]
+    fn detailed_report_matches_a_hand_tally() {
+        let mut validator = ConsensusValidator::new(0.5);
+        validator.add_node("a", 3);
+        validator.add_node("b", 1);
+        validator.add_node("c", 1);
+        validator.add_node("idle", 1);
+        let agreed = proof_aged(b"output", 0);
+        validator.add_proof("a", agreed.clone());
+        validator.add_proof("b", agreed.clone());
+        validator.add_proof("c", proof_aged(b"forged output", 0));
+
+        let report = validator.validate_detailed("agent");
+        assert_eq!(report.result, ConsensusResult::Valid);
+        assert_eq!(report.total_weight, 6.0);
+        assert_eq!(report.winning_weight, 4.0);
+        assert_eq!(report.winning_hash.as_deref(), Some(agreed.proof_hash()));
+        assert_eq!(report.consensus_ratio, 4.0 / 6.0);
+        assert_eq!(report.participating_nodes, vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(report.dissenting_nodes, vec!["c".to_string()]);
+        assert_eq!(validator.validate("agent"), report.result);
+    }
+
+    #[test]
+    fn weight_changes_apply_on_the_next_validation() {
+        let mut validator = ConsensusValidator::new(0.6);
+        for node_id in ["a", "b"] {
+            validator.add_node(node_id, 1);
+        }
+        validator.add_proof("a", proof_aged(b"output", 0));
+        assert_eq!(validator.validate("agent"), ConsensusResult::Uncertain);
+
+        assert!(validator.set_node_weight("a", 2));
+        assert_eq!(validator.validate("agent"), ConsensusResult::Valid);
+        assert!(!validator.set_node_weight("unknown", 2));
+        assert_eq!(validator.nodes()["a"].weight(), 2);
+    }
+}
@@ -0,0 +1,3 @@
+//! Consensus validation
+
+pub mod consensus;
@@ -0,0 +1,115 @@
+//! End-to-end verification of chained execution proofs
+
+use std::error::Error;
+use std::fmt;
+
+use crate::verifier::proof::ExecutionProof;
+
+/// Why a sequence of proofs does not form a valid chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    /// The proof, input, and output slices have different lengths
+    LengthMismatch {
+        proofs: usize,
+        inputs: usize,
+        outputs: usize,
+    },
+    /// The proof at `index` does not verify against its input and output
+    InvalidProof { index: usize },
+    /// The proof at `index` does not link to the proof before it
+    BrokenLink { index: usize },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::LengthMismatch { proofs, inputs, outputs } => write!(
+                f, "Chain length mismatch: {} proofs, {} inputs, {} outputs", proofs, inputs, outputs
+            ),
+            ChainError::InvalidProof { index } => write!(f, "Proof {} failed verification", index),
+            ChainError::BrokenLink { index } => {
+                write!(f, "Proof {} does not link to the previous proof", index)
+            }
+        }
+    }
+}
+
+impl Error for ChainError {}
+
+/// Verify that `proofs` form an unbroken chain over the given inputs and outputs
+///
+/// Every proof must verify against its input and output under the agent id of
+/// the first proof, and each proof after the first must name its predecessor's
+/// proof hash as `prev_hash`. The first proof may link to earlier history.
+/// Keyed proofs never verify here. Errors report the first index at fault.
+pub fn verify_chain(proofs: &[ExecutionProof], inputs: &[&[u8]], outputs: &[&[u8]]) -> Result<(), ChainError> {
+    if proofs.len() != inputs.len() || proofs.len() != outputs.len() {
+        return Err(ChainError::LengthMismatch {
+            proofs: proofs.len(),
+            inputs: inputs.len(),
+            outputs: outputs.len(),
+        });
+    }
+    let agent_id = match proofs.first() {
+        Some(first) => first.agent_id(),
+        None => return Ok(()),
+    };
+
+    for (index, proof) in proofs.iter().enumerate() {
+        if index > 0 && proof.prev_hash() != Some(proofs[index - 1].proof_hash()) {
+            return Err(ChainError::BrokenLink { index });
+        }
+        if !proof.verify(agent_id, inputs[index], outputs[index]) {
+            return Err(ChainError::InvalidProof { index });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUTS: [&[u8]; 3] = [b"a", b"b", b"c"];
+    const OUTPUTS: [&[u8]; 3] = [b"1", b"2", b"3"];
+
+    fn chain() -> Vec<ExecutionProof> {
+        let first = ExecutionProof::new("agent", INPUTS[0], OUTPUTS[0]);
+        let second = ExecutionProof::new_chained("agent", INPUTS[1], OUTPUTS[1], &first);
+        let third = ExecutionProof::new_chained("agent", INPUTS[2], OUTPUTS[2], &second);
+        vec![first, second, third]
+    }
+
+    #[test]
+    fn valid_chain_verifies() {
+        assert_eq!(verify_chain(&chain(), &INPUTS, &OUTPUTS), Ok(()));
+        assert_eq!(verify_chain(&[], &[], &[]), Ok(()));
+    }
+
+    #[test]
+    fn reordered_chain_is_rejected() {
+        let mut proofs = chain();
+        proofs.swap(1, 2);
+        let inputs = [INPUTS[0], INPUTS[2], INPUTS[1]];
+        let outputs = [OUTPUTS[0], OUTPUTS[2], OUTPUTS[1]];
+
+        assert_eq!(verify_chain(&proofs, &inputs, &outputs), Err(ChainError::BrokenLink { index: 1 }));
+    }
+
+    #[test]
+    fn tampered_middle_proof_is_rejected() {
+        let proofs = chain();
+        let outputs = [OUTPUTS[0], b"forged".as_slice(), OUTPUTS[2]];
+        assert_eq!(verify_chain(&proofs, &INPUTS, &outputs), Err(ChainError::InvalidProof { index: 1 }));
+
+        let mut replaced = chain();
+        replaced[1] = ExecutionProof::new_chained("agent", INPUTS[1], OUTPUTS[1], &replaced[0]);
+        assert_eq!(verify_chain(&replaced, &INPUTS, &OUTPUTS), Err(ChainError::BrokenLink { index: 2 }));
+    }
+
+    #[test]
+    fn mismatched_lengths_are_rejected() {
+        let error = verify_chain(&chain(), &INPUTS[..2], &OUTPUTS).unwrap_err();
+        assert_eq!(error, ChainError::LengthMismatch { proofs: 3, inputs: 2, outputs: 3 });
+    }
+}
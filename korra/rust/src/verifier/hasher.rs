@@ -0,0 +1,163 @@
+//! Hash algorithms used to build execution proofs
+
+use std::fmt;
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+/// Chunk size used when hashing from a reader
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Hash algorithm used for proof digests
+pub trait ProofHasher {
+    /// Algorithm tag recorded in serialized proofs
+    fn algorithm(&self) -> &'static str;
+
+    /// Digest the concatenation of `parts`
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8>;
+
+    /// Digest everything read from `reader`, one chunk at a time
+    fn digest_reader(&self, reader: &mut dyn Read) -> io::Result<Vec<u8>>;
+}
+
+/// Feed `reader` to `update` in fixed-size chunks until end of input
+fn read_chunks(reader: &mut dyn Read, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => update(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// SHA-256, the default proof hasher
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl ProofHasher for Sha256Hasher {
+    fn algorithm(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    fn digest_reader(&self, reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+        let mut hasher = Sha256::new();
+        read_chunks(reader, |chunk| hasher.update(chunk))?;
+        Ok(hasher.finalize().to_vec())
+    }
+}
+
+/// BLAKE3, available with the `blake3` feature
+#[cfg(feature = "blake3")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+impl ProofHasher for Blake3Hasher {
+    fn algorithm(&self) -> &'static str {
+        "blake3"
+    }
+
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().as_bytes().to_vec()
+    }
+
+    fn digest_reader(&self, reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+        let mut hasher = blake3::Hasher::new();
+        read_chunks(reader, |chunk| {
+            hasher.update(chunk);
+        })?;
+        Ok(hasher.finalize().as_bytes().to_vec())
+    }
+}
+
+/// HMAC-SHA256 block size in bytes
+const HMAC_BLOCK_SIZE: usize = 64;
+
+/// HMAC-SHA256 keyed with a secret, so only key holders can produce digests
+///
+/// Keyed proofs are tagged `hmac-sha256` and never carry the key, so
+/// `hasher_for` cannot resolve them; verify them with this hasher instead.
+#[derive(Clone)]
+pub struct HmacSha256Hasher {
+    key_block: [u8; HMAC_BLOCK_SIZE],
+}
+
+impl HmacSha256Hasher {
+    /// Create a hasher keyed with `key`
+    pub fn new(key: &[u8]) -> Self {
+        // Keys longer than a block are hashed first, per RFC 2104
+        let mut key_block = [0u8; HMAC_BLOCK_SIZE];
+        if key.len() > HMAC_BLOCK_SIZE {
+            key_block[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            key_block[..key.len()].copy_from_slice(key);
+        }
+        HmacSha256Hasher { key_block }
+    }
+
+    /// Start the inner hash, already fed the inner padded key
+    fn inner(&self) -> Sha256 {
+        let mut inner = Sha256::new();
+        inner.update(self.key_block.map(|b| b ^ 0x36));
+        inner
+    }
+
+    /// Finish the HMAC from the inner hash
+    fn outer(&self, inner: Sha256) -> Vec<u8> {
+        let mut outer = Sha256::new();
+        outer.update(self.key_block.map(|b| b ^ 0x5c));
+        outer.update(inner.finalize());
+        outer.finalize().to_vec()
+    }
+}
+
+impl fmt::Debug for HmacSha256Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HmacSha256Hasher { .. }")
+    }
+}
+
+impl ProofHasher for HmacSha256Hasher {
+    fn algorithm(&self) -> &'static str {
+        "hmac-sha256"
+    }
+
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut inner = self.inner();
+        for part in parts {
+            inner.update(part);
+        }
+        self.outer(inner)
+    }
+
+    fn digest_reader(&self, reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+        let mut inner = self.inner();
+        read_chunks(reader, |chunk| inner.update(chunk))?;
+        Ok(self.outer(inner))
+    }
+}
+
+/// Look up the hasher for an algorithm tag, if it is built in
+pub fn hasher_for(algorithm: &str) -> Option<&'static dyn ProofHasher> {
+    match algorithm {
+        "sha256" => Some(&Sha256Hasher),
+        #[cfg(feature = "blake3")]
+        "blake3" => Some(&Blake3Hasher),
+        _ => None,
+    }
+}
@@ -0,0 +1,215 @@
+//! Merkle tree over batches of execution proofs
+
+use base64::{Engine as _, engine::general_purpose};
+use subtle::ConstantTimeEq;
+
+use crate::verifier::hasher::{ProofHasher, Sha256Hasher};
+use crate::verifier::proof::ExecutionProof;
+
+/// Prefix for leaf hashes, so a leaf can never be mistaken for an inner node
+const LEAF_PREFIX: &[u8] = &[0x00];
+
+/// Prefix for inner node hashes
+const NODE_PREFIX: &[u8] = &[0x01];
+
+/// SHA-256 Merkle tree over the proof hashes of a batch of proofs
+///
+/// Leaves keep the order proofs were added in. A node without a sibling is
+/// promoted to the next level unchanged rather than paired with itself, so
+/// repeating the last proof always changes the root.
+#[derive(Debug, Clone, Default)]
+pub struct ProofTree {
+    leaves: Vec<Vec<u8>>,
+}
+
+/// One step from a leaf towards the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStep {
+    /// Base64 hash of the sibling node
+    pub sibling: String,
+    /// Whether the sibling is the left child
+    pub sibling_on_left: bool,
+}
+
+/// Proof that a proof hash is a leaf of a tree with a given root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    /// Position of the leaf in the batch
+    pub index: usize,
+    /// Siblings from the leaf up to the root; promoted levels are skipped
+    pub path: Vec<PathStep>,
+}
+
+impl ProofTree {
+    /// Create an empty tree
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a tree over `proofs` in order
+    pub fn from_proofs(proofs: &[ExecutionProof]) -> Self {
+        let mut tree = Self::new();
+        for proof in proofs {
+            tree.push(proof);
+        }
+        tree
+    }
+
+    /// Add a proof as the next leaf
+    pub fn push(&mut self, proof: &ExecutionProof) {
+        self.leaves.push(leaf_hash(proof.proof_hash()));
+    }
+
+    /// Get the number of leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Whether the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Get the base64 root hash, or `None` for an empty tree
+    pub fn root(&self) -> Option<String> {
+        let mut level = self.leaves.clone();
+        if level.is_empty() {
+            return None;
+        }
+        while level.len() > 1 {
+            level = next_level(&level);
+        }
+        Some(general_purpose::STANDARD.encode(&level[0]))
+    }
+
+    /// Build the inclusion proof for the leaf at `index`
+    pub fn inclusion_proof(&self, index: usize) -> Option<InclusionProof> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut level = self.leaves.clone();
+        let mut position = index;
+        while level.len() > 1 {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                path.push(PathStep {
+                    sibling: general_purpose::STANDARD.encode(&level[sibling]),
+                    sibling_on_left: sibling < position,
+                });
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+
+        Some(InclusionProof { index, path })
+    }
+}
+
+impl InclusionProof {
+    /// Check that `proof_hash` is included in the tree with root `root`
+    pub fn verify(&self, proof_hash: &str, root: &str) -> bool {
+        let mut current = leaf_hash(proof_hash);
+        for step in &self.path {
+            let sibling = match general_purpose::STANDARD.decode(&step.sibling) {
+                Ok(sibling) => sibling,
+                Err(_) => return false,
+            };
+            current = if step.sibling_on_left {
+                node_hash(&sibling, &current)
+            } else {
+                node_hash(&current, &sibling)
+            };
+        }
+
+        match general_purpose::STANDARD.decode(root) {
+            Ok(root) => root.ct_eq(&current).into(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Hash a proof hash into a leaf
+fn leaf_hash(proof_hash: &str) -> Vec<u8> {
+    Sha256Hasher.digest(&[LEAF_PREFIX, proof_hash.as_bytes()])
+}
+
+/// Hash two children into their parent
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    Sha256Hasher.digest(&[NODE_PREFIX, left, right])
+}
+
+/// Pair up the nodes of one level, promoting a trailing odd node
+fn next_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => single.clone(),
+            _ => unreachable!("chunks(2) yields one or two nodes"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proofs(count: usize) -> Vec<ExecutionProof> {
+        (0..count)
+            .map(|i| ExecutionProof::new_deterministic("agent", format!("input-{}", i).as_bytes(), b"output", 1_700_000_000, None))
+            .collect()
+    }
+
+    #[test]
+    fn root_depends_only_on_the_proofs_in_order() {
+        let batch = proofs(5);
+        let root = ProofTree::from_proofs(&batch).root().unwrap();
+        assert_eq!(ProofTree::from_proofs(&batch).root().unwrap(), root);
+        assert_eq!(ProofTree::from_proofs(&proofs(5)).root().unwrap(), root);
+
+        let mut reordered = batch.clone();
+        reordered.swap(0, 1);
+        assert_ne!(ProofTree::from_proofs(&reordered).root().unwrap(), root);
+
+        let mut repeated = batch.clone();
+        repeated.push(batch[4].clone());
+        assert_ne!(ProofTree::from_proofs(&repeated).root().unwrap(), root);
+        assert_eq!(ProofTree::new().root(), None);
+    }
+
+    #[test]
+    fn inclusion_proofs_verify_every_leaf() {
+        let batch = proofs(5);
+        let tree = ProofTree::from_proofs(&batch);
+        let root = tree.root().unwrap();
+
+        for (index, proof) in batch.iter().enumerate() {
+            let inclusion = tree.inclusion_proof(index).unwrap();
+            assert!(inclusion.verify(proof.proof_hash(), &root), "leaf {}", index);
+        }
+        assert!(tree.inclusion_proof(5).is_none());
+    }
+
+    #[test]
+    fn tampered_inclusion_proofs_are_rejected() {
+        let batch = proofs(4);
+        let tree = ProofTree::from_proofs(&batch);
+        let root = tree.root().unwrap();
+        let inclusion = tree.inclusion_proof(1).unwrap();
+
+        assert!(!inclusion.verify(batch[2].proof_hash(), &root));
+
+        let mut flipped = inclusion.clone();
+        flipped.path[0].sibling_on_left = !flipped.path[0].sibling_on_left;
+        assert!(!flipped.verify(batch[1].proof_hash(), &root));
+
+        let mut swapped = inclusion.clone();
+        swapped.path[0].sibling = tree.inclusion_proof(2).unwrap().path[0].sibling.clone();
+        assert!(!swapped.verify(batch[1].proof_hash(), &root));
+
+        let other_root = ProofTree::from_proofs(&proofs(3)).root().unwrap();
+        assert!(!inclusion.verify(batch[1].proof_hash(), &other_root));
+    }
+}
@@ -0,0 +1,8 @@
+//! Execution proof verification
+
+pub mod chain;
+pub mod hasher;
+pub mod merkle;
+pub mod proof;
+
+pub use chain::{verify_chain, ChainError};
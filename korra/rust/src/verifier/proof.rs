@@ -1,110 +1,475 @@
 //! Execution proof generator and validator
 
+use std::collections::BTreeMap;
+use std::io::{self, Read};
 use std::time::{SystemTime, UNIX_EPOCH};
-use sha2::{Sha256, Digest};
 use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+
+use crate::verifier::hasher::{hasher_for, HmacSha256Hasher, ProofHasher, Sha256Hasher};
+
+/// Algorithm assumed for serialized proofs that predate algorithm tags
+const DEFAULT_ALGORITHM: &str = "sha256";
+
+/// Size of the random per-proof nonce
+const NONCE_SIZE: usize = 16;
+
+/// Separates the metadata from the other proof hash inputs; never appears in base64
+const METADATA_SEPARATOR: &[u8] = &[0];
+
+/// Domain separator for nonces derived by `ExecutionProof::new_deterministic`
+const DETERMINISTIC_NONCE_DOMAIN: &[u8] = b"korra.deterministic-nonce";
 
 /// Execution proof for agent execution
+///
+/// Every proof carries a random nonce folded into its proof hash, so the
+/// proof hash is unique per execution even for identical input and output.
+/// Deterministic proofs are the exception: see `new_deterministic`.
 #[derive(Debug, Clone)]
 pub struct ExecutionProof {
     agent_id: String,
     timestamp: u64,
     input_hash: String,
     output_hash: String,
+    nonce: [u8; NONCE_SIZE],
+    prev_hash: Option<String>,
+    algorithm: String,
     proof_hash: String,
+    signature: Option<String>,
+    public_key: Option<String>,
+    ttl_secs: Option<u64>,
+    metadata: ProofMetadata,
+}
+
+/// Optional context about an execution, covered by the proof hash
+///
+/// Proofs without metadata serialize and hash exactly like proofs made before
+/// metadata existed, so old proofs still parse and verify.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofMetadata {
+    /// Type of the agent that ran, e.g. `transformer`
+    pub agent_type: Option<String>,
+    /// Base64 SHA-256 of the WASM module that ran
+    pub module_hash: Option<String>,
+    /// Free-form labels for auditors
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ProofMetadata {
+    /// Whether no metadata is set
+    pub fn is_empty(&self) -> bool {
+        self.agent_type.is_none() && self.module_hash.is_none() && self.labels.is_empty()
+    }
 }
 
 impl ExecutionProof {
-    /// Create a new execution proof
+    /// Create a new execution proof using SHA-256
     pub fn new(agent_id: &str, input: &[u8], output: &[u8]) -> Self {
+        Self::new_with_hasher(&Sha256Hasher, agent_id, input, output)
+    }
+    
+    /// Create a new execution proof using the given hash algorithm
+    pub fn new_with_hasher(hasher: &dyn ProofHasher, agent_id: &str, input: &[u8], output: &[u8]) -> Self {
+        Self::build(hasher, agent_id, hash_bytes(hasher, input), hash_bytes(hasher, output), None)
+    }
+    
+    /// Create a new SHA-256 execution proof by streaming input and output from readers
+    ///
+    /// Data is hashed in chunks, so arbitrarily large payloads never need to be
+    /// held in memory. The input and output hashes match those of `new` for the
+    /// same bytes.
+    pub fn new_streaming(
+        agent_id: &str,
+        mut input_reader: impl Read,
+        mut output_reader: impl Read,
+    ) -> io::Result<Self> {
+        let hasher = &Sha256Hasher;
+        let input_hash = general_purpose::STANDARD.encode(hasher.digest_reader(&mut input_reader)?);
+        let output_hash = general_purpose::STANDARD.encode(hasher.digest_reader(&mut output_reader)?);
+        Ok(Self::build(hasher, agent_id, input_hash, output_hash, None))
+    }
+    
+    /// Create an execution proof linked to the proof that preceded it
+    ///
+    /// The new proof uses the same hash algorithm as `prev`, falling back to
+    /// SHA-256 if that algorithm is not available in this build.
+    pub fn new_chained(agent_id: &str, input: &[u8], output: &[u8], prev: &ExecutionProof) -> Self {
+        let hasher = hasher_for(&prev.algorithm).unwrap_or(&Sha256Hasher);
+        Self::new_chained_with_hasher(hasher, agent_id, input, output, prev)
+    }
+    
+    /// Create an execution proof linked to the previous one using the given hash algorithm
+    pub fn new_chained_with_hasher(
+        hasher: &dyn ProofHasher,
+        agent_id: &str,
+        input: &[u8],
+        output: &[u8],
+        prev: &ExecutionProof,
+    ) -> Self {
+        Self::build(
+            hasher,
+            agent_id,
+            hash_bytes(hasher, input),
+            hash_bytes(hasher, output),
+            Some(prev.proof_hash.clone()),
+        )
+    }
+    
+    /// Create a reproducible proof with a caller-supplied timestamp
+    ///
+    /// The nonce is derived from the input hash and timestamp rather than drawn
+    /// at random, so the same agent id, input, output, timestamp, and previous
+    /// proof always give the same proof hash. Like `new_chained`, the hash
+    /// algorithm follows `prev` when given.
+    pub fn new_deterministic(
+        agent_id: &str,
+        input: &[u8],
+        output: &[u8],
+        timestamp: u64,
+        prev: Option<&ExecutionProof>,
+    ) -> Self {
+        let hasher = prev.and_then(|p| hasher_for(&p.algorithm)).unwrap_or(&Sha256Hasher);
+        Self::new_deterministic_with_hasher(hasher, agent_id, input, output, timestamp, prev)
+    }
+    
+    /// Create a reproducible proof using the given hash algorithm
+    pub fn new_deterministic_with_hasher(
+        hasher: &dyn ProofHasher,
+        agent_id: &str,
+        input: &[u8],
+        output: &[u8],
+        timestamp: u64,
+        prev: Option<&ExecutionProof>,
+    ) -> Self {
+        let input_hash = hash_bytes(hasher, input);
+        let digest = hasher.digest(&[DETERMINISTIC_NONCE_DOMAIN, input_hash.as_bytes(), &timestamp.to_le_bytes()]);
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&digest[..NONCE_SIZE]);
+        
+        Self::assemble(
+            hasher,
+            agent_id,
+            timestamp,
+            input_hash,
+            hash_bytes(hasher, output),
+            nonce,
+            prev.map(|p| p.proof_hash.clone()),
+        )
+    }
+    
+    /// Build a proof around already computed input and output hashes
+    ///
+    /// Stamps it with the current time and a random nonce.
+    fn build(
+        hasher: &dyn ProofHasher,
+        agent_id: &str,
+        input_hash: String,
+        output_hash: String,
+        prev_hash: Option<String>,
+    ) -> Self {
         // Get current timestamp
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
         
-        // Calculate input hash
-        let mut hasher = Sha256::new();
-        hasher.update(input);
-        let input_hash = general_purpose::STANDARD.encode(hasher.finalize());
-        
-        // Calculate output hash
-        let mut hasher = Sha256::new();
-        hasher.update(output);
-        let output_hash = general_purpose::STANDARD.encode(hasher.finalize());
-        
-        // Calculate proof hash (hash of agent_id + timestamp + input_hash + output_hash)
-        let mut hasher = Sha256::new();
-        hasher.update(agent_id.as_bytes());
-        hasher.update(timestamp.to_string().as_bytes());
-        hasher.update(input_hash.as_bytes());
-        hasher.update(output_hash.as_bytes());
-        let proof_hash = general_purpose::STANDARD.encode(hasher.finalize());
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
         
-        ExecutionProof {
+        Self::assemble(hasher, agent_id, timestamp, input_hash, output_hash, nonce, prev_hash)
+    }
+    
+    /// Assemble a proof from all of its parts
+    fn assemble(
+        hasher: &dyn ProofHasher,
+        agent_id: &str,
+        timestamp: u64,
+        input_hash: String,
+        output_hash: String,
+        nonce: [u8; NONCE_SIZE],
+        prev_hash: Option<String>,
+    ) -> Self {
+        let mut proof = ExecutionProof {
             agent_id: agent_id.to_string(),
             timestamp,
             input_hash,
             output_hash,
-            proof_hash,
-        }
+            nonce,
+            prev_hash,
+            algorithm: hasher.algorithm().to_string(),
+            proof_hash: String::new(),
+            signature: None,
+            public_key: None,
+            ttl_secs: None,
+            metadata: ProofMetadata::default(),
+        };
+        proof.proof_hash = general_purpose::STANDARD.encode(proof.digest(hasher, agent_id));
+        proof
+    }
+    
+    /// Attach metadata and recompute the proof hash so it covers it
+    ///
+    /// `hasher` must be the one the proof was built with. Any signature is
+    /// dropped since it covered the old hash, so sign afterwards.
+    pub fn with_metadata(mut self, hasher: &dyn ProofHasher, metadata: ProofMetadata) -> Self {
+        self.metadata = metadata;
+        self.proof_hash = general_purpose::STANDARD.encode(self.digest(hasher, &self.agent_id));
+        self.signature = None;
+        self.public_key = None;
+        self
     }
     
     /// Verify the execution proof against input and output
+    ///
+    /// The hash algorithm is taken from the proof; proofs using an algorithm
+    /// not available in this build never verify, and neither do keyed proofs,
+    /// which need `verify_keyed`.
     pub fn verify(&self, agent_id: &str, input: &[u8], output: &[u8]) -> bool {
+        match hasher_for(&self.algorithm) {
+            Some(hasher) => self.verify_with_hasher(hasher, agent_id, input, output),
+            None => false,
+        }
+    }
+    
+    /// Verify a proof built with `HmacSha256Hasher` keyed with `key`
+    pub fn verify_keyed(&self, key: &[u8], agent_id: &str, input: &[u8], output: &[u8]) -> bool {
+        let hasher = HmacSha256Hasher::new(key);
+        self.algorithm == hasher.algorithm() && self.verify_with_hasher(&hasher, agent_id, input, output)
+    }
+    
+    /// Verify the execution proof by recomputing it with the given hash algorithm
+    pub fn verify_with_hasher(
+        &self,
+        hasher: &dyn ProofHasher,
+        agent_id: &str,
+        input: &[u8],
+        output: &[u8],
+    ) -> bool {
         // Verify agent ID
         if self.agent_id != agent_id {
             return false;
         }
         
         // Calculate and verify input hash
-        let mut hasher = Sha256::new();
-        hasher.update(input);
-        let input_hash = general_purpose::STANDARD.encode(hasher.finalize());
-        if self.input_hash != input_hash {
+        if !digest_matches(&self.input_hash, &hasher.digest(&[input])) {
             return false;
         }
         
         // Calculate and verify output hash
-        let mut hasher = Sha256::new();
-        hasher.update(output);
-        let output_hash = general_purpose::STANDARD.encode(hasher.finalize());
-        if self.output_hash != output_hash {
+        if !digest_matches(&self.output_hash, &hasher.digest(&[output])) {
             return false;
         }
         
-        // Calculate and verify proof hash
-        let mut hasher = Sha256::new();
-        hasher.update(agent_id.as_bytes());
-        hasher.update(self.timestamp.to_string().as_bytes());
-        hasher.update(self.input_hash.as_bytes());
-        hasher.update(self.output_hash.as_bytes());
-        let proof_hash = general_purpose::STANDARD.encode(hasher.finalize());
+        // Calculate and verify proof hash with the stored nonce, link to the previous proof, and metadata
+        digest_matches(&self.proof_hash, &self.digest(hasher, agent_id))
+    }
+    
+    /// Hash of agent_id + timestamp + input_hash + output_hash + nonce, followed
+    /// by prev_hash when chained and the metadata when present
+    fn digest(&self, hasher: &dyn ProofHasher, agent_id: &str) -> Vec<u8> {
+        // Proofs without metadata hash exactly as they did before it existed
+        let metadata = (!self.metadata.is_empty())
+            .then(|| serde_json::to_value(&self.metadata).map(|v| canonical_json(&v)).unwrap_or_default());
+        let timestamp = self.timestamp.to_string();
+        let mut parts = vec![
+            agent_id.as_bytes(),
+            timestamp.as_bytes(),
+            self.input_hash.as_bytes(),
+            self.output_hash.as_bytes(),
+            self.nonce.as_slice(),
+        ];
+        if let Some(prev_hash) = &self.prev_hash {
+            parts.push(prev_hash.as_bytes());
+        }
+        if let Some(metadata) = &metadata {
+            parts.push(METADATA_SEPARATOR);
+            parts.push(metadata);
+        }
+        hasher.digest(&parts)
+    }
+    
+    /// Set how long the proof stays valid after its timestamp, or `None` for no expiry
+    pub fn set_ttl_secs(&mut self, ttl_secs: Option<u64>) {
+        self.ttl_secs = ttl_secs;
+    }
+    
+    /// Check whether the proof has aged out at the given Unix time
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.ttl_secs {
+            Some(ttl) => now >= self.timestamp.saturating_add(ttl),
+            None => false,
+        }
+    }
+    
+    /// Sign the proof hash with a node's Ed25519 key
+    ///
+    /// The signature and the matching public key are stored base64-encoded, so
+    /// the proof can be checked without knowing the signer up front.
+    pub fn sign(&mut self, signing_key: &SigningKey) {
+        let signature = signing_key.sign(self.proof_hash.as_bytes());
+        self.signature = Some(general_purpose::STANDARD.encode(signature.to_bytes()));
+        self.public_key = Some(general_purpose::STANDARD.encode(signing_key.verifying_key().to_bytes()));
+    }
+    
+    /// Verify the stored signature over the proof hash
+    ///
+    /// Unsigned proofs and malformed keys or signatures never verify.
+    pub fn verify_signature(&self) -> bool {
+        match self.decode_signature() {
+            Some((signature, public_key)) => {
+                public_key.verify(self.proof_hash.as_bytes(), &signature).is_ok()
+            }
+            None => false,
+        }
+    }
+    
+    /// Decode the stored signature and public key, if present and well formed
+    fn decode_signature(&self) -> Option<(Signature, VerifyingKey)> {
+        let signature = general_purpose::STANDARD.decode(self.signature.as_ref()?).ok()?;
+        let signature = Signature::from_slice(&signature).ok()?;
         
-        self.proof_hash == proof_hash
+        let public_key = general_purpose::STANDARD.decode(self.public_key.as_ref()?).ok()?;
+        let public_key = VerifyingKey::from_bytes(&public_key.try_into().ok()?).ok()?;
+        
+        Some((signature, public_key))
     }
     
     /// Serialize the proof to JSON
+    ///
+    /// Intended for display and debugging; use `to_canonical_bytes` for
+    /// anything that is hashed or compared across nodes.
     pub fn to_json(&self) -> String {
-        serde_json::json!({
-            "agent_id": self.agent_id,
-            "timestamp": self.timestamp,
-            "input_hash": self.input_hash,
-            "output_hash": self.output_hash,
-            "proof_hash": self.proof_hash,
-        }).to_string()
+        serde_json::to_string(&self.json_fields()).unwrap_or_default()
+    }
+    
+    /// Serialize the proof to canonical JSON bytes
+    ///
+    /// Keys are sorted at every level, no whitespace is emitted, and every
+    /// number is an integer in plain decimal, so every node produces the same
+    /// bytes for the same proof regardless of serde_json features.
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        canonical_json(&self.to_json_value())
+    }
+    
+    /// All serialized fields, keyed by JSON name
+    fn json_fields(&self) -> BTreeMap<&'static str, serde_json::Value> {
+        let mut fields = BTreeMap::from([
+            ("agent_id", self.agent_id.clone().into()),
+            ("timestamp", self.timestamp.into()),
+            ("input_hash", self.input_hash.clone().into()),
+            ("output_hash", self.output_hash.clone().into()),
+            ("nonce", general_purpose::STANDARD.encode(self.nonce).into()),
+            ("prev_hash", self.prev_hash.clone().into()),
+            ("algorithm", self.algorithm.clone().into()),
+            ("proof_hash", self.proof_hash.clone().into()),
+            ("signature", self.signature.clone().into()),
+            ("public_key", self.public_key.clone().into()),
+            ("ttl_secs", self.ttl_secs.into()),
+        ]);
+        // Metadata keys are only emitted when set, keeping older proofs' JSON unchanged
+        if let Some(agent_type) = &self.metadata.agent_type {
+            fields.insert("agent_type", agent_type.clone().into());
+        }
+        if let Some(module_hash) = &self.metadata.module_hash {
+            fields.insert("module_hash", module_hash.clone().into());
+        }
+        if !self.metadata.labels.is_empty() {
+            fields.insert("labels", serde_json::json!(self.metadata.labels));
+        }
+        fields
+    }
+    
+    /// Serialize the proof to a compact binary encoding
+    ///
+    /// Hashes, keys, and signatures are stored as raw bytes rather than base64.
+    /// Returns `None` if a stored field is not valid base64.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let decode = |s: &str| general_purpose::STANDARD.decode(s).ok();
+        let binary = BinaryProof {
+            agent_id: self.agent_id.clone(),
+            timestamp: self.timestamp,
+            input_hash: decode(&self.input_hash)?,
+            output_hash: decode(&self.output_hash)?,
+            nonce: self.nonce,
+            prev_hash: decode_optional(self.prev_hash.as_deref())?,
+            algorithm: self.algorithm.clone(),
+            proof_hash: decode(&self.proof_hash)?,
+            signature: decode_optional(self.signature.as_deref())?,
+            public_key: decode_optional(self.public_key.as_deref())?,
+            ttl_secs: self.ttl_secs,
+        };
+        // Metadata follows the original layout only when present, so proofs
+        // without it keep their old encoding
+        if self.metadata.is_empty() {
+            bincode::serialize(&binary).ok()
+        } else {
+            bincode::serialize(&(binary, &self.metadata)).ok()
+        }
+    }
+    
+    /// Deserialize the proof from its binary encoding
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (binary, metadata) = bincode::deserialize::<(BinaryProof, ProofMetadata)>(bytes)
+            .or_else(|_| bincode::deserialize::<BinaryProof>(bytes).map(|b| (b, ProofMetadata::default())))
+            .ok()?;
+        let encode = |b: Vec<u8>| general_purpose::STANDARD.encode(b);
+        
+        Some(ExecutionProof {
+            agent_id: binary.agent_id,
+            timestamp: binary.timestamp,
+            input_hash: encode(binary.input_hash),
+            output_hash: encode(binary.output_hash),
+            nonce: binary.nonce,
+            prev_hash: binary.prev_hash.map(encode),
+            algorithm: binary.algorithm,
+            proof_hash: encode(binary.proof_hash),
+            signature: binary.signature.map(encode),
+            public_key: binary.public_key.map(encode),
+            ttl_secs: binary.ttl_secs,
+            metadata,
+        })
     }
     
     /// Deserialize the proof from JSON
     pub fn from_json(json: &str) -> Option<Self> {
         let v: serde_json::Value = serde_json::from_str(json).ok()?;
-        
+        Self::from_json_value(&v)
+    }
+    
+    /// Convert the proof into a JSON value for embedding in larger documents
+    pub(crate) fn to_json_value(&self) -> serde_json::Value {
+        self.json_fields()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+    
+    /// Rebuild a proof from a JSON value produced by `to_json_value`
+    pub(crate) fn from_json_value(v: &serde_json::Value) -> Option<Self> {
         Some(ExecutionProof {
             agent_id: v["agent_id"].as_str()?.to_string(),
             timestamp: v["timestamp"].as_u64()?,
             input_hash: v["input_hash"].as_str()?.to_string(),
             output_hash: v["output_hash"].as_str()?.to_string(),
+            nonce: general_purpose::STANDARD.decode(v["nonce"].as_str()?).ok()?.try_into().ok()?,
+            prev_hash: v["prev_hash"].as_str().map(|h| h.to_string()),
+            algorithm: v["algorithm"].as_str().unwrap_or(DEFAULT_ALGORITHM).to_string(),
             proof_hash: v["proof_hash"].as_str()?.to_string(),
+            signature: v["signature"].as_str().map(|s| s.to_string()),
+            public_key: v["public_key"].as_str().map(|k| k.to_string()),
+            ttl_secs: v["ttl_secs"].as_u64(),
+            metadata: ProofMetadata {
+                agent_type: v["agent_type"].as_str().map(|t| t.to_string()),
+                module_hash: v["module_hash"].as_str().map(|h| h.to_string()),
+                labels: match v.get("labels") {
+                    Some(labels) => serde_json::from_value(labels.clone()).ok()?,
+                    None => BTreeMap::new(),
+                },
+            },
         })
     }
     
@@ -128,8 +493,277 @@ impl ExecutionProof {
         &self.output_hash
     }
     
+    /// Get the random nonce folded into the proof hash
+    pub fn nonce(&self) -> &[u8; NONCE_SIZE] {
+        &self.nonce
+    }
+    
+    /// Get the hash of the previous proof in the chain, if any
+    pub fn prev_hash(&self) -> Option<&str> {
+        self.prev_hash.as_deref()
+    }
+    
+    /// Get the hash algorithm tag
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+    
     /// Get the proof hash
     pub fn proof_hash(&self) -> &str {
         &self.proof_hash
     }
-}
\ No newline at end of file
+    
+    /// Get the validity window in seconds, if the proof expires
+    pub fn ttl_secs(&self) -> Option<u64> {
+        self.ttl_secs
+    }
+    
+    /// Get the base64-encoded signature, if signed
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+    
+    /// Get the base64-encoded public key of the signer, if signed
+    pub fn public_key(&self) -> Option<&str> {
+        self.public_key.as_deref()
+    }
+    
+    /// Get the execution metadata
+    pub fn metadata(&self) -> &ProofMetadata {
+        &self.metadata
+    }
+}
+
+/// Hash arbitrary bytes into a base64-encoded digest
+fn hash_bytes(hasher: &dyn ProofHasher, data: &[u8]) -> String {
+    general_purpose::STANDARD.encode(hasher.digest(&[data]))
+}
+
+/// Serialize a JSON value canonically: object keys sorted by their UTF-8 bytes, no whitespace
+fn canonical_json(value: &serde_json::Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &serde_json::Value, out: &mut Vec<u8>) {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push(b'{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                out.extend_from_slice(Value::from(key.as_str()).to_string().as_bytes());
+                out.push(b':');
+                write_canonical(value, out);
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(b']');
+        }
+        // Scalars have one compact form; proofs only hold integer numbers
+        scalar => out.extend_from_slice(scalar.to_string().as_bytes()),
+    }
+}
+
+/// Wire form of `ExecutionProof` used by the binary encoding
+#[derive(Serialize, Deserialize)]
+struct BinaryProof {
+    agent_id: String,
+    timestamp: u64,
+    input_hash: Vec<u8>,
+    output_hash: Vec<u8>,
+    nonce: [u8; NONCE_SIZE],
+    prev_hash: Option<Vec<u8>>,
+    algorithm: String,
+    proof_hash: Vec<u8>,
+    signature: Option<Vec<u8>>,
+    public_key: Option<Vec<u8>>,
+    ttl_secs: Option<u64>,
+}
+
+/// Decode an optional base64 field, failing only if it is present but malformed
+fn decode_optional(field: Option<&str>) -> Option<Option<Vec<u8>>> {
+    match field {
+        Some(value) => general_purpose::STANDARD.decode(value).ok().map(Some),
+        None => Some(None),
+    }
+}
+
+/// Compare a stored base64 hash with a freshly computed digest in constant time
+///
+/// The stored hash is decoded first so raw digests are compared; anything that
+/// fails to decode never matches.
+fn digest_matches(stored: &str, computed: &[u8]) -> bool {
+    match general_purpose::STANDARD.decode(stored) {
+        Ok(decoded) => decoded.ct_eq(computed).into(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labelled(labels: &[(&str, &str)]) -> ExecutionProof {
+        let metadata = ProofMetadata {
+            agent_type: Some("custom".to_string()),
+            module_hash: None,
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        ExecutionProof::new_deterministic("agent", b"input", b"output", 1_700_000_000, None)
+            .with_metadata(&Sha256Hasher, metadata)
+    }
+
+    #[test]
+    fn canonical_json_sorts_keys_at_every_level() {
+        let value = serde_json::json!({"b": 1, "a": {"d": [2, {"y": null, "x": true}], "c": "é\n"}});
+        assert_eq!(
+            canonical_json(&value),
+            r#"{"a":{"c":"é\n","d":[2,{"x":true,"y":null}]},"b":1}"#.as_bytes()
+        );
+    }
+
+    #[test]
+    fn canonical_bytes_are_stable_across_calls_and_insertion_order() {
+        let proof = labelled(&[("region", "eu"), ("batch", "7")]);
+        let reordered = labelled(&[("batch", "7"), ("region", "eu")]);
+        let reparsed = ExecutionProof::from_json(&proof.to_json()).unwrap();
+
+        assert_eq!(proof.to_canonical_bytes(), proof.to_canonical_bytes());
+        assert_eq!(proof.to_canonical_bytes(), reordered.to_canonical_bytes());
+        assert_eq!(proof.to_canonical_bytes(), reparsed.to_canonical_bytes());
+        assert_eq!(proof.proof_hash(), reordered.proof_hash());
+        assert!(reparsed.verify("agent", b"input", b"output"));
+    }
+
+    #[test]
+    fn proofs_of_the_same_execution_get_distinct_nonces() {
+        let first = ExecutionProof::new("agent", b"input", b"output");
+        let second = ExecutionProof::new("agent", b"input", b"output");
+
+        assert_ne!(first.nonce(), second.nonce());
+        assert_ne!(first.proof_hash(), second.proof_hash());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn proofs_only_verify_under_the_algorithm_they_were_built_with() {
+        use crate::verifier::hasher::Blake3Hasher;
+
+        let blake3 = ExecutionProof::new_with_hasher(&Blake3Hasher, "agent", b"input", b"output");
+        let sha256 = ExecutionProof::new("agent", b"input", b"output");
+        let reparsed = ExecutionProof::from_json(&blake3.to_json()).unwrap();
+
+        assert_eq!(reparsed.algorithm(), "blake3");
+        assert!(reparsed.verify("agent", b"input", b"output"));
+        assert!(!blake3.verify_with_hasher(&Sha256Hasher, "agent", b"input", b"output"));
+        assert!(!sha256.verify_with_hasher(&Blake3Hasher, "agent", b"input", b"output"));
+    }
+
+    #[test]
+    fn tampered_or_undecodable_hashes_never_verify() {
+        let proof = ExecutionProof::new("agent", b"input", b"output");
+        assert!(digest_matches(proof.proof_hash(), &general_purpose::STANDARD.decode(proof.proof_hash()).unwrap()));
+        assert!(!digest_matches("not base64!", b""));
+
+        let mut tampered = proof.clone();
+        tampered.proof_hash = general_purpose::STANDARD.encode([0u8; 32]);
+        assert!(!tampered.verify("agent", b"input", b"output"));
+
+        let mut undecodable = proof.clone();
+        undecodable.output_hash = "***".to_string();
+        assert!(!undecodable.verify("agent", b"input", b"output"));
+        assert!(proof.verify("agent", b"input", b"output"));
+    }
+
+    #[test]
+    fn signatures_survive_json_and_cover_the_proof_hash() {
+        let mut proof = ExecutionProof::new("agent", b"input", b"output");
+        let unsigned = ExecutionProof::from_json(&proof.to_json()).unwrap();
+        assert!(unsigned.signature().is_none());
+        assert!(!unsigned.verify_signature());
+
+        proof.sign(&SigningKey::from_bytes(&[7u8; 32]));
+        let signed = ExecutionProof::from_json(&proof.to_json()).unwrap();
+        assert!(signed.verify_signature());
+        assert_eq!(signed.public_key(), proof.public_key());
+
+        let mut forged = signed.clone();
+        forged.proof_hash = ExecutionProof::new("agent", b"input", b"output").proof_hash;
+        assert!(!forged.verify_signature());
+    }
+
+    #[test]
+    fn binary_round_trip_preserves_every_field() {
+        let mut proof = labelled(&[("region", "eu")]);
+        proof.set_ttl_secs(Some(60));
+        proof.sign(&SigningKey::from_bytes(&[7u8; 32]));
+
+        let bytes = proof.to_bytes().unwrap();
+        let decoded = ExecutionProof::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.to_json(), proof.to_json());
+        assert!(decoded.verify("agent", b"input", b"output"));
+        assert!(decoded.verify_signature());
+        assert!(bytes.len() < proof.to_json().len());
+    }
+
+    #[test]
+    fn streaming_hashes_match_in_memory_hashes() {
+        let input: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+        let output = b"output".to_vec();
+
+        let streamed = ExecutionProof::new_streaming("agent", input.as_slice(), output.as_slice()).unwrap();
+        let buffered = ExecutionProof::new("agent", &input, &output);
+
+        assert_eq!(streamed.input_hash(), buffered.input_hash());
+        assert_eq!(streamed.output_hash(), buffered.output_hash());
+        assert!(streamed.verify("agent", &input, &output));
+    }
+
+    #[test]
+    fn proofs_expire_once_their_ttl_has_passed() {
+        let mut proof = ExecutionProof::new_deterministic("agent", b"input", b"output", 1_000, None);
+        assert!(!proof.is_expired(u64::MAX));
+
+        proof.set_ttl_secs(Some(60));
+        assert!(!proof.is_expired(1_059));
+        assert!(proof.is_expired(1_060));
+
+        let from_json = ExecutionProof::from_json(&proof.to_json()).unwrap();
+        let from_bytes = ExecutionProof::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+        assert_eq!(from_json.ttl_secs(), Some(60));
+        assert_eq!(from_bytes.ttl_secs(), Some(60));
+    }
+
+    #[test]
+    fn changing_metadata_invalidates_the_proof() {
+        let proof = labelled(&[("region", "eu")]);
+        assert!(proof.verify("agent", b"input", b"output"));
+
+        let mut relabelled = proof.clone();
+        relabelled.metadata.labels.insert("region".to_string(), "us".to_string());
+        assert!(!relabelled.verify("agent", b"input", b"output"));
+
+        let mut retyped = proof.clone();
+        retyped.metadata.agent_type = Some("validator".to_string());
+        assert!(!retyped.verify("agent", b"input", b"output"));
+
+        let edited = ExecutionProof::from_json(&proof.to_json().replace("\"eu\"", "\"us\"")).unwrap();
+        assert!(!edited.verify("agent", b"input", b"output"));
+    }
+}